@@ -1,19 +1,53 @@
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use clap::Parser;
+use axum_middleware::host::AllowedHosts;
+use axum_middleware::request_id::{
+    RequestIdConfig, RequestIdFormat, DEFAULT_REQUEST_ID_HEADER,
+};
+use clap::{Parser, Subcommand};
 use ethers::providers::{Http, Provider};
 use ethers_throttle::ThrottledJsonRpcClient;
+use eyre::WrapErr;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
+use reqwest::header::{HeaderName, HeaderValue};
 use telemetry_batteries::metrics::statsd::StatsdBattery;
 use telemetry_batteries::tracing::datadog::DatadogBattery;
 use telemetry_batteries::tracing::TracingShutdownHandle;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
-use world_tree::tree::config::ServiceConfig;
+use url::Url;
+use world_tree::log_file::LogFile;
+use world_tree::metered_client::MeteredJsonRpcClient;
+use world_tree::timeout_client::TimeoutJsonRpcClient;
+use world_tree::tree::blocklist::Blocklist;
+use world_tree::tree::config::{running_in_container, ServiceConfig};
+use world_tree::tree::error::WorldTreeError;
+#[cfg(unix)]
+use world_tree::tree::handoff::{
+    complete_takeover, request_handoff, spawn_handoff_on_sigusr2,
+    verify_handoff, HandoffCheckpoint, DEFAULT_HANDOFF_TIMEOUT,
+};
+use world_tree::tree::identity_tree::DEFAULT_MAX_BATCH_SIZE;
+use world_tree::tree::listener::ListenerOptions;
+use world_tree::tree::readiness::{
+    ReadinessRegistry, Severity, DEFAULT_MAX_BLOCK_LAG,
+};
+use world_tree::tree::redact::set_redact_identities;
+use world_tree::tree::replay::{replay, UpdateRecorder};
 use world_tree::tree::service::InclusionProofService;
+#[cfg(feature = "nats")]
+use world_tree::tree::sink::{spawn_sink, NatsSink, DEFAULT_SINK_QUEUE_DEPTH};
+use world_tree::tree::snapshot::{parse_header, SnapshotSource};
+use world_tree::tree::subgraph::fetch_active_block_ranges;
 use world_tree::tree::tree_manager::{BridgedTree, CanonicalTree, TreeManager};
 use world_tree::tree::WorldTree;
 
@@ -22,20 +56,320 @@ use world_tree::tree::WorldTree;
 #[clap(name = "Tree Availability Service")]
 #[clap(version)]
 struct Opts {
+    #[clap(subcommand)]
+    command: Option<Command>,
     /// Path to the configuration file
     #[clap(short, long)]
     config: Option<PathBuf>,
+    /// Directory with one file per configuration key, e.g. a mounted Kubernetes ConfigMap, taking precedence over the
+    /// configuration file. Nested keys are separated by `__`, e.g. `canonical_tree__provider__rpc_endpoint`.
+    #[clap(long)]
+    config_dir: Option<PathBuf>,
+    /// Port to serve the API on, overriding the port of the configured socket address
+    #[clap(long, env = "PORT")]
+    port: Option<u16>,
+    /// Seconds to wait for in-flight requests to complete once shutdown begins before aborting them
+    #[clap(long, default_value = "30")]
+    shutdown_grace_period: u64,
+    /// Seconds `/ready` fails once shutdown begins before the server stops accepting new connections, giving load
+    /// balancers time to stop routing traffic to the instance
+    #[clap(long, default_value = "5")]
+    shutdown_drain_delay: u64,
+    /// Milliseconds an inclusion proof request with `min_block` waits for the block to be synced before responding with
+    /// 202 Accepted
+    #[clap(long, default_value = "5000")]
+    read_after_write_timeout_ms: u64,
+    /// Maximum number of blocks the tree may lag behind the head of any monitored chain for `/readyz` to pass
+    #[clap(long, default_value_t = DEFAULT_MAX_BLOCK_LAG)]
+    readiness_max_block_lag: u64,
+    /// Minutes since the last applied insertion batch after which the `last_update` readiness check fails
+    #[clap(long, default_value = "60")]
+    readiness_max_update_age_minutes: u64,
+    /// Comma separated readiness checks whose failures are reported as warnings without failing `/readyz`, e.g. `block_lag`
+    #[clap(long, value_delimiter = ',')]
+    readiness_warn_only: Vec<String>,
+    /// Log the client IP from the `X-Forwarded-For` header, only enable when running behind a trusted reverse proxy
+    #[clap(long)]
+    trust_proxy: bool,
+    /// Header request IDs are read from and echoed in, e.g. `X-Correlation-Id`
+    #[clap(long, default_value = DEFAULT_REQUEST_ID_HEADER)]
+    request_id_header_name: HeaderName,
+    /// Format of the request IDs generated for requests that do not carry one: uuid, hex16 or hex32
+    #[clap(long, default_value = "uuid")]
+    request_id_format: RequestIdFormat,
+    /// File JSON logs are appended to in addition to the console, e.g. for log rotation. Reopened on SIGHUP.
+    #[clap(long)]
+    log_file: Option<PathBuf>,
+    /// Comma separated hosts requests may be addressed to, others are rejected with 421. Any host is accepted if unset or `*`.
+    #[clap(long, value_delimiter = ',')]
+    allowed_hosts: Vec<String>,
+    /// GraphQL subgraph serving the block ranges that contain canonical tree events as `activeBlockRanges`. Only these
+    /// ranges, those of `canonical_tree.active_block_ranges` and blocks after the last range are scanned.
+    #[clap(long)]
+    skip_empty_block_ranges_via_subgraph: Option<Url>,
+    /// File of IP addresses and CIDR ranges, one per line, whose requests are rejected with 403. Reloaded on SIGHUP.
+    #[clap(long)]
+    blocklist_path: Option<PathBuf>,
+    /// Set SO_REUSEPORT on the server socket so that a new instance can bind the port before the old one releases it
+    #[clap(long)]
+    reuse_port: bool,
+    /// File the tree is written to on SIGUSR2 for a new instance taking over, and read from with `--takeover`.
+    /// Must be on the same filesystem as the cache file.
+    #[cfg(unix)]
+    #[clap(long, env = "WORLD_TREE_HANDOFF_PATH")]
+    handoff_path: Option<PathBuf>,
+    /// Process ID of a running instance to take over from without downtime. Its tree is handed off through
+    /// `--handoff-path`, the port is bound next to it with SO_REUSEPORT and it is sent SIGTERM once this instance serves.
+    #[cfg(unix)]
+    #[clap(long, requires = "handoff_path")]
+    takeover: Option<u32>,
+    /// Seconds to wait for the instance taken over to write its tree to the handoff path
+    #[cfg(unix)]
+    #[clap(long, default_value_t = DEFAULT_HANDOFF_TIMEOUT.as_secs())]
+    takeover_timeout: u64,
+    /// Maximum number of tree updates buffered between the log listeners and the tasks applying them to the tree
+    #[clap(long, default_value = "1024")]
+    log_queue_depth: NonZeroUsize,
+    /// Number of worker threads for the async runtime, defaults to the number of CPU cores
+    #[clap(long)]
+    worker_threads: Option<NonZeroUsize>,
+    /// URL of a pre-built tree cache to download when the configured cache file does not exist yet.
+    /// The SHA-256 checksum of the snapshot must be served at the same URL with a `.sha256` suffix.
+    #[clap(long)]
+    initial_sync_snapshot_url: Option<Url>,
+    /// Header sent when downloading the snapshot, e.g. `Authorization: Bearer <token>`
+    #[clap(long, value_parser = parse_header, requires = "initial_sync_snapshot_url")]
+    snapshot_auth_header: Option<(HeaderName, HeaderValue)>,
+    /// Chain ID the canonical tree provider is expected to report, overriding `canonical_tree.chain_id`
+    #[clap(long)]
+    chain_id: Option<u64>,
+    /// Seconds between self-tests verifying proofs for a random sample of leaves and retained roots, disabled if unset
+    #[clap(long)]
+    self_test_interval: Option<NonZeroU64>,
+    /// Sync the tree, print `root=<root> leaves=<count> block=<block>` to stdout and exit without serving the API
+    #[clap(long)]
+    print_root_and_exit: bool,
+    /// Sync the tree, print the sync throughput to stdout and exit without serving the API
+    #[clap(long, conflicts_with = "print_root_and_exit")]
+    benchmark_sync: bool,
+    /// Number of canonical blocks synced between checks of the canonical root against the chain, zero to disable
+    #[clap(long, default_value = "1000")]
+    consistency_check_interval_blocks: u64,
+    /// Independent RPC provider for the canonical chain used to cross-check the canonical root, disabled if unset
+    #[clap(long)]
+    verification_rpc_endpoint: Option<Url>,
+    /// Number of canonical batches applied between cross-checks of the canonical root against the verification provider
+    #[clap(long, default_value = "10", requires = "verification_rpc_endpoint")]
+    verification_interval_batches: u64,
+    /// Milliseconds to wait for the response to any RPC request before failing it, e.g. an `eth_getLogs` call stuck on an
+    /// overloaded node. Failed requests are retried on the next sync iteration.
+    #[clap(long, default_value = "30000")]
+    rpc_call_timeout_ms: u64,
+    /// Maximum number of identities inserted or deleted by a single batch, larger batches are rejected as malformed
+    #[clap(long, default_value_t = DEFAULT_MAX_BATCH_SIZE)]
+    max_batch_size: usize,
+    /// Populate an empty tree with this many synthetic identities if no events are found onchain, for local development only
+    #[clap(long, default_value = "0")]
+    seed_identities: usize,
+    /// Append every canonical update received from the chain to this JSON lines file, the input of the `replay` subcommand.
+    /// Only a recording started with an empty cache can be replayed.
+    #[clap(long)]
+    record_updates: Option<PathBuf>,
+    /// Replace identity commitments in logs, traces and errors with a truncated keyed hash. Proofs are unaffected.
+    #[clap(long)]
+    redact_identities: bool,
+    /// Print the full error chain on failure instead of a single line
+    #[clap(short, long)]
+    verbose: bool,
+    /// NATS server to publish a summary of every update applied to the tree to
+    #[cfg(feature = "nats")]
+    #[clap(long)]
+    nats_url: Option<String>,
+    /// NATS subject to publish tree update summaries to
+    #[cfg(feature = "nats")]
+    #[clap(long, default_value = "world-tree.updates")]
+    nats_subject: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Rebuild the tree offline from a JSON lines file of recorded updates, checking the root after every update
+    Replay {
+        /// File with one recorded update per line
+        #[clap(long)]
+        updates: PathBuf,
+        /// Depth of the tree to rebuild
+        #[clap(long, default_value = "30")]
+        tree_depth: usize,
+    },
+}
+
+type Client = Provider<
+    MeteredJsonRpcClient<TimeoutJsonRpcClient<ThrottledJsonRpcClient<Http>>>,
+>;
+
+/// Interval between summaries of the RPC requests sent to each provider
+const RPC_USAGE_LOG_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Category of failure, determining the exit code of the process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureKind {
+    /// The service failed after starting up
+    Runtime = 1,
+    /// The configuration is invalid, restarting will not help until it is fixed
+    Config = 2,
+    /// The service failed to sync the tree on startup, e.g. because a provider is unreachable
+    Startup = 3,
+}
+
+struct Failure {
+    kind: FailureKind,
+    report: eyre::Report,
+}
+
+impl Failure {
+    fn new(kind: FailureKind, report: eyre::Report) -> Self {
+        // Some errors surfaced during startup or at runtime are caused by the configuration
+        let is_config_error = report
+            .downcast_ref::<WorldTreeError<Client>>()
+            .is_some_and(WorldTreeError::is_config_error);
+
+        let kind = if is_config_error {
+            FailureKind::Config
+        } else {
+            kind
+        };
+
+        Self { kind, report }
+    }
+
+    fn exit_code(&self) -> ExitCode {
+        ExitCode::from(self.kind as u8)
+    }
+}
+
+trait FailureExt<T> {
+    fn or_fail(self, kind: FailureKind) -> Result<T, Failure>;
 }
 
-#[tokio::main]
-pub async fn main() -> eyre::Result<()> {
+impl<T, E> FailureExt<T> for Result<T, E>
+where
+    E: Into<eyre::Report>,
+{
+    fn or_fail(self, kind: FailureKind) -> Result<T, Failure> {
+        self.map_err(|error| Failure::new(kind, error.into()))
+    }
+}
+
+pub fn main() -> ExitCode {
     dotenv::dotenv().ok();
 
     let opts = Opts::parse();
+    let verbose = opts.verbose;
+
+    match start(opts) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            if verbose {
+                eprintln!("Error: {:?}", failure.report);
+            } else {
+                eprintln!("Error: {}", failure.report);
+            }
+
+            failure.exit_code()
+        }
+    }
+}
+
+fn start(opts: Opts) -> Result<(), Failure> {
+    if let Some(Command::Replay {
+        updates,
+        tree_depth,
+    }) = &opts.command
+    {
+        return replay_updates(updates, *tree_depth);
+    }
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(worker_threads) = opts.worker_threads {
+        runtime.worker_threads(worker_threads.get());
+    }
+
+    runtime
+        .thread_name_fn(|| {
+            static WORKER_ID: AtomicUsize = AtomicUsize::new(0);
+            let id = WORKER_ID.fetch_add(1, Ordering::SeqCst);
+            format!("world-tree-worker-{id}")
+        })
+        .enable_all()
+        .build()
+        .or_fail(FailureKind::Runtime)?
+        .block_on(run(opts))
+}
+
+fn replay_updates(updates: &Path, tree_depth: usize) -> Result<(), Failure> {
+    let file = File::open(updates)
+        .wrap_err_with(|| format!("Failed to open {}", updates.display()))
+        .or_fail(FailureKind::Config)?;
+
+    let report = replay(tree_depth, BufReader::new(file))
+        .or_fail(FailureKind::Runtime)?;
 
-    let config = ServiceConfig::load(opts.config.as_deref())?;
+    println!("{report}");
+
+    if let Some(divergence) = report.divergence {
+        return Err(eyre::eyre!(
+            "Replay diverged at update {}",
+            divergence.index
+        ))
+        .or_fail(FailureKind::Runtime);
+    }
+
+    Ok(())
+}
+
+async fn run(opts: Opts) -> Result<(), Failure> {
+    let mut config =
+        ServiceConfig::load(opts.config.as_deref(), opts.config_dir.as_deref())
+            .or_fail(FailureKind::Config)?;
+
+    if let Some(chain_id) = opts.chain_id {
+        config.canonical_tree.chain_id = Some(chain_id);
+    }
+
+    if let Some(url) = &opts.skip_empty_block_ranges_via_subgraph {
+        let ranges = fetch_active_block_ranges(url)
+            .await
+            .wrap_err("Failed to fetch active block ranges from the subgraph")
+            .or_fail(FailureKind::Startup)?;
+        config.canonical_tree.active_block_ranges.extend(ranges);
+    }
+
+    set_redact_identities(opts.redact_identities);
+
+    let log_file = opts
+        .log_file
+        .as_ref()
+        .map(LogFile::open)
+        .transpose()
+        .or_fail(FailureKind::Config)?;
 
     let _tracing_shutdown_handle = if let Some(telemetry) = &config.telemetry {
+        if log_file.is_some() {
+            return Err(eyre::eyre!(
+                "--log-file is not supported when telemetry is configured"
+            ))
+            .or_fail(FailureKind::Config);
+        }
+
+        // The Datadog subscriber logs to stdout, which must stay machine-parseable in these modes
+        if opts.print_root_and_exit || opts.benchmark_sync {
+            return Err(eyre::eyre!(
+                "--print-root-and-exit and --benchmark-sync are not supported when telemetry is configured"
+            ))
+            .or_fail(FailureKind::Config);
+        }
+
         let tracing_shutdown_handle = DatadogBattery::init(
             telemetry.traces_endpoint.as_deref(),
             &telemetry.service_name,
@@ -50,80 +384,320 @@ pub async fn main() -> eyre::Result<()> {
                 metrics_config.queue_size,
                 metrics_config.buffer_size,
                 Some(&metrics_config.prefix),
-            )?;
+            )
+            .or_fail(FailureKind::Config)?;
         }
 
         tracing_shutdown_handle
     } else {
+        // Keep stdout machine-parseable when printing the root or sync throughput
+        let writer = if opts.print_root_and_exit || opts.benchmark_sync {
+            BoxMakeWriter::new(std::io::stderr)
+        } else {
+            BoxMakeWriter::new(std::io::stdout)
+        };
+
         tracing_subscriber::registry()
-            .with(tracing_subscriber::fmt::layer().pretty().compact())
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .pretty()
+                    .compact()
+                    .with_writer(writer),
+            )
+            .with(log_file.clone().map(|log_file| {
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_ansi(false)
+                    .with_writer(log_file)
+            }))
             .with(tracing_subscriber::EnvFilter::from_default_env())
             .init();
 
+        if let Some(log_file) = log_file {
+            tracing::info!(path = ?log_file.path(), "Logging to file");
+            #[cfg(unix)]
+            log_file.spawn_reopen_on_sighup();
+        }
+
         TracingShutdownHandle
     };
 
     tracing::info!(?config, "Starting World Tree service");
 
-    let world_tree = initialize_world_tree(&config).await?;
+    let socket_address =
+        config.resolve_socket_address(opts.port, running_in_container());
 
-    let handles = InclusionProofService::new(world_tree)
-        .serve(config.socket_address)
-        .await?;
+    #[cfg(unix)]
+    let takeover = request_takeover(&opts, &config).await?;
 
-    let mut handles = handles.into_iter().collect::<FuturesUnordered<_>>();
-    while let Some(result) = handles.next().await {
-        tracing::error!("TreeAvailabilityError: {:?}", result);
-        result??;
+    let snapshot = opts.initial_sync_snapshot_url.clone().map(|url| {
+        SnapshotSource::new(url)
+            .with_auth_header(opts.snapshot_auth_header.clone())
+    });
+
+    let world_tree = initialize_world_tree(
+        &config,
+        opts.log_queue_depth.get(),
+        Duration::from_millis(opts.rpc_call_timeout_ms),
+        snapshot,
+    )
+    .await
+    .or_fail(FailureKind::Startup)?
+    .with_self_test_interval(
+        opts.self_test_interval
+            .map(|secs| Duration::from_secs(secs.get())),
+    )
+    .with_consistency_check_interval_blocks(
+        opts.consistency_check_interval_blocks,
+    )
+    .with_seed_identities(opts.seed_identities)
+    .with_max_batch_size(opts.max_batch_size);
+
+    let world_tree = match &opts.record_updates {
+        Some(path) => world_tree.with_update_recorder(
+            UpdateRecorder::open(path).or_fail(FailureKind::Config)?,
+        ),
+        None => world_tree,
+    };
+
+    let world_tree = match &opts.verification_rpc_endpoint {
+        Some(rpc_endpoint) => world_tree.with_verification_provider(
+            Arc::new(throttled_client(
+                rpc_endpoint.clone(),
+                config.canonical_tree.provider.throttle,
+                Duration::from_millis(opts.rpc_call_timeout_ms),
+                "verification",
+            )),
+            opts.verification_interval_batches,
+        ),
+        None => world_tree,
+    };
+
+    #[cfg(unix)]
+    if let Some((_, checkpoint)) = &takeover {
+        verify_handoff(&world_tree, checkpoint)
+            .await
+            .or_fail(FailureKind::Startup)?;
+    }
+
+    if opts.print_root_and_exit {
+        return print_root(&world_tree).await.or_fail(FailureKind::Startup);
+    }
+
+    if opts.benchmark_sync {
+        return benchmark_sync(&world_tree)
+            .await
+            .or_fail(FailureKind::Startup);
     }
 
+    #[cfg(feature = "nats")]
+    let world_tree = connect_update_sink(world_tree, &opts)
+        .await
+        .or_fail(FailureKind::Startup)?;
+
+    let blocklist = opts
+        .blocklist_path
+        .as_ref()
+        .map(Blocklist::load)
+        .transpose()
+        .or_fail(FailureKind::Startup)?;
+
+    let mut readiness = ReadinessRegistry::with_default_checks(
+        opts.readiness_max_block_lag,
+        Duration::from_secs(opts.readiness_max_update_age_minutes * 60),
+    );
+    for name in &opts.readiness_warn_only {
+        if !readiness.set_severity(name, Severity::Soft) {
+            return Err(eyre::eyre!("Unknown readiness check `{name}`"))
+                .or_fail(FailureKind::Config);
+        }
+    }
+
+    #[cfg(unix)]
+    let reuse_port = opts.reuse_port || takeover.is_some();
+    #[cfg(not(unix))]
+    let reuse_port = opts.reuse_port;
+
+    let world_tree = Arc::new(world_tree);
+
+    #[cfg(unix)]
+    if let Some(handoff_path) = &opts.handoff_path {
+        spawn_handoff_on_sigusr2(world_tree.clone(), handoff_path.clone());
+    }
+
+    let mut service = InclusionProofService::new(world_tree)
+        .with_shutdown_grace_period(Duration::from_secs(
+            opts.shutdown_grace_period,
+        ))
+        .with_shutdown_drain_delay(Duration::from_secs(
+            opts.shutdown_drain_delay,
+        ))
+        .with_read_after_write_timeout(Duration::from_millis(
+            opts.read_after_write_timeout_ms,
+        ))
+        .with_readiness_registry(readiness)
+        .with_trust_proxy(opts.trust_proxy)
+        .with_listener_options(ListenerOptions { reuse_port })
+        .with_request_id_config(RequestIdConfig {
+            header_name: opts.request_id_header_name.clone(),
+            format: opts.request_id_format,
+        })
+        .with_allowed_hosts(AllowedHosts::new(&opts.allowed_hosts));
+    if let Some(blocklist) = blocklist {
+        service = service.with_blocklist(Arc::new(blocklist));
+    }
+
+    let service_handle = service
+        .serve(socket_address)
+        .await
+        .or_fail(FailureKind::Startup)?;
+
+    tracing::info!(local_addrs = ?service_handle.local_addrs, "World Tree service started");
+
+    // The tree is synced and the port bound, so the instance taken over can stop accepting connections
+    #[cfg(unix)]
+    if let Some((pid, _)) = takeover {
+        tracing::info!(pid, "Stopping the instance taken over");
+        if let Err(error) = complete_takeover(pid) {
+            tracing::warn!(
+                ?error,
+                pid,
+                "Failed to stop the instance taken over"
+            );
+        }
+    }
+
+    // Every task runs until the process exits except for the server, which completes once graceful shutdown is done
+    let mut handles = service_handle
+        .handles
+        .into_iter()
+        .collect::<FuturesUnordered<_>>();
+    if let Some(result) = handles.next().await {
+        if !matches!(result, Ok(Ok(()))) {
+            tracing::error!("TreeAvailabilityError: {:?}", result);
+        }
+        result
+            .or_fail(FailureKind::Runtime)?
+            .or_fail(FailureKind::Runtime)?;
+    }
+
+    tracing::info!("Shutdown complete");
+
     Ok(())
 }
 
+/// Asks the instance passed to `--takeover` to hand off its tree and moves it to the cache file
+#[cfg(unix)]
+async fn request_takeover(
+    opts: &Opts,
+    config: &ServiceConfig,
+) -> Result<Option<(u32, HandoffCheckpoint)>, Failure> {
+    let (Some(pid), Some(handoff_path)) = (opts.takeover, &opts.handoff_path)
+    else {
+        return Ok(None);
+    };
+
+    if config.cache.purge_cache {
+        return Err(eyre::eyre!(
+            "--takeover is not supported when the cache is purged on startup"
+        ))
+        .or_fail(FailureKind::Config);
+    }
+
+    tracing::info!(pid, ?handoff_path, "Taking over from running instance");
+
+    let checkpoint = request_handoff(
+        pid,
+        handoff_path,
+        &config.cache.cache_file,
+        Duration::from_secs(opts.takeover_timeout),
+    )
+    .await
+    .or_fail(FailureKind::Startup)?;
+
+    tracing::info!(?checkpoint, "Received handoff snapshot");
+
+    Ok(Some((pid, checkpoint)))
+}
+
+/// Builds a throttled client whose requests time out after `timeout` and are counted per method under the `provider` label
+fn throttled_client(
+    rpc_endpoint: Url,
+    throttle: u32,
+    timeout: Duration,
+    provider: impl Into<String>,
+) -> Client {
+    let http_provider = Http::new(rpc_endpoint);
+    let throttled_provider =
+        ThrottledJsonRpcClient::new(http_provider, throttle, None);
+    let timeout_provider =
+        TimeoutJsonRpcClient::new(throttled_provider, timeout);
+    let metered_provider =
+        MeteredJsonRpcClient::new(timeout_provider, provider);
+
+    metered_provider.stats().spawn_summary_log(
+        metered_provider.provider().to_owned(),
+        RPC_USAGE_LOG_INTERVAL,
+    );
+
+    Provider::new(metered_provider)
+}
+
 async fn initialize_world_tree(
     config: &ServiceConfig,
-) -> eyre::Result<Arc<WorldTree<Provider<ThrottledJsonRpcClient<Http>>>>> {
+    log_queue_depth: usize,
+    rpc_call_timeout: Duration,
+    snapshot: Option<SnapshotSource>,
+) -> eyre::Result<WorldTree<Client>> {
     let canonical_provider_config = &config.canonical_tree.provider;
 
-    let http_provider =
-        Http::new(canonical_provider_config.rpc_endpoint.clone());
-    let throttled_provider = ThrottledJsonRpcClient::new(
-        http_provider,
+    let canonical_middleware = Arc::new(throttled_client(
+        canonical_provider_config.rpc_endpoint.clone(),
         canonical_provider_config.throttle,
-        None,
-    );
-    let canonical_middleware = Arc::new(Provider::new(throttled_provider));
+        rpc_call_timeout,
+        "canonical",
+    ));
 
     let canonical_tree_config = &config.canonical_tree;
     let canonical_tree_manager = TreeManager::<_, CanonicalTree>::new(
         canonical_tree_config.address,
         canonical_tree_config.window_size,
         canonical_tree_config.creation_block,
+        canonical_tree_config.block_range_filter(),
         canonical_middleware,
     )
     .await?;
+    canonical_tree_manager.ensure_chain_id(canonical_tree_config.chain_id)?;
+    canonical_tree_manager
+        .ensure_creation_block(canonical_tree_config.creation_block)
+        .await?;
+    canonical_tree_manager
+        .ensure_tree_depth(config.tree_depth)
+        .await?;
 
     let mut bridged_tree_managers = vec![];
 
-    for tree_config in config.bridged_trees.iter() {
+    for (index, tree_config) in config.bridged_trees.iter().enumerate() {
         let bridged_provider_config = &tree_config.provider;
-        let http_provider =
-            Http::new(bridged_provider_config.rpc_endpoint.clone());
-
-        let throttled_provider = ThrottledJsonRpcClient::new(
-            http_provider,
+        let bridged_middleware = Arc::new(throttled_client(
+            bridged_provider_config.rpc_endpoint.clone(),
             bridged_provider_config.throttle,
-            None,
-        );
-        let bridged_middleware = Arc::new(Provider::new(throttled_provider));
+            rpc_call_timeout,
+            format!("bridged-{index}"),
+        ));
 
         let tree_manager = TreeManager::<_, BridgedTree>::new(
             tree_config.address,
             tree_config.window_size,
             tree_config.creation_block,
+            tree_config.block_range_filter(),
             bridged_middleware,
         )
         .await?;
+        tree_manager.ensure_chain_id(tree_config.chain_id)?;
+        tree_manager
+            .ensure_creation_block(tree_config.creation_block)
+            .await?;
 
         bridged_tree_managers.push(tree_manager);
     }
@@ -133,10 +707,74 @@ async fn initialize_world_tree(
         fs::remove_file(&config.cache.cache_file)?;
     }
 
-    Ok(Arc::new(WorldTree::new(
+    if let Some(snapshot) = snapshot {
+        let cache_file = &config.cache.cache_file;
+
+        if !cache_file.exists() {
+            tracing::info!(url = %snapshot.url, ?cache_file, "Downloading tree snapshot");
+
+            // The tree can always be synced from the chain, a missing snapshot only makes the initial sync slower
+            match snapshot.download(cache_file).await {
+                Ok(()) => tracing::info!("Downloaded tree snapshot"),
+                Err(err) => tracing::warn!(
+                    ?err,
+                    "Failed to download tree snapshot, syncing from the chain instead"
+                ),
+            }
+        }
+    }
+
+    Ok(WorldTree::new(
         config.tree_depth,
         canonical_tree_manager,
         bridged_tree_managers,
         &config.cache.cache_file,
-    )?))
+    )?
+    .with_log_queue_depth(log_queue_depth))
+}
+
+async fn benchmark_sync(world_tree: &WorldTree<Client>) -> eyre::Result<()> {
+    let start = Instant::now();
+    let summary = world_tree.sync_to_head().await?;
+    let duration = start.elapsed().as_secs_f64();
+
+    println!(
+        "blocks_scanned={}, events_processed={}, duration={duration:.1}s, events/s={:.0}, leaves/s={:.0}",
+        summary.blocks_scanned,
+        summary.events_processed,
+        summary.events_processed as f64 / duration,
+        summary.leaves_processed as f64 / duration,
+    );
+
+    Ok(())
+}
+
+async fn print_root(world_tree: &WorldTree<Client>) -> eyre::Result<()> {
+    world_tree.sync_to_head().await?;
+
+    let root = world_tree.current_root().await;
+    let leaves = world_tree.identity_tree.read().await.tree.num_leaves();
+    let block = world_tree.last_synced_blocks()
+        [&world_tree.canonical_tree_manager.chain_id];
+
+    println!("root={root:#x} leaves={leaves} block={block}");
+
+    Ok(())
+}
+
+#[cfg(feature = "nats")]
+async fn connect_update_sink(
+    world_tree: WorldTree<Client>,
+    opts: &Opts,
+) -> eyre::Result<WorldTree<Client>> {
+    let Some(nats_url) = &opts.nats_url else {
+        return Ok(world_tree);
+    };
+
+    tracing::info!(%nats_url, subject = %opts.nats_subject, "Publishing tree updates to NATS");
+
+    let sink = NatsSink::connect(nats_url, opts.nats_subject.clone()).await?;
+    let (update_publisher, _) = spawn_sink(sink, DEFAULT_SINK_QUEUE_DEPTH);
+
+    Ok(world_tree.with_update_observer(Arc::new(update_publisher)))
 }
@@ -0,0 +1,89 @@
+use axum::extract::State;
+use axum::http::header::HOST;
+use axum::http::uri::Authority;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+/// Hosts requests may be addressed to, compared case-insensitively and without the port
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AllowedHosts {
+    /// Requests are accepted whatever their host
+    #[default]
+    Any,
+    List(Vec<String>),
+}
+
+impl AllowedHosts {
+    /// Allows the hosts in `hosts`, or any host if `hosts` is empty or contains `*`
+    pub fn new(hosts: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let hosts = hosts
+            .into_iter()
+            .map(|host| host.as_ref().trim().to_ascii_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect::<Vec<_>>();
+
+        if hosts.is_empty() || hosts.iter().any(|host| host == "*") {
+            Self::Any
+        } else {
+            Self::List(hosts)
+        }
+    }
+
+    /// Whether a request addressed to `authority`, e.g. `example.com:8080`, is accepted
+    pub fn is_allowed(&self, authority: Option<&str>) -> bool {
+        let Self::List(hosts) = self else {
+            return true;
+        };
+
+        authority
+            .and_then(|authority| authority.parse::<Authority>().ok())
+            .is_some_and(|authority| {
+                hosts
+                    .iter()
+                    .any(|host| host.eq_ignore_ascii_case(authority.host()))
+            })
+    }
+}
+
+/// Rejects requests whose `Host` header, or URI authority for HTTP/2, is not allowed with 421 Misdirected Request
+pub async fn middleware<B>(
+    State(allowed_hosts): State<AllowedHosts>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let authority = request
+        .headers()
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| request.uri().authority().map(Authority::as_str));
+
+    if !allowed_hosts.is_allowed(authority) {
+        tracing::debug!(?authority, "Rejected request to a host not allowed");
+        return StatusCode::MISDIRECTED_REQUEST.into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_allowed() {
+        assert_eq!(AllowedHosts::new(Vec::<String>::new()), AllowedHosts::Any);
+        assert_eq!(AllowedHosts::new(["a.com", "*"]), AllowedHosts::Any);
+        assert!(AllowedHosts::Any.is_allowed(None));
+
+        let allowed_hosts = AllowedHosts::new(["World-Tree.example", "[::1]"]);
+        assert!(allowed_hosts.is_allowed(Some("world-tree.example")));
+        assert!(allowed_hosts.is_allowed(Some("WORLD-TREE.example:8080")));
+        assert!(allowed_hosts.is_allowed(Some("[::1]:8080")));
+
+        assert!(!allowed_hosts.is_allowed(Some("attacker.example")));
+        assert!(!allowed_hosts.is_allowed(Some("world-tree.example.attacker")));
+        assert!(!allowed_hosts.is_allowed(Some("not a host")));
+        assert!(!allowed_hosts.is_allowed(None));
+    }
+}
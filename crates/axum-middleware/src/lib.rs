@@ -1 +1,3 @@
+pub mod host;
 pub mod logging;
+pub mod request_id;
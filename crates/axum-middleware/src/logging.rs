@@ -1,5 +1,6 @@
 #![allow(clippy::cast_possible_truncation)]
 
+use axum::extract::State;
 use axum::http::{Request, StatusCode};
 use axum::middleware::Next;
 use axum::response::Response;
@@ -8,10 +9,25 @@ use hyper::body::HttpBody;
 use hyper::{Body, Method};
 use tracing::{error, info, info_span, warn, Instrument};
 
+use crate::request_id::RequestContext;
+
 // 1 MiB
 const MAX_REQUEST_BODY_SIZE: u64 = 1024 * 1024;
 
+/// Logged in place of request bodies that may contain sensitive data
+const REDACTED_BODY: &str = "<redacted>";
+
+/// Whether request bodies are logged along with the method, path and query of every request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BodyLogging {
+    #[default]
+    Full,
+    /// Bodies are replaced with a placeholder, e.g. when they contain identifiers that must not be logged
+    Redacted,
+}
+
 pub async fn middleware<B>(
+    State(body_logging): State<BodyLogging>,
     request: Request<B>,
     next: Next<Body>,
 ) -> Result<Response, StatusCode>
@@ -24,10 +40,19 @@ where
     let uri_path = parts.uri.path().to_string();
     let request_method = parts.method.clone();
     let request_query = parts.uri.query().map(ToString::to_string);
+    let request_id = parts
+        .extensions
+        .get::<RequestContext>()
+        .map(|context| context.request_id.clone());
 
     if let Method::GET = request_method {
-        let span =
-            info_span!("request", ?uri_path, ?request_method, ?request_query);
+        let span = info_span!(
+            "request",
+            ?uri_path,
+            ?request_method,
+            ?request_query,
+            request_id
+        );
 
         async {
             telemetry_batteries::tracing::trace_from_headers(&parts.headers);
@@ -62,13 +87,19 @@ where
         .await
     } else {
         let body = body_to_string(body).await?;
+        // Owned since the body itself is moved into the forwarded request
+        let logged_body = match body_logging {
+            BodyLogging::Full => body.clone(),
+            BodyLogging::Redacted => REDACTED_BODY.to_owned(),
+        };
 
         let span = info_span!(
             "request",
             ?uri_path,
             ?request_method,
             ?request_query,
-            ?body
+            request_id,
+            body = ?logged_body
         );
 
         async {
@@ -78,7 +109,7 @@ where
                 ?uri_path,
                 ?request_method,
                 ?request_query,
-                ?body,
+                body = ?logged_body,
                 "Processing request"
             );
 
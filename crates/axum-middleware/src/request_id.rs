@@ -0,0 +1,179 @@
+use std::fmt;
+use std::str::FromStr;
+
+use axum::extract::State;
+use axum::http::header::HeaderName;
+use axum::http::{HeaderMap, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use rand::RngCore;
+
+/// Header carrying the request ID unless configured otherwise
+pub const DEFAULT_REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Strategy used to generate request IDs for requests that do not carry one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RequestIdFormat {
+    /// Random UUID v4, e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`
+    #[default]
+    Uuid,
+    /// 16 random hex characters
+    Hex16,
+    /// 32 random hex characters
+    Hex32,
+}
+
+impl RequestIdFormat {
+    pub fn generate(&self) -> String {
+        match self {
+            Self::Uuid => uuid::Uuid::new_v4().to_string(),
+            Self::Hex16 => random_hex::<8>(),
+            Self::Hex32 => random_hex::<16>(),
+        }
+    }
+}
+
+impl FromStr for RequestIdFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uuid" => Ok(Self::Uuid),
+            "hex16" => Ok(Self::Hex16),
+            "hex32" => Ok(Self::Hex32),
+            _ => Err(format!(
+                "Invalid request ID format `{s}`, expected one of uuid, hex16, hex32"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RequestIdFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Uuid => write!(f, "uuid"),
+            Self::Hex16 => write!(f, "hex16"),
+            Self::Hex32 => write!(f, "hex32"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestIdConfig {
+    /// Header the request ID is read from and echoed in, e.g. `X-Correlation-Id`
+    pub header_name: HeaderName,
+    pub format: RequestIdFormat,
+}
+
+impl Default for RequestIdConfig {
+    fn default() -> Self {
+        Self {
+            header_name: HeaderName::from_static(DEFAULT_REQUEST_ID_HEADER),
+            format: RequestIdFormat::default(),
+        }
+    }
+}
+
+impl RequestIdConfig {
+    /// Request ID sent by the client in the configured header, or a newly generated one
+    pub fn request_id(&self, headers: &HeaderMap) -> String {
+        headers
+            .get(&self.header_name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|request_id| !request_id.is_empty())
+            .map_or_else(|| self.format.generate(), ToOwned::to_owned)
+    }
+}
+
+/// Context of the request being processed, available as a request extension to handlers and inner middleware
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestContext {
+    pub request_id: String,
+}
+
+/// Assigns a request ID to every request, preferring the ID sent by the client, and echoes it in the response headers
+pub async fn middleware<B>(
+    State(config): State<RequestIdConfig>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let request_id = config.request_id(request.headers());
+    let header_value = HeaderValue::from_str(&request_id)
+        .expect("Request IDs are generated or received as valid header values");
+
+    request
+        .headers_mut()
+        .insert(config.header_name.clone(), header_value.clone());
+    request
+        .extensions_mut()
+        .insert(RequestContext { request_id });
+
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .insert(config.header_name, header_value);
+
+    response
+}
+
+fn random_hex<const N: usize>() -> String {
+    let mut bytes = [0; N];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let uuid = RequestIdFormat::Uuid.generate();
+        assert!(uuid::Uuid::parse_str(&uuid).is_ok());
+
+        let hex16 = RequestIdFormat::Hex16.generate();
+        assert_eq!(hex16.len(), 16);
+        assert!(hex::decode(hex16).is_ok());
+
+        assert_eq!(RequestIdFormat::Hex32.generate().len(), 32);
+    }
+
+    #[test]
+    fn test_parse_format() {
+        for format in [
+            RequestIdFormat::Uuid,
+            RequestIdFormat::Hex16,
+            RequestIdFormat::Hex32,
+        ] {
+            assert_eq!(format.to_string().parse(), Ok(format));
+        }
+
+        assert!("snowflake".parse::<RequestIdFormat>().is_err());
+    }
+
+    #[test]
+    fn test_prefers_client_request_id() {
+        let config = RequestIdConfig {
+            header_name: HeaderName::from_static("x-correlation-id"),
+            format: RequestIdFormat::Hex16,
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-request-id",
+            HeaderValue::from_static("ignored-request-id"),
+        );
+        assert_eq!(config.request_id(&headers).len(), 16);
+
+        headers.insert(
+            "x-correlation-id",
+            HeaderValue::from_static("client-request-id"),
+        );
+        assert_eq!(config.request_id(&headers), "client-request-id");
+
+        // Empty IDs are replaced
+        headers.insert("x-correlation-id", HeaderValue::from_static(" "));
+        assert_eq!(config.request_id(&headers).len(), 16);
+    }
+}
@@ -0,0 +1,113 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, FnArg, ImplItem, ItemImpl, Visibility,
+};
+
+/// Adds `#[tracing::instrument(level = "debug")]` to every `pub async fn` in the annotated `impl` block, skipping
+/// `self`. Spans are recorded at debug level so that they don't flood the default info level logs.
+///
+/// Methods that already carry an `instrument` attribute are left untouched, which allows overriding the
+/// default, e.g. to record a span at info level or to skip arguments that are expensive to format or do not
+/// implement `Debug`.
+#[proc_macro_attribute]
+pub fn instrument_async_methods(
+    _attr: TokenStream,
+    item: TokenStream,
+) -> TokenStream {
+    let item_impl = parse_macro_input!(item as ItemImpl);
+    let item_impl = instrument_impl(item_impl);
+
+    quote!(#item_impl).into()
+}
+
+fn instrument_impl(mut item_impl: ItemImpl) -> ItemImpl {
+    for item in item_impl.items.iter_mut() {
+        let ImplItem::Fn(method) = item else {
+            continue;
+        };
+
+        let is_pub = matches!(method.vis, Visibility::Public(_));
+        let is_async = method.sig.asyncness.is_some();
+        let is_instrumented = method.attrs.iter().any(|attr| {
+            attr.path()
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == "instrument")
+        });
+
+        if !is_pub || !is_async || is_instrumented {
+            continue;
+        }
+
+        let has_receiver =
+            matches!(method.sig.inputs.first(), Some(FnArg::Receiver(_)));
+        let attr = if has_receiver {
+            parse_quote!(#[tracing::instrument(level = "debug", skip(self))])
+        } else {
+            parse_quote!(#[tracing::instrument(level = "debug")])
+        };
+
+        method.attrs.insert(0, attr);
+    }
+
+    item_impl
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::ImplItemFn;
+
+    use super::*;
+
+    fn instrument_attrs(method: &ImplItemFn) -> Vec<String> {
+        method
+            .attrs
+            .iter()
+            .map(|attr| quote!(#attr).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_instrument_impl() {
+        let item_impl: ItemImpl = parse_quote! {
+            impl Foo {
+                pub async fn method(&self, value: u64) {}
+
+                pub async fn associated(value: u64) {}
+
+                #[tracing::instrument(skip(self, value))]
+                pub async fn instrumented(&self, value: u64) {}
+
+                async fn private(&self) {}
+
+                pub fn sync(&self) {}
+            }
+        };
+
+        let methods = instrument_impl(item_impl)
+            .items
+            .into_iter()
+            .map(|item| match item {
+                ImplItem::Fn(method) => method,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            instrument_attrs(&methods[0]),
+            vec![quote!(#[tracing::instrument(level = "debug", skip(self))])
+                .to_string()]
+        );
+        assert_eq!(
+            instrument_attrs(&methods[1]),
+            vec![quote!(#[tracing::instrument(level = "debug")]).to_string()]
+        );
+        assert_eq!(
+            instrument_attrs(&methods[2]),
+            vec![quote!(#[tracing::instrument(skip(self, value))]).to_string()]
+        );
+        assert!(methods[3].attrs.is_empty());
+        assert!(methods[4].attrs.is_empty());
+    }
+}
@@ -0,0 +1,28 @@
+//! Writes the conformance test vectors produced by the current code, checked by `tests/test_vectors.rs`.
+//!
+//! ```bash
+//! cargo run --example generate_test_vectors --features testing -- tests/vectors/identity_tree.json
+//! ```
+
+use std::path::PathBuf;
+
+use world_tree::testing::test_vectors;
+
+const DEFAULT_OUTPUT: &str = "tests/vectors/identity_tree.json";
+
+fn main() -> eyre::Result<()> {
+    let output = std::env::args()
+        .nth(1)
+        .map_or_else(|| PathBuf::from(DEFAULT_OUTPUT), PathBuf::from);
+
+    let vectors = test_vectors::generate()?;
+    std::fs::write(&output, serde_json::to_string_pretty(&vectors)? + "\n")?;
+
+    println!(
+        "Wrote {} scenarios to {}",
+        vectors.scenarios.len(),
+        output.display()
+    );
+
+    Ok(())
+}
@@ -1,9 +1,21 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethers::abi::Detokenize;
+use ethers::contract::{ContractCall, ContractError};
 use ethers::middleware::contract::abigen;
+use ethers::providers::Middleware;
+use ethers::types::{H160, U256};
+
+use crate::tree::error::WorldTreeError;
+use crate::tree::Hash;
 
 abigen!(
     IWorldIDIdentityManager,
     r#"[
         function latestRoot() external returns (uint256)
+        function getTreeDepth() external view returns (uint8)
+        function getRootHistoryExpiry() external view returns (uint256)
         event TreeChanged(uint256 indexed preRoot, uint8 indexed kind, uint256 indexed postRoot)
         function registerIdentities(uint256[8] calldata insertionProof, uint256 preRoot, uint32 startIndex, uint256[] calldata identityCommitments, uint256 postRoot) external
         function deleteIdentities(uint256[8] calldata deletionProof, bytes calldata packedDeletionIndices, uint256 preRoot, uint256 postRoot) external
@@ -18,3 +30,219 @@ abigen!(
     event_derives(serde::Deserialize, serde::Serialize)
 
 );
+
+/// Retry policy applied to every view call made by a [`ContractReader`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first call
+    pub attempts: u32,
+    /// Delay before the first retry, increased linearly with each attempt
+    pub backoff: Duration,
+    /// Timeout of each individual attempt
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Reads the view functions of a World ID contract, retrying transient provider failures.
+///
+/// Failures are reported as `ViewCallFailed` or `ViewCallTimeout` errors naming the view call that failed.
+/// Reverts and decoding errors are not retried.
+#[derive(Debug)]
+pub struct ContractReader<M: Middleware + 'static> {
+    pub address: H160,
+    middleware: Arc<M>,
+    retry_policy: RetryPolicy,
+}
+
+impl<M> ContractReader<M>
+where
+    M: Middleware + 'static,
+{
+    pub fn new(address: H160, middleware: Arc<M>) -> Self {
+        Self {
+            address,
+            middleware,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Latest root of the identity manager as of `block`.
+    ///
+    /// Bridged World ID contracts expose the same `latestRoot` function, so this also reads bridged roots.
+    pub async fn latest_root(
+        &self,
+        block: u64,
+    ) -> Result<Hash, WorldTreeError<M>> {
+        let root: U256 = self
+            .call(
+                "latestRoot",
+                self.identity_manager().latest_root().block(block),
+            )
+            .await?;
+
+        Ok(Hash::from_limbs(root.0))
+    }
+
+    pub async fn tree_depth(&self) -> Result<usize, WorldTreeError<M>> {
+        let depth = self
+            .call("getTreeDepth", self.identity_manager().get_tree_depth())
+            .await?;
+
+        Ok(depth as usize)
+    }
+
+    /// Duration for which a root remains valid once it is replaced by a newer root
+    pub async fn root_history_expiry(
+        &self,
+    ) -> Result<Duration, WorldTreeError<M>> {
+        let expiry = self
+            .call(
+                "getRootHistoryExpiry",
+                self.identity_manager().get_root_history_expiry(),
+            )
+            .await?;
+
+        Ok(Duration::from_secs(expiry.low_u64()))
+    }
+
+    fn identity_manager(&self) -> IWorldIDIdentityManager<M> {
+        IWorldIDIdentityManager::new(self.address, self.middleware.clone())
+    }
+
+    async fn call<D: Detokenize>(
+        &self,
+        function: &'static str,
+        call: ContractCall<M, D>,
+    ) -> Result<D, WorldTreeError<M>> {
+        let mut attempt = 1;
+
+        loop {
+            let (err, transient) = match tokio::time::timeout(
+                self.retry_policy.timeout,
+                call.call(),
+            )
+            .await
+            {
+                Ok(Ok(value)) => return Ok(value),
+                Ok(Err(source)) => {
+                    let transient = matches!(
+                        source,
+                        ContractError::MiddlewareError { .. }
+                            | ContractError::ProviderError { .. }
+                    );
+
+                    let err = WorldTreeError::ViewCallFailed {
+                        function,
+                        address: self.address,
+                        source,
+                    };
+
+                    (err, transient)
+                }
+                Err(_) => {
+                    let err = WorldTreeError::ViewCallTimeout {
+                        function,
+                        address: self.address,
+                        timeout: self.retry_policy.timeout,
+                    };
+
+                    (err, true)
+                }
+            };
+
+            if !transient || attempt >= self.retry_policy.attempts {
+                return Err(err);
+            }
+
+            tracing::warn!(?err, attempt, "View call failed, retrying");
+
+            tokio::time::sleep(self.retry_policy.backoff * attempt).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::abi::AbiEncode;
+    use ethers::providers::{
+        JsonRpcError, MockProvider, MockResponse, Provider,
+    };
+    use ethers::types::Bytes;
+
+    use super::*;
+
+    const RETRY_POLICY: RetryPolicy = RetryPolicy {
+        attempts: 3,
+        backoff: Duration::ZERO,
+        timeout: Duration::from_secs(1),
+    };
+
+    fn reader() -> (ContractReader<Provider<MockProvider>>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        let reader = ContractReader::new(H160::zero(), Arc::new(provider))
+            .with_retry_policy(RETRY_POLICY);
+
+        (reader, mock)
+    }
+
+    fn push_transient_error(mock: &MockProvider) {
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: -32000,
+            message: "header not found".to_owned(),
+            data: None,
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_failures() -> eyre::Result<()> {
+        let (reader, mock) = reader();
+
+        // Responses are returned in reverse order
+        mock.push::<Bytes, _>(U256::from(42).encode().into())?;
+        push_transient_error(&mock);
+        push_transient_error(&mock);
+
+        assert_eq!(reader.latest_root(100).await?, Hash::from(42));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_attempts() -> eyre::Result<()> {
+        let (reader, mock) = reader();
+
+        mock.push::<Bytes, _>(U256::from(30).encode().into())?;
+        for _ in 0..RETRY_POLICY.attempts {
+            push_transient_error(&mock);
+        }
+
+        let err = reader.tree_depth().await.unwrap_err();
+        assert!(matches!(
+            err,
+            WorldTreeError::ViewCallFailed {
+                function: "getTreeDepth",
+                ..
+            }
+        ));
+
+        // The remaining response is served once the provider recovers
+        assert_eq!(reader.tree_depth().await?, 30);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,9 @@
 pub mod abi;
 mod error;
+pub mod log_file;
+pub mod metered_client;
 pub mod serde_utils;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod timeout_client;
 pub mod tree;
@@ -0,0 +1,132 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use eyre::WrapErr;
+use tokio::task::JoinHandle;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Log file opened in append mode, which can be reopened at the same path once it was moved away by log rotation
+#[derive(Debug, Clone)]
+pub struct LogFile {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl LogFile {
+    pub fn open(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = open_append(&path)?;
+
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Closes the current file and opens the file at the same path, keeping the current file if it can't be opened
+    pub fn reopen(&self) -> eyre::Result<()> {
+        let file = open_append(&self.path)?;
+        *self.file.lock().unwrap_or_else(PoisonError::into_inner) = file;
+
+        tracing::info!(path = ?self.path, "Log file reopened");
+
+        Ok(())
+    }
+
+    /// Spawns a task reopening the log file every time the process receives SIGHUP
+    #[cfg(unix)]
+    pub fn spawn_reopen_on_sighup(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut sighup = match tokio::signal::unix::signal(
+                tokio::signal::unix::SignalKind::hangup(),
+            ) {
+                Ok(sighup) => sighup,
+                Err(error) => {
+                    tracing::error!(
+                        ?error,
+                        "Failed to install SIGHUP handler, log file reopening is disabled"
+                    );
+                    return;
+                }
+            };
+
+            while sighup.recv().await.is_some() {
+                if let Err(error) = self.reopen() {
+                    tracing::error!(
+                        ?error,
+                        path = ?self.path,
+                        "Failed to reopen log file, keeping the current file"
+                    );
+                }
+            }
+        })
+    }
+}
+
+fn open_append(path: &Path) -> eyre::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .wrap_err_with(|| format!("Failed to open log file {path:?}"))
+}
+
+/// Writer holding the lock on the log file while a single event is written
+pub struct LogFileWriter<'a>(MutexGuard<'a, File>);
+
+impl Write for LogFileWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = LogFileWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogFileWriter(self.file.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reopen() -> eyre::Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("world-tree-log-{}", std::process::id()));
+        let rotated = path.with_extension("1");
+        std::fs::write(&path, "existing\n")?;
+
+        let log_file = LogFile::open(&path)?;
+        log_file.make_writer().write_all(b"first\n")?;
+
+        // Rotated files keep receiving logs until the file is reopened
+        std::fs::rename(&path, &rotated)?;
+        log_file.make_writer().write_all(b"second\n")?;
+        log_file.reopen()?;
+        log_file.make_writer().write_all(b"third\n")?;
+
+        assert_eq!(
+            std::fs::read_to_string(&rotated)?,
+            "existing\nfirst\nsecond\n"
+        );
+        assert_eq!(std::fs::read_to_string(&path)?, "third\n");
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(&rotated)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use ethers::providers::JsonRpcClient;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+/// Requests sent for a single JSON-RPC method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MethodStats {
+    pub requests: u64,
+    /// Number of requests that returned an error, included in `requests`
+    pub errors: u64,
+    /// Cumulative time spent waiting for responses
+    pub latency: Duration,
+}
+
+/// Per method request counts of a provider, shared between its client and the task logging summaries
+#[derive(Debug, Clone, Default)]
+pub struct RpcStats {
+    methods: Arc<Mutex<BTreeMap<String, MethodStats>>>,
+}
+
+impl RpcStats {
+    fn record(&self, method: &str, latency: Duration, is_error: bool) {
+        let mut methods = self.methods.lock().unwrap();
+        let stats = methods.entry(method.to_owned()).or_default();
+
+        stats.requests += 1;
+        stats.errors += u64::from(is_error);
+        stats.latency += latency;
+    }
+
+    /// Stats of every method called since the client was created, by method name
+    pub fn snapshot(&self) -> BTreeMap<String, MethodStats> {
+        self.methods.lock().unwrap().clone()
+    }
+
+    /// Spawns a task logging the stats of every method called through `provider` every `interval`
+    pub fn spawn_summary_log(
+        &self,
+        provider: String,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let stats = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                for (method, stats) in stats.snapshot() {
+                    tracing::info!(
+                        provider,
+                        method,
+                        requests = stats.requests,
+                        errors = stats.errors,
+                        latency = ?stats.latency,
+                        "RPC usage"
+                    );
+                }
+            }
+        })
+    }
+}
+
+/// Transparent wrapper around a JSON-RPC client counting requests, errors and cumulative latency per method, to attribute
+/// RPC costs. Counters are exported with `provider` and `method` labels.
+#[derive(Debug)]
+pub struct MeteredJsonRpcClient<C> {
+    inner: C,
+    /// Label identifying the provider in metrics and logs, e.g. `canonical`
+    provider: String,
+    stats: RpcStats,
+}
+
+impl<C> MeteredJsonRpcClient<C> {
+    pub fn new(inner: C, provider: impl Into<String>) -> Self {
+        Self {
+            inner,
+            provider: provider.into(),
+            stats: RpcStats::default(),
+        }
+    }
+
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    pub fn stats(&self) -> &RpcStats {
+        &self.stats
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<C: JsonRpcClient> JsonRpcClient for MeteredJsonRpcClient<C> {
+    type Error = C::Error;
+
+    async fn request<T, R>(
+        &self,
+        method: &str,
+        params: T,
+    ) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let start = Instant::now();
+        let result = self.inner.request(method, params).await;
+        let latency = start.elapsed();
+
+        self.stats.record(method, latency, result.is_err());
+
+        let provider = self.provider.clone();
+        let method = method.to_owned();
+        metrics::counter!("world_tree_rpc_requests_total", 1, "provider" => provider.clone(), "method" => method.clone());
+        metrics::counter!("world_tree_rpc_latency_milliseconds_total", latency.as_millis() as u64, "provider" => provider.clone(), "method" => method.clone());
+        if result.is_err() {
+            metrics::counter!("world_tree_rpc_errors_total", 1, "provider" => provider, "method" => method);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{Middleware, MockProvider, Provider};
+    use ethers::types::U64;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_counts_requests_per_method() -> eyre::Result<()> {
+        let mock = MockProvider::new();
+        mock.push(U64::from(100))?;
+        mock.push(U64::from(1))?;
+        mock.push(U64::from(101))?;
+
+        let client = MeteredJsonRpcClient::new(mock, "canonical");
+        let stats = client.stats().clone();
+        let provider = Provider::new(client);
+
+        provider.get_block_number().await?;
+        provider.get_chainid().await?;
+        provider.get_block_number().await?;
+        // No response left to return
+        assert!(provider.get_block_number().await.is_err());
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot["eth_blockNumber"].requests, 3);
+        assert_eq!(snapshot["eth_blockNumber"].errors, 1);
+        assert_eq!(snapshot["eth_chainId"].requests, 1);
+        assert_eq!(snapshot["eth_chainId"].errors, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+//! Mock middleware for testing code built on the World Tree without an Ethereum node.
+//!
+//! Enabled with the `mock-middleware` feature.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use ethers::contract::EthEvent;
+use ethers::providers::{
+    JsonRpcClient, JsonRpcError, Middleware, MockProvider, MockResponse,
+    Provider, ProviderError,
+};
+use ethers::types::{
+    BigEndianHash, Block, Bytes, Log, Transaction, TxHash, H160, H256, U256,
+    U64,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::abi::TreeChangedFilter;
+
+const ETH_CHAIN_ID: &str = "eth_chainId";
+const ETH_BLOCK_NUMBER: &str = "eth_blockNumber";
+const ETH_GET_LOGS: &str = "eth_getLogs";
+const ETH_GET_TRANSACTION: &str = "eth_getTransactionByHash";
+const ETH_CALL: &str = "eth_call";
+const ETH_GET_BLOCK: &str = "eth_getBlockByNumber";
+
+/// Middleware returning preset responses, which panics on drop if any expected call was not made.
+///
+/// Responses are returned in the order the calls were expected, regardless of the method called. Every JSON-RPC
+/// request is recorded, so a call to another method consumes the response of an expected call, which is reported as missed.
+#[derive(Debug)]
+pub struct MockMiddleware {
+    provider: Provider<RecordingProvider>,
+    expected_calls: Vec<&'static str>,
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockMiddleware {
+    pub fn builder() -> MockMiddlewareBuilder {
+        MockMiddlewareBuilder::default()
+    }
+
+    /// JSON-RPC methods called so far, in order
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Expected calls that have not been made yet
+    pub fn missed_calls(&self) -> Vec<&'static str> {
+        let mut remaining = HashMap::<_, usize>::new();
+        for method in self.calls.lock().unwrap().iter() {
+            *remaining.entry(method.clone()).or_default() += 1;
+        }
+
+        self.expected_calls
+            .iter()
+            .filter(|method| match remaining.get_mut(**method) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    false
+                }
+                _ => true,
+            })
+            .copied()
+            .collect()
+    }
+}
+
+impl Drop for MockMiddleware {
+    fn drop(&mut self) {
+        // Avoid a double panic, which would abort instead of reporting the original failure
+        if std::thread::panicking() {
+            return;
+        }
+
+        let missed_calls = self.missed_calls();
+        if !missed_calls.is_empty() {
+            panic!("Expected calls were not made: {missed_calls:?}");
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Middleware for MockMiddleware {
+    type Error = ProviderError;
+    type Provider = RecordingProvider;
+    type Inner = Provider<RecordingProvider>;
+
+    fn inner(&self) -> &Self::Inner {
+        &self.provider
+    }
+}
+
+/// Mock provider recording the method of every request, so that calls are tracked whichever [`Middleware`] method makes them
+#[derive(Debug)]
+pub struct RecordingProvider {
+    mock: MockProvider,
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl JsonRpcClient for RecordingProvider {
+    type Error = <MockProvider as JsonRpcClient>::Error;
+
+    async fn request<T, R>(
+        &self,
+        method: &str,
+        params: T,
+    ) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        self.calls.lock().unwrap().push(method.to_owned());
+        self.mock.request(method, params).await
+    }
+}
+
+/// Sets up the calls expected by a [`MockMiddleware`] along with their responses
+#[derive(Debug, Default)]
+pub struct MockMiddlewareBuilder {
+    responses: Vec<(&'static str, MockResponse)>,
+}
+
+impl MockMiddlewareBuilder {
+    /// Expects an `eth_chainId` call
+    pub fn chain_id(self, chain_id: u64) -> Self {
+        self.expect(ETH_CHAIN_ID, U256::from(chain_id))
+    }
+
+    /// Expects an `eth_blockNumber` call
+    pub fn block_number(self, block_number: u64) -> Self {
+        self.expect(ETH_BLOCK_NUMBER, U64::from(block_number))
+    }
+
+    /// Expects an `eth_getLogs` call
+    pub fn logs(self, logs: Vec<Log>) -> Self {
+        self.expect(ETH_GET_LOGS, logs)
+    }
+
+    /// Expects an `eth_getLogs` call failing with a JSON-RPC error, e.g. a provider rejecting a query that matches too many logs
+    pub fn logs_error(self, code: i64, message: &str) -> Self {
+        self.expect_error(ETH_GET_LOGS, code, message)
+    }
+
+    /// Expects an `eth_getLogs` call returning the given `TreeChanged` events, see [`tree_changed_log`]
+    pub fn tree_changed_events(
+        self,
+        address: H160,
+        events: &[(TreeChangedFilter, u64, H256)],
+    ) -> Self {
+        let logs = events
+            .iter()
+            .map(|(event, block_number, tx_hash)| {
+                tree_changed_log(address, event, *block_number, *tx_hash)
+            })
+            .collect();
+
+        self.logs(logs)
+    }
+
+    /// Expects an `eth_getTransactionByHash` call
+    pub fn transaction(self, transaction: Option<Transaction>) -> Self {
+        self.expect(ETH_GET_TRANSACTION, transaction)
+    }
+
+    /// Expects an `eth_getBlockByNumber` call, `None` if the block does not exist
+    pub fn block(self, block: Option<Block<TxHash>>) -> Self {
+        self.expect(ETH_GET_BLOCK, block)
+    }
+
+    /// Expects an `eth_call` call returning the ABI encoded `output`, e.g. the result of a contract view function
+    pub fn call_result(self, output: Bytes) -> Self {
+        self.expect(ETH_CALL, output)
+    }
+
+    pub fn build(self) -> MockMiddleware {
+        let mock = MockProvider::new();
+
+        // The mock provider returns the most recently pushed response first
+        for (_, response) in self.responses.iter().rev() {
+            mock.push_response(response.clone());
+        }
+
+        let calls = Arc::new(Mutex::new(vec![]));
+
+        MockMiddleware {
+            provider: Provider::new(RecordingProvider {
+                mock,
+                calls: calls.clone(),
+            }),
+            expected_calls: self
+                .responses
+                .into_iter()
+                .map(|(method, _)| method)
+                .collect(),
+            calls,
+        }
+    }
+
+    fn expect<T: Serialize>(
+        mut self,
+        method: &'static str,
+        response: T,
+    ) -> Self {
+        let response =
+            serde_json::to_value(response).expect("Responses are serializable");
+
+        self.responses.push((method, MockResponse::Value(response)));
+        self
+    }
+
+    fn expect_error(
+        mut self,
+        method: &'static str,
+        code: i64,
+        message: &str,
+    ) -> Self {
+        let error = JsonRpcError {
+            code,
+            message: message.to_owned(),
+            data: None,
+        };
+
+        self.responses.push((method, MockResponse::Error(error)));
+        self
+    }
+}
+
+/// Encodes a `TreeChanged` event emitted by the identity manager at `address` as a log
+pub fn tree_changed_log(
+    address: H160,
+    event: &TreeChangedFilter,
+    block_number: u64,
+    tx_hash: H256,
+) -> Log {
+    Log {
+        address,
+        topics: vec![
+            TreeChangedFilter::signature(),
+            H256::from_uint(&event.pre_root),
+            H256::from_low_u64_be(event.kind as u64),
+            H256::from_uint(&event.post_root),
+        ],
+        block_number: Some(block_number.into()),
+        transaction_hash: Some(tx_hash),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::abi::RawLog;
+    use ethers::contract::EthLogDecode;
+    use ethers::types::Filter;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_middleware() -> eyre::Result<()> {
+        let middleware = MockMiddleware::builder()
+            .chain_id(1)
+            .block_number(100)
+            .build();
+
+        assert_eq!(middleware.get_chainid().await?, U256::from(1));
+        assert_eq!(middleware.get_block_number().await?, U64::from(100));
+        assert_eq!(middleware.calls(), vec![ETH_CHAIN_ID, ETH_BLOCK_NUMBER]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Expected calls were not made")]
+    async fn test_missed_calls_panic() {
+        let middleware = MockMiddleware::builder()
+            .chain_id(1)
+            .block_number(100)
+            .build();
+
+        middleware.get_chainid().await.unwrap();
+        assert_eq!(middleware.missed_calls(), vec![ETH_BLOCK_NUMBER]);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Expected calls were not made")]
+    async fn test_unexpected_method_is_recorded() {
+        let middleware = MockMiddleware::builder().chain_id(1).build();
+
+        // Not overridden by the mock, the call still goes through the recording provider
+        middleware.get_gas_price().await.unwrap();
+        assert_eq!(middleware.calls(), vec!["eth_gasPrice"]);
+        assert_eq!(middleware.missed_calls(), vec![ETH_CHAIN_ID]);
+    }
+
+    #[tokio::test]
+    async fn test_tree_changed_events() -> eyre::Result<()> {
+        let event = TreeChangedFilter {
+            pre_root: U256::from(1),
+            kind: 0,
+            post_root: U256::from(2),
+        };
+
+        let middleware = MockMiddleware::builder()
+            .tree_changed_events(
+                H160::zero(),
+                &[(event.clone(), 10, H256::zero())],
+            )
+            .build();
+
+        let logs = middleware.get_logs(&Filter::new()).await?;
+        assert_eq!(logs.len(), 1);
+
+        let decoded =
+            TreeChangedFilter::decode_log(&RawLog::from(logs[0].clone()))?;
+        assert_eq!(decoded, event);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+//! World tree synced through a [`MockMiddleware`], for testing the service without an Ethereum node.
+//!
+//! Enabled with the `mock-middleware` feature.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ethers::types::H160;
+
+use super::{MockMiddleware, MockMiddlewareBuilder};
+use crate::tree::block_scanner::BlockRangeFilter;
+use crate::tree::tree_manager::{CanonicalTree, TreeManager};
+use crate::tree::WorldTree;
+
+/// Chain ID reported by the middleware of [`mock_world_tree`]
+pub const MOCK_CHAIN_ID: u64 = 1;
+
+/// Builds a world tree for the identity manager at the zero address on chain [`MOCK_CHAIN_ID`], backed by
+/// `cache_file`. `responses` sets up the calls expected after the ones made while constructing the tree.
+pub async fn mock_world_tree(
+    tree_depth: usize,
+    cache_file: &PathBuf,
+    responses: impl FnOnce(MockMiddlewareBuilder) -> MockMiddlewareBuilder,
+) -> eyre::Result<WorldTree<MockMiddleware>> {
+    // The tree manager and its block scanner both ask for the chain ID
+    let builder = MockMiddleware::builder()
+        .chain_id(MOCK_CHAIN_ID)
+        .chain_id(MOCK_CHAIN_ID);
+    let middleware = responses(builder).build();
+
+    let tree_manager = TreeManager::<_, CanonicalTree>::new(
+        H160::zero(),
+        100,
+        0,
+        BlockRangeFilter::default(),
+        Arc::new(middleware),
+    )
+    .await?;
+
+    Ok(WorldTree::new(
+        tree_depth,
+        tree_manager,
+        vec![],
+        cache_file,
+    )?)
+}
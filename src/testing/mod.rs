@@ -0,0 +1,18 @@
+//! Helpers for testing code built on the World Tree, enabled with the `testing` feature.
+//!
+//! The mock middleware and the world tree built on it additionally require the `mock-middleware` feature.
+
+#[cfg(feature = "mock-middleware")]
+mod mock_middleware;
+#[cfg(feature = "mock-middleware")]
+mod mock_world_tree;
+pub mod test_vectors;
+mod tracing_test;
+
+#[cfg(feature = "mock-middleware")]
+pub use self::mock_middleware::{
+    tree_changed_log, MockMiddleware, MockMiddlewareBuilder, RecordingProvider,
+};
+#[cfg(feature = "mock-middleware")]
+pub use self::mock_world_tree::{mock_world_tree, MOCK_CHAIN_ID};
+pub use self::tracing_test::{RecordKind, RecordedSpan, TracingTestSubscriber};
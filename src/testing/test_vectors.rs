@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use eyre::ContextCompat;
+use semaphore::merkle_tree::Branch;
+use serde::{Deserialize, Serialize};
+
+use crate::tree::identity_tree::{IdentityTree, LeafUpdates};
+use crate::tree::replay::RecordedUpdate;
+use crate::tree::{Hash, LeafIndex};
+
+/// Conformance vectors for implementations of the World Tree in other languages, produced by [`generate`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestVectors {
+    pub scenarios: Vec<Scenario>,
+}
+
+/// Sequence of updates applied to an empty tree, with the root expected after each update and proofs expected once all
+/// updates are applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Scenario {
+    pub name: String,
+    pub tree_depth: usize,
+    pub updates: Vec<RecordedUpdate>,
+    pub proofs: Vec<ExpectedProof>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpectedProof {
+    pub identity_commitment: Hash,
+    pub leaf_index: u32,
+    pub root: Hash,
+    /// Sibling hashes from the leaf up to the root, the bits of `leaf_index` giving the direction of each step
+    pub siblings: Vec<Hash>,
+}
+
+impl Scenario {
+    /// Applies the updates of the scenario to an empty tree and computes the proofs of `proven_identities`, recording
+    /// the resulting roots and proofs as the expected values
+    fn record(
+        name: &str,
+        tree_depth: usize,
+        leaf_updates: Vec<LeafUpdates>,
+        proven_identities: &[Hash],
+    ) -> eyre::Result<Self> {
+        let mut identity_tree = IdentityTree::new(tree_depth);
+        let mut updates = vec![];
+
+        for leaf_updates in leaf_updates {
+            identity_tree.apply_leaf_updates(leaf_updates.clone())?;
+            updates.push(RecordedUpdate {
+                root: identity_tree.tree.root(),
+                leaf_updates,
+            });
+        }
+
+        let proofs = proven_identities
+            .iter()
+            .map(|identity| expected_proof(&identity_tree, *identity))
+            .collect::<eyre::Result<_>>()?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            tree_depth,
+            updates,
+            proofs,
+        })
+    }
+
+    /// Replays the scenario with the current code, returning an error describing the first root or proof that differs
+    /// from the expected values
+    pub fn check(&self) -> eyre::Result<()> {
+        let mut identity_tree = IdentityTree::new(self.tree_depth);
+
+        for (index, update) in self.updates.iter().enumerate() {
+            identity_tree.apply_leaf_updates(update.leaf_updates.clone())?;
+
+            let root = identity_tree.tree.root();
+            eyre::ensure!(
+                root == update.root,
+                "Scenario {}: root after update {index} is {root:#x}, expected {:#x}",
+                self.name,
+                update.root
+            );
+        }
+
+        for expected in &self.proofs {
+            let actual =
+                expected_proof(&identity_tree, expected.identity_commitment)?;
+            eyre::ensure!(
+                &actual == expected,
+                "Scenario {}: proof of leaf {} does not match the expected proof",
+                self.name,
+                expected.leaf_index
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn expected_proof(
+    identity_tree: &IdentityTree<Vec<Hash>>,
+    identity_commitment: Hash,
+) -> eyre::Result<ExpectedProof> {
+    let leaf_index = *identity_tree
+        .leaves
+        .get(&identity_commitment)
+        .with_context(|| {
+            format!("Identity {identity_commitment:#x} not in the tree")
+        })?;
+    let inclusion_proof = identity_tree
+        .inclusion_proof(identity_commitment, None)?
+        .with_context(|| {
+            format!("No proof for identity {identity_commitment:#x}")
+        })?;
+
+    let siblings = inclusion_proof
+        .proof
+        .0
+        .iter()
+        .map(|branch| match branch {
+            Branch::Left(sibling) | Branch::Right(sibling) => *sibling,
+        })
+        .collect();
+
+    Ok(ExpectedProof {
+        identity_commitment,
+        leaf_index,
+        root: inclusion_proof.root,
+        siblings,
+    })
+}
+
+fn insert(start: u32, identities: &[Hash]) -> LeafUpdates {
+    LeafUpdates::Insert(
+        identities
+            .iter()
+            .enumerate()
+            .map(|(i, identity)| (LeafIndex::from(start + i as u32), *identity))
+            .collect(),
+    )
+}
+
+fn delete(indices: &[u32]) -> LeafUpdates {
+    LeafUpdates::Delete(
+        indices
+            .iter()
+            .map(|index| (LeafIndex::from(*index), Hash::ZERO))
+            .collect::<HashMap<_, _>>(),
+    )
+}
+
+/// Deterministic identity commitments that look random, all within the scalar field
+fn identities(seed: u64, count: usize) -> Vec<Hash> {
+    (0..count as u64)
+        .map(|i| {
+            let x = (seed << 32 | i).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+            Hash::from_limbs([x, x.rotate_left(17), x.rotate_left(41), 0])
+        })
+        .collect()
+}
+
+/// Generates the conformance vectors with the current code. Scenarios cover insertions, deletions and insertions at
+/// indices recycled after a deletion, for several tree depths.
+pub fn generate() -> eyre::Result<TestVectors> {
+    let small = identities(1, 8);
+    let medium = identities(2, 100);
+    let recycled = identities(3, 2);
+    let large = identities(4, 16);
+
+    let scenarios = vec![
+        Scenario::record(
+            "insert_then_delete",
+            10,
+            vec![
+                insert(0, &small[..5]),
+                delete(&[1, 3]),
+                insert(5, &small[5..]),
+            ],
+            &[small[0], small[2], small[7]],
+        )?,
+        Scenario::record(
+            "recycled_indices",
+            20,
+            vec![
+                insert(0, &medium[..60]),
+                insert(60, &medium[60..]),
+                delete(&[0, 17, 99]),
+                insert(0, &recycled[..1]),
+                insert(17, &recycled[1..]),
+            ],
+            &[recycled[0], recycled[1], medium[50], medium[98]],
+        )?,
+        Scenario::record(
+            "full_depth",
+            30,
+            vec![insert(0, &large), delete(&[15])],
+            &[large[0], large[14]],
+        )?,
+    ];
+
+    Ok(TestVectors { scenarios })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_vectors_check() -> eyre::Result<()> {
+        let vectors = generate()?;
+
+        for scenario in &vectors.scenarios {
+            scenario.check()?;
+        }
+
+        // Tampering with any expected value is detected
+        let mut scenario = vectors.scenarios[0].clone();
+        scenario.updates[1].root = Hash::from(1);
+        assert!(scenario.check().is_err());
+
+        let mut scenario = vectors.scenarios[1].clone();
+        scenario.proofs[0].siblings[3] = Hash::from(1);
+        assert!(scenario.check().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::DefaultGuard;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Span,
+    Event,
+}
+
+/// Span or event captured by a [`TracingTestSubscriber`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedSpan {
+    pub kind: RecordKind,
+    /// Name of the span, or the name of the callsite for events
+    pub name: String,
+    /// Fields formatted with their `Debug` implementation, except for strings which are recorded as is.
+    /// Fields recorded on a span after it was created are included.
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Records every span and event emitted while it is installed, for asserting on instrumentation in tests
+///
+/// ```ignore
+/// let subscriber = TracingTestSubscriber::default();
+/// let _guard = subscriber.set_default();
+///
+/// world_tree.sync_to_head().await?;
+///
+/// assert_eq!(subscriber.spans_named("sync_to_head").count(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TracingTestSubscriber {
+    records: Arc<Mutex<Vec<RecordedSpan>>>,
+}
+
+/// Position of a span in the recorded spans, stored in the span extensions to record fields added later
+struct RecordIndex(usize);
+
+impl TracingTestSubscriber {
+    /// Installs the subscriber as the default for the current thread until the guard is dropped
+    pub fn set_default(&self) -> DefaultGuard {
+        tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(self.clone()),
+        )
+    }
+
+    /// All recorded spans and events, in the order they were created
+    pub fn records(&self) -> Vec<RecordedSpan> {
+        self.records.lock().unwrap().clone()
+    }
+
+    pub fn spans_named(
+        &self,
+        name: &str,
+    ) -> impl Iterator<Item = RecordedSpan> {
+        let name = name.to_owned();
+
+        self.records().into_iter().filter(move |record| {
+            record.kind == RecordKind::Span && record.name == name
+        })
+    }
+
+    /// Values of the field `name` across all recorded spans and events
+    pub fn field_values(&self, name: &str) -> Vec<String> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|record| record.fields.get(name).cloned())
+            .collect()
+    }
+
+    fn push(&self, record: RecordedSpan) -> usize {
+        let mut records = self.records.lock().unwrap();
+        records.push(record);
+        records.len() - 1
+    }
+}
+
+impl<S> Layer<S> for TracingTestSubscriber
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(
+        &self,
+        attrs: &Attributes<'_>,
+        id: &Id,
+        ctx: Context<'_, S>,
+    ) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+
+        let index = self.push(RecordedSpan {
+            kind: RecordKind::Span,
+            name: attrs.metadata().name().to_owned(),
+            fields: visitor.0,
+        });
+
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(RecordIndex(index));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let index = match span.extensions().get::<RecordIndex>() {
+            Some(RecordIndex(index)) => *index,
+            None => return,
+        };
+
+        let mut visitor = FieldVisitor::default();
+        values.record(&mut visitor);
+
+        self.records.lock().unwrap()[index].fields.extend(visitor.0);
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+
+        self.push(RecordedSpan {
+            kind: RecordKind::Event,
+            name: event.metadata().name().to_owned(),
+            fields: visitor.0,
+        });
+    }
+}
+
+#[derive(Default)]
+struct FieldVisitor(BTreeMap<String, String>);
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_owned(), value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_owned(), format!("{value:?}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tracing::instrument(fields(root))]
+    fn apply_update(identity: u64) {
+        tracing::Span::current().record("root", "0x1");
+        tracing::info!(%identity, "Applied update");
+    }
+
+    #[test]
+    fn test_records_spans_and_events() {
+        let subscriber = TracingTestSubscriber::default();
+        let _guard = subscriber.set_default();
+
+        apply_update(1);
+        apply_update(2);
+
+        assert_eq!(subscriber.spans_named("apply_update").count(), 2);
+        assert_eq!(subscriber.spans_named("other").count(), 0);
+
+        // Fields of spans and events are both captured
+        assert_eq!(
+            subscriber.field_values("identity"),
+            vec!["1", "1", "2", "2"]
+        );
+        assert_eq!(subscriber.field_values("root"), vec!["0x1", "0x1"]);
+        assert_eq!(
+            subscriber.field_values("message"),
+            vec!["Applied update", "Applied update"]
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt::Debug;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, JsonRpcError, ProviderError, RpcError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+/// Default maximum time to wait for the response to a single JSON-RPC request
+pub const DEFAULT_RPC_CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+pub enum TimeoutClientError<E> {
+    #[error("RPC call {method} timed out after {timeout_ms}ms")]
+    RpcTimeout { method: String, timeout_ms: u64 },
+    #[error(transparent)]
+    Client(E),
+}
+
+impl<E: RpcError> RpcError for TimeoutClientError<E> {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            Self::RpcTimeout { .. } => None,
+            Self::Client(err) => err.as_error_response(),
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            Self::RpcTimeout { .. } => None,
+            Self::Client(err) => err.as_serde_error(),
+        }
+    }
+}
+
+impl<E> From<TimeoutClientError<E>> for ProviderError
+where
+    E: RpcError + Into<ProviderError> + 'static,
+{
+    fn from(err: TimeoutClientError<E>) -> Self {
+        match err {
+            TimeoutClientError::Client(err) => err.into(),
+            err => ProviderError::JsonRpcClientError(Box::new(err)),
+        }
+    }
+}
+
+/// Wrapper around a JSON-RPC client failing requests that take longer than `timeout`, so that an overloaded node can't
+/// block a sync task indefinitely. Timed out requests are retried like any other failed request.
+#[derive(Debug)]
+pub struct TimeoutJsonRpcClient<C> {
+    inner: C,
+    timeout: Duration,
+}
+
+impl<C> TimeoutJsonRpcClient<C> {
+    pub fn new(inner: C, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<C> JsonRpcClient for TimeoutJsonRpcClient<C>
+where
+    C: JsonRpcClient,
+    C::Error: 'static,
+{
+    type Error = TimeoutClientError<C::Error>;
+
+    async fn request<T, R>(
+        &self,
+        method: &str,
+        params: T,
+    ) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        match tokio::time::timeout(
+            self.timeout,
+            self.inner.request(method, params),
+        )
+        .await
+        {
+            Ok(result) => result.map_err(TimeoutClientError::Client),
+            Err(_) => {
+                let timeout_ms = self.timeout.as_millis() as u64;
+                tracing::warn!(method, timeout_ms, "RPC call timed out");
+
+                Err(TimeoutClientError::RpcTimeout {
+                    method: method.to_owned(),
+                    timeout_ms,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{Middleware, MockProvider, Provider};
+    use ethers::types::U64;
+
+    use super::*;
+
+    /// Client never responding to any request
+    #[derive(Debug)]
+    struct UnresponsiveClient;
+
+    #[async_trait]
+    impl JsonRpcClient for UnresponsiveClient {
+        type Error = <MockProvider as JsonRpcClient>::Error;
+
+        async fn request<T, R>(
+            &self,
+            _method: &str,
+            _params: T,
+        ) -> Result<R, Self::Error>
+        where
+            T: Debug + Serialize + Send + Sync,
+            R: DeserializeOwned + Send,
+        {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_times_out() -> eyre::Result<()> {
+        let client = TimeoutJsonRpcClient::new(
+            UnresponsiveClient,
+            Duration::from_millis(500),
+        );
+
+        let err = client
+            .request::<_, U64>("eth_getLogs", ())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            TimeoutClientError::RpcTimeout { ref method, timeout_ms: 500 } if method == "eth_getLogs"
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_passes_responses_through() -> eyre::Result<()> {
+        let mock = MockProvider::new();
+        mock.push(U64::from(100))?;
+
+        let provider = Provider::new(TimeoutJsonRpcClient::new(
+            mock,
+            DEFAULT_RPC_CALL_TIMEOUT,
+        ));
+        assert_eq!(provider.get_block_number().await?, U64::from(100));
+
+        // No response left to return
+        assert!(provider.get_block_number().await.is_err());
+
+        Ok(())
+    }
+}
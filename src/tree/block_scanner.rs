@@ -1,11 +1,14 @@
 use std::fmt::Debug;
+use std::ops::RangeInclusive;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use ethers::providers::Middleware;
 use ethers::types::{BlockNumber, Filter, Log};
 use futures::stream::FuturesOrdered;
 use futures::StreamExt;
+use serde::{Deserialize, Serialize};
 
 /// The `BlockScanner` utility tool enables allows parsing arbitrary onchain events
 #[derive(Debug)]
@@ -14,10 +17,18 @@ pub struct BlockScanner<M: Middleware + 'static> {
     pub middleware: Arc<M>,
     /// The block from which to start parsing a given event
     pub next_block: AtomicU64,
-    /// The maximum block range to parse
-    window_size: u64,
+    /// Latest block reported by the provider
+    pub head_block: AtomicU64,
+    /// Unix timestamp in seconds of the last successful response from the provider, zero if it has not responded yet
+    pub last_response: AtomicU64,
+    /// The maximum block range to parse, reduced when the provider rejects a query for returning too many results
+    window_size: AtomicU64,
+    /// The configured window size, which `window_size` grows back to after successful queries
+    max_window_size: u64,
     /// Filter specifying the address and topics to match on when scanning
     filter: Filter,
+    /// Restricts scanning to block ranges that may contain events
+    block_range_filter: BlockRangeFilter,
     chain_id: u64,
 }
 
@@ -36,33 +47,55 @@ where
         Ok(Self {
             middleware,
             next_block: AtomicU64::new(current_block),
-            window_size,
+            head_block: AtomicU64::new(0),
+            last_response: AtomicU64::new(0),
+            window_size: AtomicU64::new(window_size),
+            max_window_size: window_size,
             filter,
+            block_range_filter: BlockRangeFilter::default(),
             chain_id,
         })
     }
 
+    /// Restricts scanning to the active ranges of `block_range_filter`, skipping blocks known not to contain events
+    pub fn with_block_range_filter(
+        mut self,
+        block_range_filter: BlockRangeFilter,
+    ) -> Self {
+        self.block_range_filter = block_range_filter;
+        self
+    }
+
     /// Retrieves events matching the specified address and topics from the last synced block to the latest block, stepping by `window_size`.
     /// Note that the logs are unsorted and should be handled accordingly.
     pub async fn next(&self) -> Result<Vec<Log>, M::Error> {
+        self.next_with_progress(|_, _| {}).await
+    }
+
+    /// Same as [`Self::next`], calling `on_range` with the last block of each range scanned and the latest block, in
+    /// block order
+    pub async fn next_with_progress(
+        &self,
+        mut on_range: impl FnMut(u64, u64),
+    ) -> Result<Vec<Log>, M::Error> {
         let latest_block = self.middleware.get_block_number().await?.as_u64();
+        self.head_block.store(latest_block, Ordering::SeqCst);
+        self.record_response();
         let mut next_block = self.next_block.load(Ordering::SeqCst);
+        let window_size = self.window_size();
 
         let mut tasks = FuturesOrdered::new();
         while next_block < latest_block {
-            let to_block = (next_block + self.window_size).min(latest_block);
+            let to_block = (next_block + window_size).min(latest_block);
 
-            tracing::debug!(chain_id = ?self.chain_id, from_block = ?next_block, ?to_block, "Scanning blocks");
+            for (range_start, range_end) in
+                self.block_range_filter.intersect(next_block, to_block)
+            {
+                tracing::debug!(chain_id = ?self.chain_id, from_block = ?range_start, to_block = ?range_end, window_size, "Scanning blocks");
 
-            let filter = self
-                .filter
-                .clone()
-                .from_block(BlockNumber::Number(next_block.into()))
-                .to_block(BlockNumber::Number(to_block.into()));
-
-            let middleware = self.middleware.clone();
-
-            tasks.push_back(async move { middleware.get_logs(&filter).await });
+                let logs = self.get_logs(range_start, range_end);
+                tasks.push_back(async move { (range_end, logs.await) });
+            }
 
             next_block = to_block + 1;
         }
@@ -70,16 +103,384 @@ where
         //Sort all of the results
         let mut aggregated_logs = vec![];
 
-        while let Some(result) = tasks.next().await {
+        while let Some((range_end, result)) = tasks.next().await {
             let logs = result?;
+            on_range(range_end, latest_block);
 
             aggregated_logs.extend(logs);
         }
 
         self.next_block.store(next_block, Ordering::SeqCst);
+        self.record_response();
 
         tracing::debug!(chain_id = ?self.chain_id, last_synced_block = ?next_block - 1, "Last synced block updated");
 
+        let chain_id = self.chain_id.to_string();
+        metrics::gauge!("world_tree_chain_head_block", latest_block as f64, "chain_id" => chain_id.clone());
+        metrics::gauge!("world_tree_last_synced_block", next_block.saturating_sub(1) as f64, "chain_id" => chain_id);
+
         Ok(aggregated_logs)
     }
+
+    /// Current maximum block range of a single log query
+    pub fn window_size(&self) -> u64 {
+        self.window_size.load(Ordering::SeqCst)
+    }
+
+    /// Retrieves the logs in the inclusive range `[from_block, to_block]`. If the provider rejects a query for returning
+    /// too many results, the query is split in halves down to a single block, and the window size is reduced accordingly
+    /// for subsequent queries. Once a query spanning the reduced window succeeds, the window size is doubled back towards
+    /// its configured value.
+    async fn get_logs(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<Log>, M::Error> {
+        let mut logs = vec![];
+        let mut from = from_block;
+        let mut window_size = to_block - from_block;
+        let mut reduced = false;
+
+        while from <= to_block {
+            let to = from.saturating_add(window_size).min(to_block);
+
+            let filter = self
+                .filter
+                .clone()
+                .from_block(BlockNumber::Number(from.into()))
+                .to_block(BlockNumber::Number(to.into()));
+
+            match self.middleware.get_logs(&filter).await {
+                Ok(range_logs) => {
+                    logs.extend(range_logs);
+
+                    if !reduced && to - from >= self.window_size() {
+                        self.grow_window_size();
+                    }
+
+                    from = to + 1;
+                }
+                Err(err) if to > from && is_too_many_results(&err) => {
+                    window_size = (to - from) / 2;
+                    reduced = true;
+                    // A window of zero would query a single block at a time
+                    self.window_size
+                        .fetch_min(window_size.max(1), Ordering::SeqCst);
+
+                    tracing::warn!(
+                        chain_id = ?self.chain_id,
+                        from_block = from,
+                        to_block = to,
+                        window_size,
+                        "Log query returned too many results, reducing the window size"
+                    );
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(logs)
+    }
+
+    fn grow_window_size(&self) {
+        let max_window_size = self.max_window_size;
+        let _ = self.window_size.fetch_update(
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+            |window_size| {
+                (window_size < max_window_size).then(|| {
+                    window_size.saturating_mul(2).min(max_window_size).max(1)
+                })
+            },
+        );
+    }
+
+    /// Last block scanned for events
+    pub fn last_synced_block(&self) -> u64 {
+        self.next_block.load(Ordering::SeqCst).saturating_sub(1)
+    }
+
+    /// Returns the chain head observed from the provider and how far the scanner has processed
+    pub fn provider_status(&self) -> ProviderStatus {
+        let last_response = self.last_response.load(Ordering::SeqCst);
+
+        ProviderStatus {
+            head_block: self.head_block.load(Ordering::SeqCst),
+            last_synced_block: self.last_synced_block(),
+            last_response: (last_response != 0).then_some(last_response),
+            window_size: self.window_size(),
+        }
+    }
+
+    fn record_response(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        self.last_response.store(now, Ordering::SeqCst);
+    }
+}
+
+/// Substrings of the errors returned by common providers when a log query matches too many logs or spans too many blocks
+const TOO_MANY_RESULTS_ERRORS: &[&str] = &[
+    "query returned more than",
+    "too many results",
+    "response size exceeded",
+    "block range is too wide",
+    "block range too large",
+    "exceed maximum block range",
+];
+
+fn is_too_many_results(err: &impl std::error::Error) -> bool {
+    let message = err.to_string().to_lowercase();
+
+    TOO_MANY_RESULTS_ERRORS
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+/// Chain head observed from a provider compared to the last block processed from it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderStatus {
+    /// Latest block reported by the provider
+    pub head_block: u64,
+    /// Last block scanned for events
+    pub last_synced_block: u64,
+    /// Unix timestamp in seconds of the last successful response from the provider
+    pub last_response: Option<u64>,
+    /// Current maximum block range of a single log query
+    pub window_size: u64,
+}
+
+impl ProviderStatus {
+    /// Number of blocks between the observed head and the last scanned block
+    pub fn lag(&self) -> u64 {
+        self.head_block.saturating_sub(self.last_synced_block)
+    }
+}
+
+/// Narrows block scanning to ranges known to contain relevant events, allowing contracts that are updated
+/// infrequently to be synced without querying every block.
+///
+/// Ranges describe the known history of the contract, so blocks after the end of the last active range are
+/// always scanned. A filter without any ranges scans every block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockRangeFilter {
+    /// Sorted, non-overlapping, inclusive ranges of blocks that may contain events
+    active_ranges: Vec<RangeInclusive<u64>>,
+}
+
+impl BlockRangeFilter {
+    /// Creates a new filter from inclusive block ranges, merging any ranges that overlap or are adjacent
+    pub fn new(ranges: impl IntoIterator<Item = RangeInclusive<u64>>) -> Self {
+        let mut ranges = ranges
+            .into_iter()
+            .filter(|range| !range.is_empty())
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|range| *range.start());
+
+        let mut active_ranges: Vec<RangeInclusive<u64>> = vec![];
+        for range in ranges {
+            match active_ranges.last_mut() {
+                Some(last)
+                    if *range.start() <= last.end().saturating_add(1) =>
+                {
+                    *last = *last.start()..=*last.end().max(range.end());
+                }
+                _ => active_ranges.push(range),
+            }
+        }
+
+        Self { active_ranges }
+    }
+
+    pub fn active_ranges(&self) -> &[RangeInclusive<u64>] {
+        &self.active_ranges
+    }
+
+    /// Returns the sub-ranges of the inclusive range `[from_block, to_block]` that should be scanned
+    pub fn intersect(&self, from_block: u64, to_block: u64) -> Vec<(u64, u64)> {
+        let Some(last_range) = self.active_ranges.last() else {
+            return vec![(from_block, to_block)];
+        };
+
+        let mut ranges = vec![];
+        for range in self.active_ranges.iter() {
+            let start = from_block.max(*range.start());
+            let end = to_block.min(*range.end());
+
+            if start <= end {
+                ranges.push((start, end));
+            }
+        }
+
+        // Blocks past the known history are always scanned
+        if let Some(start) = last_range.end().checked_add(1) {
+            let start = from_block.max(start);
+            if start <= to_block {
+                ranges.push((start, to_block));
+            }
+        }
+
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_status_serialization() -> eyre::Result<()> {
+        let status = ProviderStatus {
+            head_block: 110,
+            last_synced_block: 100,
+            last_response: None,
+            window_size: 1000,
+        };
+
+        assert_eq!(status.lag(), 10);
+        assert_eq!(
+            serde_json::to_value(status)?,
+            serde_json::json!({
+                "headBlock": 110,
+                "lastSyncedBlock": 100,
+                "lastResponse": null,
+                "windowSize": 1000,
+            })
+        );
+
+        // The scanner may run ahead of the last observed head
+        let status = ProviderStatus {
+            head_block: 90,
+            ..status
+        };
+        assert_eq!(status.lag(), 0);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-middleware")]
+    #[tokio::test]
+    async fn test_too_many_results_halves_window() -> eyre::Result<()> {
+        use crate::testing::MockMiddleware;
+
+        let middleware = MockMiddleware::builder()
+            .chain_id(1)
+            .block_number(100)
+            // [0, 100] is split into [0, 50] and [51, 100]
+            .logs_error(-32005, "query returned more than 10000 results")
+            .logs(vec![Log::default()])
+            .logs(vec![Log::default()])
+            // Queries at the reduced window size succeed, so it is doubled back to its configured value
+            .block_number(200)
+            .logs(vec![])
+            .logs(vec![])
+            .build();
+
+        let block_scanner =
+            BlockScanner::new(Arc::new(middleware), 100, 0, Filter::new())
+                .await?;
+
+        assert_eq!(block_scanner.next().await?.len(), 2);
+        assert_eq!(block_scanner.window_size(), 50);
+        assert_eq!(block_scanner.last_synced_block(), 100);
+
+        assert!(block_scanner.next().await?.is_empty());
+        assert_eq!(block_scanner.provider_status().window_size, 100);
+        assert_eq!(block_scanner.last_synced_block(), 200);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-middleware")]
+    #[tokio::test]
+    async fn test_window_size_stays_positive() -> eyre::Result<()> {
+        use crate::testing::MockMiddleware;
+
+        let middleware = MockMiddleware::builder()
+            .chain_id(1)
+            .block_number(1)
+            // [0, 1] is split into single blocks
+            .logs_error(-32005, "query returned more than 10000 results")
+            .logs(vec![Log::default()])
+            .logs(vec![Log::default()])
+            .build();
+
+        let block_scanner =
+            BlockScanner::new(Arc::new(middleware), 1, 0, Filter::new())
+                .await?;
+
+        assert_eq!(block_scanner.next().await?.len(), 2);
+        assert_eq!(block_scanner.window_size(), 1);
+        assert_eq!(block_scanner.last_synced_block(), 1);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-middleware")]
+    #[tokio::test]
+    async fn test_other_errors_are_not_retried() -> eyre::Result<()> {
+        use crate::testing::MockMiddleware;
+
+        let middleware = MockMiddleware::builder()
+            .chain_id(1)
+            .block_number(100)
+            .logs_error(-32000, "header not found")
+            .build();
+
+        let block_scanner =
+            BlockScanner::new(Arc::new(middleware), 100, 0, Filter::new())
+                .await?;
+
+        assert!(block_scanner.next().await.is_err());
+        assert_eq!(block_scanner.window_size(), 100);
+        assert_eq!(block_scanner.last_synced_block(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_too_many_results_errors() {
+        let err = std::io::Error::other(
+            "Query returned more than 10000 results. Try with this block range [0x1, 0x2].",
+        );
+        assert!(is_too_many_results(&err));
+
+        let err = std::io::Error::other("header not found");
+        assert!(!is_too_many_results(&err));
+    }
+
+    #[test]
+    fn test_block_range_filter_merges_ranges() {
+        let filter = BlockRangeFilter::new([20..=30, 0..=5, 6..=10, 25..=40]);
+
+        assert_eq!(filter.active_ranges(), &[0..=10, 20..=40]);
+    }
+
+    #[test]
+    fn test_block_range_filter_without_ranges_scans_everything() {
+        let filter = BlockRangeFilter::default();
+
+        assert_eq!(filter.intersect(100, 200), vec![(100, 200)]);
+    }
+
+    #[test]
+    fn test_block_range_filter_intersect() {
+        let filter = BlockRangeFilter::new([10..=20, 50..=60]);
+
+        // Window entirely within a gap between active ranges
+        assert!(filter.intersect(25, 45).is_empty());
+
+        // Window overlapping both active ranges
+        assert_eq!(filter.intersect(15, 55), vec![(15, 20), (50, 55)]);
+
+        // Window extending past the known history
+        assert_eq!(filter.intersect(55, 100), vec![(55, 60), (61, 100)]);
+
+        // Window entirely past the known history
+        assert_eq!(filter.intersect(70, 80), vec![(70, 80)]);
+    }
 }
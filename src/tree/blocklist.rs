@@ -0,0 +1,174 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use eyre::WrapErr;
+use ipnet::IpNet;
+use tokio::task::JoinHandle;
+
+/// Client IP ranges denied access to the API, loaded from a file with one IP address or CIDR range per line.
+/// Empty lines and lines starting with `#` are ignored.
+#[derive(Debug)]
+pub struct Blocklist {
+    path: PathBuf,
+    ranges: RwLock<Vec<IpNet>>,
+}
+
+impl Blocklist {
+    pub fn load(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let ranges = read_blocklist(&path)?;
+
+        tracing::info!(?path, entries = ranges.len(), "Blocklist loaded");
+
+        Ok(Self {
+            path,
+            ranges: RwLock::new(ranges),
+        })
+    }
+
+    /// Reads the blocklist file again, keeping the current entries if it can't be read or parsed
+    pub fn reload(&self) -> eyre::Result<()> {
+        let ranges = read_blocklist(&self.path)?;
+
+        let mut current = self.ranges.write().unwrap();
+        tracing::info!(
+            path = ?self.path,
+            before = current.len(),
+            after = ranges.len(),
+            "Blocklist reloaded"
+        );
+        *current = ranges;
+
+        Ok(())
+    }
+
+    pub fn is_blocked(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+
+        self.ranges
+            .read()
+            .unwrap()
+            .iter()
+            .any(|range| range.contains(&ip))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Spawns a task reloading the blocklist every time the process receives SIGHUP
+    #[cfg(unix)]
+    pub fn spawn_reload_on_sighup(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut sighup = match tokio::signal::unix::signal(
+                tokio::signal::unix::SignalKind::hangup(),
+            ) {
+                Ok(sighup) => sighup,
+                Err(error) => {
+                    tracing::error!(
+                        ?error,
+                        "Failed to install SIGHUP handler, blocklist reloading is disabled"
+                    );
+                    return;
+                }
+            };
+
+            while sighup.recv().await.is_some() {
+                if let Err(error) = self.reload() {
+                    tracing::error!(
+                        ?error,
+                        path = ?self.path,
+                        "Failed to reload blocklist, keeping the current entries"
+                    );
+                }
+            }
+        })
+    }
+}
+
+fn read_blocklist(path: &Path) -> eyre::Result<Vec<IpNet>> {
+    let contents = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read blocklist {path:?}"))?;
+
+    parse_blocklist(&contents)
+}
+
+/// Parses one IP address or CIDR range per line, single addresses are treated as ranges of one address
+pub fn parse_blocklist(contents: &str) -> eyre::Result<Vec<IpNet>> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            line.parse::<IpNet>()
+                .or_else(|_| line.parse::<IpAddr>().map(IpNet::from))
+                .wrap_err_with(|| {
+                    format!(
+                        "Invalid blocklist entry `{line}` on line {}",
+                        index + 1
+                    )
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_blocklist() -> eyre::Result<()> {
+        let ranges = parse_blocklist(
+            "# Known abusers\n203.0.113.0/24\n\n  198.51.100.7  \n2001:db8::/32\n",
+        )?;
+
+        assert_eq!(
+            ranges,
+            vec![
+                "203.0.113.0/24".parse::<IpNet>()?,
+                "198.51.100.7/32".parse()?,
+                "2001:db8::/32".parse()?,
+            ]
+        );
+
+        assert!(parse_blocklist("203.0.113.0/33").is_err());
+        assert!(parse_blocklist("not an ip").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload() -> eyre::Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("world-tree-blocklist-{}", std::process::id()));
+        std::fs::write(&path, "203.0.113.0/24\n")?;
+
+        let blocklist = Blocklist::load(&path)?;
+        assert!(blocklist.is_blocked("203.0.113.42".parse()?));
+        // IPv4 addresses mapped to IPv6 match IPv4 ranges
+        assert!(blocklist.is_blocked("::ffff:203.0.113.42".parse()?));
+        assert!(!blocklist.is_blocked("198.51.100.7".parse()?));
+
+        std::fs::write(&path, "198.51.100.7\n2001:db8::/32\n")?;
+        blocklist.reload()?;
+        assert_eq!(blocklist.len(), 2);
+        assert!(!blocklist.is_blocked("203.0.113.42".parse()?));
+        assert!(blocklist.is_blocked("198.51.100.7".parse()?));
+        assert!(blocklist.is_blocked("2001:db8::1".parse()?));
+
+        // An invalid file keeps the current entries
+        std::fs::write(&path, "invalid\n")?;
+        assert!(blocklist.reload().is_err());
+        assert_eq!(blocklist.len(), 2);
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// Number of recent insertion batches used to estimate the batch cadence
+pub const BATCH_HISTORY_SIZE: usize = 32;
+
+/// Tracks when recent insertion batches were received to estimate when the next batch will land
+#[derive(Debug, Default)]
+pub struct BatchCadence {
+    /// Unix timestamp in seconds at which each batch was received and its number of identities, oldest first
+    batches: Mutex<VecDeque<(u64, usize)>>,
+}
+
+impl BatchCadence {
+    /// Records a batch of `batch_size` identities received at `timestamp`, evicting the oldest batch once full
+    pub fn record(&self, timestamp: u64, batch_size: usize) {
+        let mut batches = self.batches.lock().unwrap();
+
+        if batches.len() == BATCH_HISTORY_SIZE {
+            batches.pop_front();
+        }
+        batches.push_back((timestamp, batch_size));
+    }
+
+    /// Records a batch received now
+    pub fn record_now(&self, batch_size: usize) {
+        self.record(unix_timestamp(), batch_size);
+    }
+
+    /// Estimates the cadence of batches as of `now`, based only on the batches observed by this instance
+    pub fn estimate(&self, now: u64) -> InclusionEta {
+        let batches = self.batches.lock().unwrap();
+
+        let last_batch = batches.back().map(|(timestamp, _)| *timestamp);
+        let seconds_since_last_batch =
+            last_batch.map(|timestamp| now.saturating_sub(timestamp));
+
+        let average_batch_interval = match (batches.front(), last_batch) {
+            (Some((first_batch, _)), Some(last_batch)) if batches.len() > 1 => {
+                // The wall clock can step backwards between batches
+                Some(
+                    last_batch.saturating_sub(*first_batch)
+                        / (batches.len() as u64 - 1),
+                )
+            }
+            _ => None,
+        };
+
+        let average_batch_size = (!batches.is_empty()).then(|| {
+            batches.iter().map(|(_, size)| size).sum::<usize>() / batches.len()
+        });
+
+        // Once a batch is overdue, the next one is expected at any moment
+        let estimated_seconds_until_next_batch = average_batch_interval
+            .zip(seconds_since_last_batch)
+            .map(|(interval, elapsed)| interval.saturating_sub(elapsed));
+
+        InclusionEta {
+            average_batch_interval,
+            average_batch_size,
+            seconds_since_last_batch,
+            estimated_seconds_until_next_batch,
+        }
+    }
+}
+
+/// Naive estimate of when the next batch of identities will be inserted, derived from the observed batch cadence.
+/// Fields are `None` until enough batches have been observed since the service started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InclusionEta {
+    /// Average number of seconds between recent batches
+    pub average_batch_interval: Option<u64>,
+    /// Average number of identities inserted by recent batches
+    pub average_batch_size: Option<usize>,
+    pub seconds_since_last_batch: Option<u64>,
+    /// Estimate only, batches are submitted by the sequencer and are not guaranteed to follow the observed cadence
+    pub estimated_seconds_until_next_batch: Option<u64>,
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_without_batches() {
+        let cadence = BatchCadence::default();
+
+        assert_eq!(
+            cadence.estimate(1000),
+            InclusionEta {
+                average_batch_interval: None,
+                average_batch_size: None,
+                seconds_since_last_batch: None,
+                estimated_seconds_until_next_batch: None,
+            }
+        );
+
+        // A single batch gives no interval to estimate from
+        cadence.record(900, 10);
+        let eta = cadence.estimate(1000);
+        assert_eq!(eta.seconds_since_last_batch, Some(100));
+        assert_eq!(eta.estimated_seconds_until_next_batch, None);
+    }
+
+    #[test]
+    fn test_estimate() {
+        let cadence = BatchCadence::default();
+        cadence.record(1000, 10);
+        cadence.record(1300, 20);
+        cadence.record(1600, 30);
+
+        assert_eq!(
+            cadence.estimate(1700),
+            InclusionEta {
+                average_batch_interval: Some(300),
+                average_batch_size: Some(20),
+                seconds_since_last_batch: Some(100),
+                estimated_seconds_until_next_batch: Some(200),
+            }
+        );
+
+        // Overdue batches are expected immediately
+        assert_eq!(
+            cadence.estimate(2000).estimated_seconds_until_next_batch,
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_estimate_with_clock_going_backwards() {
+        let cadence = BatchCadence::default();
+        cadence.record(1000, 10);
+        cadence.record(900, 10);
+
+        let eta = cadence.estimate(950);
+        assert_eq!(eta.average_batch_interval, Some(0));
+        assert_eq!(eta.seconds_since_last_batch, Some(50));
+    }
+
+    #[test]
+    fn test_evicts_oldest_batch() {
+        let cadence = BatchCadence::default();
+
+        // The first batch is far apart from the others and is evicted
+        cadence.record(0, 1);
+        for i in 0..BATCH_HISTORY_SIZE as u64 {
+            cadence.record(10_000 + i * 60, 1);
+        }
+
+        let eta = cadence.estimate(10_000 + BATCH_HISTORY_SIZE as u64 * 60);
+        assert_eq!(eta.average_batch_interval, Some(60));
+        assert_eq!(eta.estimated_seconds_until_next_batch, Some(0));
+    }
+}
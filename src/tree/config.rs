@@ -1,12 +1,18 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::{Path, PathBuf};
 
 use ethers::types::Address;
+use eyre::WrapErr;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+use super::block_scanner::BlockRangeFilter;
+
 pub const CONFIG_PREFIX: &str = "WLD";
 
+/// Separator between the segments of nested keys in environment variables and config directory file names
+pub const KEY_SEPARATOR: &str = "__";
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ServiceConfig {
     pub tree_depth: usize,
@@ -33,7 +39,12 @@ pub struct CacheConfig {
 }
 
 impl ServiceConfig {
-    pub fn load(config_path: Option<&Path>) -> eyre::Result<Self> {
+    /// Loads the configuration from, in increasing order of priority, the TOML file at `config_path`, the key files in
+    /// `config_dir` and environment variables prefixed with `WLD__`
+    pub fn load(
+        config_path: Option<&Path>,
+        config_dir: Option<&Path>,
+    ) -> eyre::Result<Self> {
         let mut settings = config::Config::builder();
 
         if let Some(path) = config_path {
@@ -41,18 +52,111 @@ impl ServiceConfig {
                 settings.add_source(config::File::from(path).required(true));
         }
 
+        if let Some(dir) = config_dir {
+            settings = settings.add_source(
+                config::Environment::default()
+                    .source(Some(read_config_dir(dir)?))
+                    .separator(KEY_SEPARATOR)
+                    .try_parsing(true),
+            );
+        }
+
         let settings = settings
             .add_source(
                 config::Environment::with_prefix(CONFIG_PREFIX)
-                    .separator("__")
+                    .separator(KEY_SEPARATOR)
                     .try_parsing(true),
             )
             .build()?;
 
         let config = settings.try_deserialize::<Self>()?;
+        config.validate()?;
 
         Ok(config)
     }
+
+    /// Checks the values that deserialize but can't be used, e.g. block ranges ending before they start
+    pub fn validate(&self) -> eyre::Result<()> {
+        for tree in
+            std::iter::once(&self.canonical_tree).chain(&self.bridged_trees)
+        {
+            for range in &tree.active_block_ranges {
+                eyre::ensure!(
+                    range.start <= range.end,
+                    "Active block range {}..={} of the tree at {:?} ends before it starts",
+                    range.start,
+                    range.end,
+                    tree.address
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the socket address the service should bind to.
+    ///
+    /// If `port` is provided (e.g. via the conventional `PORT` environment variable) it takes precedence over the
+    /// configured port. When running inside a container, a loopback host would make the service unreachable from
+    /// outside the container, so it is replaced with the unspecified address of the same IP version.
+    pub fn resolve_socket_address(
+        &self,
+        port: Option<u16>,
+        in_container: bool,
+    ) -> SocketAddr {
+        let mut socket_address = self.socket_address;
+
+        if let Some(port) = port {
+            socket_address.set_port(port);
+        }
+
+        if in_container && socket_address.ip().is_loopback() {
+            let unspecified = match socket_address.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            };
+
+            tracing::warn!(
+                configured = ?socket_address.ip(),
+                ?unspecified,
+                "Loopback address configured inside a container, binding to the unspecified address instead"
+            );
+
+            socket_address.set_ip(unspecified);
+        }
+
+        socket_address
+    }
+}
+
+/// Reads a directory with one file per configuration key, as mounted from a Kubernetes ConfigMap or Secret.
+/// Nested keys are separated by `__` like environment variables, e.g. `canonical_tree__provider__rpc_endpoint`.
+/// Hidden entries are skipped since Kubernetes mounts keys as symlinks into hidden directories.
+fn read_config_dir(dir: &Path) -> eyre::Result<config::Map<String, String>> {
+    let mut values = config::Map::new();
+
+    for entry in std::fs::read_dir(dir)
+        .wrap_err_with(|| format!("Failed to read config directory {dir:?}"))?
+    {
+        let entry = entry?;
+        let key = entry.file_name().to_string_lossy().into_owned();
+
+        if key.starts_with('.') || !std::fs::metadata(entry.path())?.is_file() {
+            continue;
+        }
+
+        let value = std::fs::read_to_string(entry.path())
+            .wrap_err_with(|| format!("Failed to read config key {key}"))?;
+        values.insert(key, value.trim().to_owned());
+    }
+
+    Ok(values)
+}
+
+/// Returns true if the process appears to be running inside a Docker container or Kubernetes pod
+pub fn running_in_container() -> bool {
+    std::env::var_os("KUBERNETES_SERVICE_HOST").is_some()
+        || Path::new("/.dockerenv").exists()
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -62,7 +166,30 @@ pub struct TreeConfig {
     pub window_size: u64,
     #[serde(default)]
     pub creation_block: u64,
+    /// Chain ID the provider is expected to report, the service refuses to start if it reports a different one
+    #[serde(default)]
+    pub chain_id: Option<u64>,
     pub provider: ProviderConfig,
+    /// Block ranges known to contain events. If set, only these ranges and blocks after the last range are scanned
+    #[serde(default)]
+    pub active_block_ranges: Vec<BlockRange>,
+}
+
+impl TreeConfig {
+    pub fn block_range_filter(&self) -> BlockRangeFilter {
+        BlockRangeFilter::new(
+            self.active_block_ranges
+                .iter()
+                .map(|range| range.start..=range.end),
+        )
+    }
+}
+
+/// Inclusive range of blocks
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct BlockRange {
+    pub start: u64,
+    pub end: u64,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -125,3 +252,121 @@ mod map_vec {
         Ok(v.into_values().collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with_socket_address(socket_address: &str) -> ServiceConfig {
+        let config = format!(
+            r#"
+            tree_depth = 30
+            socket_address = "{socket_address}"
+
+            [cache]
+            cache_file = "tree-cache"
+
+            [canonical_tree]
+            address = "0x0000000000000000000000000000000000000000"
+            provider.rpc_endpoint = "http://localhost:8545"
+            "#
+        );
+
+        toml::from_str(&config).expect("Invalid test config")
+    }
+
+    #[test]
+    fn test_load_config_dir() -> eyre::Result<()> {
+        let dir = std::env::temp_dir()
+            .join(format!("world-tree-config-dir-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("..data"))?;
+
+        for (key, value) in [
+            ("tree_depth", "20\n"),
+            ("cache__cache_file", "tree-cache"),
+            (
+                "canonical_tree__address",
+                "0x0000000000000000000000000000000000000001",
+            ),
+            ("canonical_tree__window_size", "500"),
+            (
+                "canonical_tree__provider__rpc_endpoint",
+                "http://localhost:8545",
+            ),
+            ("..data/tree_depth", "30"),
+        ] {
+            std::fs::write(dir.join(key), value)?;
+        }
+
+        let config = ServiceConfig::load(None, Some(&dir));
+        std::fs::remove_dir_all(&dir)?;
+        let config = config?;
+
+        assert_eq!(config.tree_depth, 20);
+        assert_eq!(config.cache.cache_file, PathBuf::from("tree-cache"));
+        assert_eq!(config.canonical_tree.address, Address::from_low_u64_be(1));
+        assert_eq!(config.canonical_tree.window_size, 500);
+        assert_eq!(
+            config.canonical_tree.provider.rpc_endpoint.as_str(),
+            "http://localhost:8545/"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_inverted_block_range() {
+        let mut config = config_with_socket_address("127.0.0.1:8080");
+        config.canonical_tree.active_block_ranges =
+            vec![BlockRange { start: 10, end: 20 }];
+        assert!(config.validate().is_ok());
+
+        config
+            .canonical_tree
+            .active_block_ranges
+            .push(BlockRange { start: 30, end: 25 });
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_resolve_socket_address_uses_configured_address() {
+        let config = config_with_socket_address("127.0.0.1:8080");
+
+        assert_eq!(
+            config.resolve_socket_address(None, false),
+            "127.0.0.1:8080".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resolve_socket_address_port_override() {
+        let config = config_with_socket_address("0.0.0.0:8080");
+
+        assert_eq!(
+            config.resolve_socket_address(Some(3000), false),
+            "0.0.0.0:3000".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resolve_socket_address_in_container() {
+        let config = config_with_socket_address("127.0.0.1:8080");
+        assert_eq!(
+            config.resolve_socket_address(Some(3000), true),
+            "0.0.0.0:3000".parse().unwrap()
+        );
+
+        let config = config_with_socket_address("[::1]:8080");
+        assert_eq!(
+            config.resolve_socket_address(None, true),
+            "[::]:8080".parse().unwrap()
+        );
+
+        // Non-loopback addresses are left untouched
+        let config = config_with_socket_address("10.0.0.1:8080");
+        assert_eq!(
+            config.resolve_socket_address(None, true),
+            "10.0.0.1:8080".parse().unwrap()
+        );
+    }
+}
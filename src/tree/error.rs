@@ -1,9 +1,133 @@
+use std::time::Duration;
+
 use axum::response::IntoResponse;
 use ethers::prelude::{AbiError, ContractError};
 use ethers::providers::Middleware;
+use ethers::types::{Log, H160, H256};
+use hyper::header::{HeaderName, HeaderValue, RETRY_AFTER};
 use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use super::redact::LoggableIdentity;
+use super::status::ServiceStatus;
+use super::{ChainId, Hash};
+
+/// Header carrying the [`ErrorCode`] of an error response
+pub const ERROR_CODE_HEADER: &str = "x-error-code";
+
+/// Declares [`ErrorCode`] along with [`ErrorCode::ALL`], so that the catalog can't miss a variant
+macro_rules! error_codes {
+    ($(#[$meta:meta])* pub enum $name:ident { $($variant:ident),* $(,)? }) => {
+        $(#[$meta])*
+        pub enum $name {
+            $($variant),*
+        }
+
+        impl $name {
+            pub const ALL: &'static [$name] = &[$($name::$variant),*];
+        }
+    };
+}
+
+error_codes! {
+    /// Every error the API can respond with, served by `/errors` so that clients can map codes to their own messages.
+    /// Codes are stable, new errors get new codes.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ErrorCode {
+        ServiceUnavailable,
+        InvalidLeafIndex,
+        InvalidLeafRange,
+        LeafNotEmpty,
+        LeafAfterCutoff,
+        Internal,
+        InvalidRequest,
+        Forbidden,
+        MisdirectedRequest,
+    }
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::ServiceUnavailable => "service_unavailable",
+            ErrorCode::InvalidLeafIndex => "invalid_leaf_index",
+            ErrorCode::InvalidLeafRange => "invalid_leaf_range",
+            ErrorCode::LeafNotEmpty => "leaf_not_empty",
+            ErrorCode::LeafAfterCutoff => "leaf_after_cutoff",
+            ErrorCode::Internal => "internal_error",
+            ErrorCode::InvalidRequest => "invalid_request",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::MisdirectedRequest => "misdirected_request",
+        }
+    }
+
+    pub fn http_status(&self) -> StatusCode {
+        match self {
+            ErrorCode::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::InvalidLeafIndex
+            | ErrorCode::InvalidLeafRange
+            | ErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::LeafNotEmpty => StatusCode::CONFLICT,
+            ErrorCode::LeafAfterCutoff => StatusCode::NOT_FOUND,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::MisdirectedRequest => StatusCode::MISDIRECTED_REQUEST,
+        }
+    }
+
+    pub fn docs(&self) -> &'static str {
+        match self {
+            ErrorCode::ServiceUnavailable => "The service is syncing, shutting down or unhealthy. Retry after the number of seconds in the Retry-After header, if any.",
+            ErrorCode::InvalidLeafIndex => "The requested leaf index is out of bounds for the depth of the tree.",
+            ErrorCode::InvalidLeafRange => "The requested range of leaves extends past the capacity of the tree.",
+            ErrorCode::LeafNotEmpty => "The leaf whose emptiness was requested holds an identity.",
+            ErrorCode::LeafAfterCutoff => "The identity was inserted at an index above the requested maxLeafIndex.",
+            ErrorCode::Internal => "Unexpected failure of the service, the message gives details.",
+            ErrorCode::InvalidRequest => "The query string or body of the request is missing, malformed or too large, the message gives details.",
+            ErrorCode::Forbidden => "The client IP is blocklisted.",
+            ErrorCode::MisdirectedRequest => "The Host header of the request is not one the service is configured to serve.",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorCatalogEntry {
+    pub code: String,
+    pub http_status: u16,
+    pub docs: String,
+}
+
+impl From<ErrorCode> for ErrorCatalogEntry {
+    fn from(error_code: ErrorCode) -> Self {
+        Self {
+            code: error_code.code().to_owned(),
+            http_status: error_code.http_status().as_u16(),
+            docs: error_code.docs().to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorCatalog {
+    pub errors: Vec<ErrorCatalogEntry>,
+}
+
+impl ErrorCatalog {
+    pub fn new() -> Self {
+        Self {
+            errors: ErrorCode::ALL.iter().copied().map(Into::into).collect(),
+        }
+    }
+}
+
+impl Default for ErrorCatalog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum WorldTreeError<M>
 where
@@ -17,16 +141,70 @@ where
     LeafChannelClosed,
     #[error("Bridged root channel closed")]
     BridgedRootChannelClosed,
-    #[error("Chain ID not found")]
-    ChainIdNotFound,
-    #[error("Tree not synced")]
-    TreeNotSynced,
-    #[error("Transaction hash not found")]
-    TransactionHashNotFound,
-    #[error("Transaction found")]
-    TransactionNotFound,
-    #[error("Calldata does not have a function selector")]
-    MissingFunctionSelector,
+    #[error("Chain ID {chain_id} not found")]
+    ChainIdNotFound { chain_id: ChainId },
+    #[error("Service unavailable while {0}")]
+    ServiceUnavailable(ServiceStatus),
+    #[error(
+        "Transaction hash not found for log {:?} at block {:?}",
+        .log.log_index,
+        .log.block_number
+    )]
+    TransactionHashNotFound { log: Box<Log> },
+    #[error("Transaction {tx_hash:?} not found")]
+    TransactionNotFound { tx_hash: H256 },
+    #[error(
+        "Calldata of transaction {tx_hash:?} does not have a function selector"
+    )]
+    MissingFunctionSelector { tx_hash: H256 },
+    #[error("Failed to decode calldata of transaction {tx_hash:?}")]
+    CalldataDecodeError {
+        tx_hash: H256,
+        #[source]
+        source: AbiError,
+    },
+    #[error(
+        "Failed to decode log {:?} in transaction {:?} at block {:?}",
+        .log.log_index,
+        .log.transaction_hash,
+        .log.block_number
+    )]
+    AbiDecodeError {
+        log: Box<Log>,
+        #[source]
+        source: ethers::abi::Error,
+    },
+    #[error("Tree depth {actual} does not match configured depth {expected}")]
+    TreeDepthMismatch { expected: usize, actual: usize },
+    #[error("Provider for {address:?} reports chain ID {actual}, expected {expected}")]
+    ChainIdMismatch {
+        address: H160,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("Creation block {0} does not exist on the connected chain")]
+    BlockNotFound(u64),
+    #[error(
+        "Canonical root {local:#x} does not match onchain root {onchain:#x} at block {block}"
+    )]
+    RootMismatch {
+        block: u64,
+        onchain: Hash,
+        local: Hash,
+    },
+    #[error("View call {function} on {address:?} failed")]
+    ViewCallFailed {
+        function: &'static str,
+        address: H160,
+        #[source]
+        source: ContractError<M>,
+    },
+    #[error("View call {function} on {address:?} timed out after {timeout:?}")]
+    ViewCallTimeout {
+        function: &'static str,
+        address: H160,
+        timeout: Duration,
+    },
     #[error(transparent)]
     IdentityTreeError(#[from] IdentityTreeError),
     #[error(transparent)]
@@ -34,19 +212,39 @@ where
     #[error(transparent)]
     ContractError(#[from] ContractError<M>),
     #[error(transparent)]
-    ABICodecError(#[from] AbiError),
-    #[error(transparent)]
-    EthABIError(#[from] ethers::abi::Error),
-    #[error(transparent)]
     HyperError(#[from] hyper::Error),
 }
 
 #[derive(Error, Debug)]
 pub enum IdentityTreeError {
-    #[error("Root not found")]
-    RootNotFound,
-    #[error("Leaf already exists")]
-    LeafAlreadyExists,
+    #[error("Root {root:#x} not found")]
+    RootNotFound { root: Hash },
+    #[error("Leaf {} already exists at index {index}", LoggableIdentity(*.leaf))]
+    LeafAlreadyExists { leaf: Hash, index: u32 },
+    #[error(
+        "Leaf index {index} is out of bounds for a tree of {capacity} leaves"
+    )]
+    InvalidLeafIndex { index: usize, capacity: usize },
+    #[error(
+        "Leaf range of {count} leaves starting at {start} is out of bounds for a tree of {capacity} leaves"
+    )]
+    InvalidLeafRange {
+        start: usize,
+        count: usize,
+        capacity: usize,
+    },
+    #[error("Trees of depth {left} and {right} can't be compared")]
+    DepthMismatch { left: usize, right: usize },
+    #[error("Batch of {size} leaves exceeds the maximum batch size of {max}")]
+    BatchTooLarge { size: usize, max: usize },
+    #[error(
+        "Leaf was inserted at index {index}, after the requested maximum leaf index {max_leaf_index}"
+    )]
+    LeafAfterCutoff { index: u32, max_leaf_index: u32 },
+    #[error("Leaf {index} is not empty, found {}", LoggableIdentity(*.leaf))]
+    LeafNotEmpty { index: u32, leaf: Hash },
+    #[error("Proof for leaf {index} does not verify against root {root:#x}")]
+    ProofVerificationFailed { root: Hash, index: u32 },
     #[error(transparent)]
     MmapVecError(#[from] eyre::Report),
     #[error(transparent)]
@@ -57,8 +255,50 @@ impl<M> WorldTreeError<M>
 where
     M: Middleware + 'static,
 {
-    fn to_status_code(&self) -> StatusCode {
-        StatusCode::INTERNAL_SERVER_ERROR
+    /// Returns true if the error is caused by the service configuration and will persist until it is changed
+    pub fn is_config_error(&self) -> bool {
+        matches!(
+            self,
+            WorldTreeError::TreeDepthMismatch { .. }
+                | WorldTreeError::ChainIdMismatch { .. }
+                | WorldTreeError::BlockNotFound(_)
+        )
+    }
+
+    /// Code of the error in the catalog served by `/errors`, which determines the status of the response
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            WorldTreeError::ServiceUnavailable(_) => {
+                ErrorCode::ServiceUnavailable
+            }
+            WorldTreeError::IdentityTreeError(
+                IdentityTreeError::InvalidLeafIndex { .. },
+            ) => ErrorCode::InvalidLeafIndex,
+            WorldTreeError::IdentityTreeError(
+                IdentityTreeError::InvalidLeafRange { .. },
+            ) => ErrorCode::InvalidLeafRange,
+            WorldTreeError::IdentityTreeError(
+                IdentityTreeError::LeafNotEmpty { .. },
+            ) => ErrorCode::LeafNotEmpty,
+            WorldTreeError::IdentityTreeError(
+                IdentityTreeError::LeafAfterCutoff { .. },
+            ) => ErrorCode::LeafAfterCutoff,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+/// Responds with the status and code only, for rejections that happen before a handler runs
+impl IntoResponse for ErrorCode {
+    fn into_response(self) -> axum::response::Response {
+        (
+            self.http_status(),
+            [(
+                HeaderName::from_static(ERROR_CODE_HEADER),
+                HeaderValue::from_static(self.code()),
+            )],
+        )
+            .into_response()
     }
 }
 
@@ -67,8 +307,122 @@ where
     M: Middleware + 'static,
 {
     fn into_response(self) -> axum::response::Response {
-        let status_code = self.to_status_code();
+        let error_code = self.error_code();
         let response_body = self.to_string();
-        (status_code, response_body).into_response()
+        let mut response =
+            (error_code.http_status(), response_body).into_response();
+        response.headers_mut().insert(
+            HeaderName::from_static(ERROR_CODE_HEADER),
+            HeaderValue::from_static(error_code.code()),
+        );
+
+        if let WorldTreeError::ServiceUnavailable(status) = &self {
+            if let Some(retry_after) = status.retry_after() {
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, retry_after.as_secs().into());
+            }
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use ethers::providers::{MockProvider, Provider};
+
+    use super::*;
+    use crate::tree::status::SYNCING_RETRY_AFTER;
+
+    type TestError = WorldTreeError<Provider<MockProvider>>;
+
+    #[test]
+    fn test_service_unavailable_response() {
+        let response = TestError::ServiceUnavailable(ServiceStatus::Syncing)
+            .into_response();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers().get(RETRY_AFTER),
+            Some(&HeaderValue::from(SYNCING_RETRY_AFTER.as_secs()))
+        );
+
+        // No recovery estimate is given when the service is unhealthy
+        let response = TestError::ServiceUnavailable(ServiceStatus::Unhealthy)
+            .into_response();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().get(RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn test_empty_leaf_proof_error_responses() {
+        let response = TestError::from(IdentityTreeError::LeafNotEmpty {
+            index: 0,
+            leaf: Hash::from(1),
+        })
+        .into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            response.headers().get(ERROR_CODE_HEADER),
+            Some(&HeaderValue::from_static("leaf_not_empty"))
+        );
+
+        let response = TestError::from(IdentityTreeError::InvalidLeafIndex {
+            index: 4,
+            capacity: 4,
+        })
+        .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_is_config_error() {
+        assert!(TestError::ChainIdMismatch {
+            address: H160::zero(),
+            expected: 1,
+            actual: 11155111,
+        }
+        .is_config_error());
+        assert!(TestError::TreeDepthMismatch {
+            expected: 30,
+            actual: 20,
+        }
+        .is_config_error());
+        assert!(!TestError::LeafChannelClosed.is_config_error());
+    }
+
+    #[test]
+    fn test_internal_error_response() {
+        let response = TestError::LeafChannelClosed.into_response();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_error_codes_unique() {
+        let codes = ErrorCode::ALL
+            .iter()
+            .map(ErrorCode::code)
+            .collect::<HashSet<_>>();
+        assert_eq!(codes.len(), ErrorCode::ALL.len());
+
+        for &error_code in ErrorCode::ALL {
+            let response = error_code.into_response();
+            assert_eq!(response.status(), error_code.http_status());
+            assert_eq!(
+                response.headers().get(ERROR_CODE_HEADER),
+                Some(&HeaderValue::from_static(error_code.code()))
+            );
+
+            assert!(
+                error_code.http_status().is_client_error()
+                    || error_code.http_status().is_server_error()
+            );
+            assert!(HeaderValue::from_str(error_code.code()).is_ok());
+        }
     }
 }
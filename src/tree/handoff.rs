@@ -0,0 +1,358 @@
+//! Handoff of the tree from a running instance to a new instance taking over its port, so that the service can be
+//! restarted without a load balancer and without dropping requests while the new instance restores the tree:
+//!
+//! 1. The new instance, started with `--takeover <pid>`, waits for the pid file the running instance writes once it
+//!    handles SIGUSR2, then sends it SIGUSR2
+//! 2. The running instance copies its tree cache to the handoff path, then writes a checkpoint describing the copy
+//! 3. The new instance moves the snapshot to its cache file, syncs to head and binds the port with SO_REUSEPORT
+//! 4. Once serving, the new instance sends SIGTERM to the running instance, which stops accepting connections and
+//!    exits once its in-flight requests complete
+
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ethers::providers::Middleware;
+use eyre::{ContextCompat, WrapErr};
+#[cfg(unix)]
+use nix::sys::signal::{kill, Signal};
+#[cfg(unix)]
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::task::JoinHandle;
+
+use super::{Hash, WorldTree};
+
+/// Default time the new instance waits for the running instance to write the handoff snapshot
+pub const DEFAULT_HANDOFF_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Interval at which the new instance checks whether the handoff checkpoint was written
+const HANDOFF_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Describes the snapshot written by the running instance. The checkpoint is written once the snapshot is complete.
+/// No block is recorded: the snapshot only holds the tree bridged to every chain, so the new instance rescans the
+/// canonical chain from its creation block to recover the updates that are still pending.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HandoffCheckpoint {
+    /// Root of the tree in the snapshot
+    pub root: Hash,
+    /// Number of leaves in the snapshot
+    pub num_leaves: usize,
+}
+
+/// Path of the checkpoint describing the snapshot at `path`
+pub fn checkpoint_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".checkpoint")
+}
+
+/// Path of the file holding the ID of the process handing off its tree to `path`, written once it handles SIGUSR2
+pub fn pid_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".pid")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+/// Copies the tree cache to `path` and writes the checkpoint describing it. The tree is read-locked while it is copied
+/// so that the snapshot matches the checkpoint.
+pub async fn write_handoff<M: Middleware + 'static>(
+    world_tree: &WorldTree<M>,
+    path: &Path,
+) -> eyre::Result<HandoffCheckpoint> {
+    let cache_file = world_tree
+        .cache_file
+        .clone()
+        .context("The tree is not backed by a cache file")?;
+
+    let identity_tree = world_tree.identity_tree.clone().read_owned().await;
+    let checkpoint = HandoffCheckpoint {
+        root: identity_tree.tree.root(),
+        num_leaves: identity_tree.tree.num_leaves(),
+    };
+    let checkpoint_json = serde_json::to_vec(&checkpoint)?;
+
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || -> eyre::Result<()> {
+        // Both files are moved into place once complete so that the new instance never reads a partial file
+        let partial_snapshot = with_suffix(&path, ".partial");
+        std::fs::copy(&cache_file, &partial_snapshot).wrap_err_with(|| {
+            format!("Failed to copy {cache_file:?} to {partial_snapshot:?}")
+        })?;
+        std::fs::rename(&partial_snapshot, &path)?;
+        drop(identity_tree);
+
+        let checkpoint_file = checkpoint_path(&path);
+        let partial_checkpoint = with_suffix(&checkpoint_file, ".partial");
+        std::fs::write(&partial_checkpoint, checkpoint_json)?;
+        std::fs::rename(&partial_checkpoint, &checkpoint_file)?;
+
+        Ok(())
+    })
+    .await??;
+
+    Ok(checkpoint)
+}
+
+/// Spawns a task writing the handoff snapshot to `path` every time the process receives SIGUSR2
+#[cfg(unix)]
+pub fn spawn_handoff_on_sigusr2<M: Middleware + 'static>(
+    world_tree: Arc<WorldTree<M>>,
+    path: PathBuf,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut sigusr2 = match tokio::signal::unix::signal(
+            tokio::signal::unix::SignalKind::user_defined2(),
+        ) {
+            Ok(sigusr2) => sigusr2,
+            Err(error) => {
+                tracing::error!(
+                    ?error,
+                    "Failed to install SIGUSR2 handler, handoff is disabled"
+                );
+                return;
+            }
+        };
+
+        // Only announced once SIGUSR2 is handled, since SIGUSR2 terminates the process by default
+        let pid_file = pid_path(&path);
+        if let Err(error) =
+            std::fs::write(&pid_file, std::process::id().to_string())
+        {
+            tracing::error!(
+                ?error,
+                ?pid_file,
+                "Failed to write pid file, handoff is disabled"
+            );
+            return;
+        }
+
+        while sigusr2.recv().await.is_some() {
+            tracing::info!(?path, "Writing handoff snapshot");
+
+            match write_handoff(&world_tree, &path).await {
+                Ok(checkpoint) => {
+                    tracing::info!(?checkpoint, "Wrote handoff snapshot")
+                }
+                Err(error) => tracing::error!(
+                    ?error,
+                    ?path,
+                    "Failed to write handoff snapshot"
+                ),
+            }
+        }
+    })
+}
+
+/// Asks the instance running as `pid` to hand off its tree to `path` and moves the snapshot to `cache_file`.
+/// `path` must be on the same filesystem as `cache_file`.
+#[cfg(unix)]
+pub async fn request_handoff(
+    pid: u32,
+    path: &Path,
+    cache_file: &Path,
+    timeout: Duration,
+) -> eyre::Result<HandoffCheckpoint> {
+    let start = Instant::now();
+    wait_for_pid_file(pid, path, timeout).await?;
+
+    // A checkpoint left behind by an earlier handoff would be mistaken for the answer to this one
+    match std::fs::remove_file(checkpoint_path(path)) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+            return Err(error).wrap_err("Failed to remove stale checkpoint")
+        }
+        _ => {}
+    }
+
+    send_signal(pid, Signal::SIGUSR2)?;
+
+    wait_for_handoff(path, cache_file, timeout.saturating_sub(start.elapsed()))
+        .await
+        .wrap_err_with(|| format!("Process {pid} did not hand off its tree"))
+}
+
+/// Waits for the process `pid` to announce that it hands off its tree to `path`, so that it is never sent SIGUSR2
+/// without a handler
+pub async fn wait_for_pid_file(
+    pid: u32,
+    path: &Path,
+    timeout: Duration,
+) -> eyre::Result<()> {
+    let pid_file = pid_path(path);
+    let start = Instant::now();
+
+    loop {
+        match tokio::fs::read_to_string(&pid_file).await {
+            Ok(contents) if contents.trim() == pid.to_string() => return Ok(()),
+            Ok(_) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => {
+                return Err(error)
+                    .wrap_err_with(|| format!("Failed to read {pid_file:?}"))
+            }
+        }
+
+        eyre::ensure!(
+            start.elapsed() < timeout,
+            "Process {pid} did not enable handoff to {path:?} within {timeout:?}, is it running with --handoff-path?"
+        );
+        tokio::time::sleep(HANDOFF_POLL_INTERVAL).await;
+    }
+}
+
+/// Waits for the checkpoint of the snapshot at `path` and moves the snapshot to `cache_file`
+pub async fn wait_for_handoff(
+    path: &Path,
+    cache_file: &Path,
+    timeout: Duration,
+) -> eyre::Result<HandoffCheckpoint> {
+    let checkpoint_file = checkpoint_path(path);
+    let start = Instant::now();
+
+    while !checkpoint_file.exists() {
+        eyre::ensure!(
+            start.elapsed() < timeout,
+            "No handoff checkpoint at {checkpoint_file:?} after {timeout:?}"
+        );
+        tokio::time::sleep(HANDOFF_POLL_INTERVAL).await;
+    }
+
+    let checkpoint =
+        serde_json::from_slice(&tokio::fs::read(&checkpoint_file).await?)
+            .wrap_err("Invalid handoff checkpoint")?;
+
+    tokio::fs::rename(path, cache_file)
+        .await
+        .wrap_err_with(|| {
+            format!("Failed to move {path:?} to {cache_file:?}")
+        })?;
+    tokio::fs::remove_file(&checkpoint_file).await?;
+
+    Ok(checkpoint)
+}
+
+/// Checks that the tree restored from the handoff snapshot matches its checkpoint
+pub async fn verify_handoff<M: Middleware + 'static>(
+    world_tree: &WorldTree<M>,
+    checkpoint: &HandoffCheckpoint,
+) -> eyre::Result<()> {
+    let identity_tree = world_tree.identity_tree.read().await;
+    let root = identity_tree.tree.root();
+    let num_leaves = identity_tree.tree.num_leaves();
+
+    eyre::ensure!(
+        root == checkpoint.root && num_leaves == checkpoint.num_leaves,
+        "Restored tree with root {root:?} and {num_leaves} leaves does not match the handoff checkpoint {checkpoint:?}"
+    );
+
+    Ok(())
+}
+
+/// Tells the instance running as `pid` that the new instance is serving, so that it shuts down gracefully
+#[cfg(unix)]
+pub fn complete_takeover(pid: u32) -> eyre::Result<()> {
+    send_signal(pid, Signal::SIGTERM)
+}
+
+#[cfg(unix)]
+fn send_signal(pid: u32, signal: Signal) -> eyre::Result<()> {
+    let raw_pid = i32::try_from(pid)
+        .wrap_err_with(|| format!("Invalid process ID {pid}"))?;
+
+    kill(Pid::from_raw(raw_pid), signal)
+        .wrap_err_with(|| format!("Failed to send {signal:?} to process {pid}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("world-tree-handoff-{name}-{}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_handoff() -> eyre::Result<()> {
+        let path = temp_path("snapshot");
+        let cache_file = temp_path("cache");
+        let checkpoint = HandoffCheckpoint {
+            root: Hash::from(10),
+            num_leaves: 3,
+        };
+
+        let writer = tokio::spawn({
+            let path = path.clone();
+            let checkpoint_json = serde_json::to_vec(&checkpoint)?;
+            async move {
+                tokio::time::sleep(HANDOFF_POLL_INTERVAL * 2).await;
+                std::fs::write(&path, b"snapshot")?;
+                std::fs::write(checkpoint_path(&path), checkpoint_json)
+            }
+        });
+
+        let received =
+            wait_for_handoff(&path, &cache_file, DEFAULT_HANDOFF_TIMEOUT)
+                .await?;
+        writer.await??;
+
+        assert_eq!(received, checkpoint);
+        assert_eq!(std::fs::read(&cache_file)?, b"snapshot");
+        assert!(!path.exists());
+        assert!(!checkpoint_path(&path).exists());
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_pid_file() -> eyre::Result<()> {
+        let path = temp_path("pid");
+        let pid = std::process::id();
+
+        // Missing pid file
+        assert!(wait_for_pid_file(pid, &path, Duration::ZERO).await.is_err());
+
+        // Pid file written by another process
+        std::fs::write(pid_path(&path), (pid + 1).to_string())?;
+        assert!(wait_for_pid_file(pid, &path, Duration::ZERO).await.is_err());
+
+        std::fs::write(pid_path(&path), pid.to_string())?;
+        wait_for_pid_file(pid, &path, Duration::ZERO).await?;
+
+        std::fs::remove_file(pid_path(&path))?;
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_request_handoff_without_pid_file() {
+        // Would terminate the test process if SIGUSR2 were sent without a handler
+        let result = request_handoff(
+            std::process::id(),
+            &temp_path("unannounced"),
+            &temp_path("unused"),
+            Duration::ZERO,
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_handoff_timeout() {
+        let path = temp_path("missing");
+
+        let result =
+            wait_for_handoff(&path, &temp_path("unused"), Duration::ZERO).await;
+
+        assert!(result.is_err());
+    }
+}
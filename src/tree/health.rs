@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use ethers::providers::Middleware;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::status::ServiceStatus;
+use super::WorldTree;
+
+/// Maximum time a single component check may take before the component is reported as failing
+pub const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Maximum time to wait for a read lock on the tree. Requests contend for the same lock, so a slow lock means stalled requests.
+pub const TREE_LOCK_TIMEOUT: Duration = Duration::from_millis(100);
+/// Time the result of the RPC check is reused for, so that frequent probes don't each cost an RPC request
+pub const RPC_CHECK_TTL: Duration = Duration::from_secs(10);
+/// Free space required next to the tree cache to write the cache or a downloaded snapshot
+pub const MIN_FREE_DISK_SPACE: u64 = 1 << 30;
+
+/// Health of a single component, ordered from best to worst
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentStatus {
+    Ok,
+    /// The service keeps serving proofs, but they may be stale or it may fail soon
+    Degraded,
+    /// The service is unable to serve requests
+    Unhealthy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub components: BTreeMap<String, ComponentStatus>,
+    /// Worst status of all components
+    pub status: ComponentStatus,
+}
+
+impl HealthReport {
+    pub fn new(components: BTreeMap<String, ComponentStatus>) -> Self {
+        let status = components
+            .values()
+            .copied()
+            .max()
+            .unwrap_or(ComponentStatus::Ok);
+
+        Self { components, status }
+    }
+}
+
+/// Result of a check reused until it is older than `ttl`, so that frequent probes don't each repeat an expensive check.
+/// Concurrent callers wait for the check in flight instead of starting their own.
+#[derive(Debug)]
+pub struct CachedCheck<T> {
+    ttl: Duration,
+    last: Mutex<Option<(Instant, T)>>,
+}
+
+impl<T: Clone> CachedCheck<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Returns the last result if it is recent enough, otherwise runs `check` and caches its result
+    pub async fn get_or_run<F>(&self, check: impl FnOnce() -> F) -> T
+    where
+        F: Future<Output = T>,
+    {
+        let mut last = self.last.lock().await;
+
+        if let Some((checked_at, result)) = last.as_ref() {
+            if checked_at.elapsed() < self.ttl {
+                return result.clone();
+            }
+        }
+
+        let result = check().await;
+        *last = Some((Instant::now(), result.clone()));
+
+        result
+    }
+}
+
+/// Polls every component of the world tree concurrently, each with its own timeout. The result of the RPC check is
+/// reused from `rpc_check` while it is recent enough.
+pub async fn check_health<M: Middleware + 'static>(
+    world_tree: &WorldTree<M>,
+    rpc_check: &CachedCheck<ComponentStatus>,
+) -> HealthReport {
+    let (rpc, tree_lock, disk) = tokio::join!(
+        rpc_check.get_or_run(|| check_rpc(world_tree)),
+        check_tree_lock(world_tree),
+        async {
+            match &world_tree.cache_file {
+                Some(cache_file) => Some(check_disk(cache_file).await),
+                None => None,
+            }
+        },
+    );
+
+    let mut components = BTreeMap::from([
+        ("rpc".to_owned(), rpc),
+        ("tree_lock".to_owned(), tree_lock),
+        ("sync".to_owned(), check_sync(world_tree)),
+    ]);
+    if let Some(disk) = disk {
+        components.insert("disk".to_owned(), disk);
+    }
+
+    HealthReport::new(components)
+}
+
+/// The sync tasks don't restart once they exit, so the tree stays stale until the process is restarted
+fn check_sync<M: Middleware + 'static>(
+    world_tree: &WorldTree<M>,
+) -> ComponentStatus {
+    match world_tree.status.get() {
+        ServiceStatus::Unhealthy => {
+            tracing::warn!(component = "sync", "Health check failed");
+            ComponentStatus::Unhealthy
+        }
+        ServiceStatus::Syncing | ServiceStatus::Serving => ComponentStatus::Ok,
+    }
+}
+
+/// The tree keeps serving proofs for the last synced state while the provider is down
+async fn check_rpc<M: Middleware + 'static>(
+    world_tree: &WorldTree<M>,
+) -> ComponentStatus {
+    let middleware =
+        &world_tree.canonical_tree_manager.block_scanner.middleware;
+
+    check(
+        "rpc",
+        HEALTH_CHECK_TIMEOUT,
+        ComponentStatus::Degraded,
+        async {
+            middleware
+                .get_block_number()
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        },
+    )
+    .await
+}
+
+/// The tree is write-locked during the initial sync and while large batches are applied, during which requests stall but
+/// the process must not be restarted
+async fn check_tree_lock<M: Middleware + 'static>(
+    world_tree: &WorldTree<M>,
+) -> ComponentStatus {
+    check(
+        "tree_lock",
+        TREE_LOCK_TIMEOUT,
+        ComponentStatus::Degraded,
+        async {
+            drop(world_tree.identity_tree.read().await);
+            Ok(())
+        },
+    )
+    .await
+}
+
+async fn check_disk(cache_file: &Path) -> ComponentStatus {
+    let dir = match cache_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+        _ => PathBuf::from("."),
+    };
+
+    check(
+        "disk",
+        HEALTH_CHECK_TIMEOUT,
+        ComponentStatus::Degraded,
+        async {
+            let available =
+                tokio::task::spawn_blocking(move || fs2::available_space(dir))
+                    .await
+                    .map_err(|err| err.to_string())?
+                    .map_err(|err| err.to_string())?;
+
+            if available < MIN_FREE_DISK_SPACE {
+                return Err(format!("{available} bytes available"));
+            }
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+/// Runs `check` with a timeout, reporting `failure` if it errors or times out
+async fn check(
+    component: &str,
+    timeout: Duration,
+    failure: ComponentStatus,
+    check: impl Future<Output = Result<(), String>>,
+) -> ComponentStatus {
+    let err = match tokio::time::timeout(timeout, check).await {
+        Ok(Ok(())) => return ComponentStatus::Ok,
+        Ok(Err(err)) => err,
+        Err(_) => format!("timed out after {timeout:?}"),
+    };
+
+    tracing::warn!(component, %err, "Health check failed");
+    failure
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_is_worst_component() {
+        let report = HealthReport::new(BTreeMap::from([
+            ("rpc".to_owned(), ComponentStatus::Degraded),
+            ("tree_lock".to_owned(), ComponentStatus::Ok),
+        ]));
+        assert_eq!(report.status, ComponentStatus::Degraded);
+
+        let report = HealthReport::new(BTreeMap::from([
+            ("rpc".to_owned(), ComponentStatus::Degraded),
+            ("tree_lock".to_owned(), ComponentStatus::Unhealthy),
+        ]));
+        assert_eq!(report.status, ComponentStatus::Unhealthy);
+
+        assert_eq!(
+            HealthReport::new(BTreeMap::new()).status,
+            ComponentStatus::Ok
+        );
+    }
+
+    #[test]
+    fn test_report_format() -> eyre::Result<()> {
+        let report = HealthReport::new(BTreeMap::from([
+            ("disk".to_owned(), ComponentStatus::Ok),
+            ("rpc".to_owned(), ComponentStatus::Degraded),
+        ]));
+
+        assert_eq!(
+            serde_json::to_value(&report)?,
+            serde_json::json!({
+                "components": { "disk": "ok", "rpc": "degraded" },
+                "status": "degraded",
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cached_check() {
+        let runs = std::sync::atomic::AtomicUsize::new(0);
+        let run = || async {
+            runs.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1
+        };
+
+        let cached = CachedCheck::new(Duration::from_secs(60));
+        assert_eq!(cached.get_or_run(run).await, 1);
+        assert_eq!(cached.get_or_run(run).await, 1);
+
+        let expired = CachedCheck::new(Duration::ZERO);
+        assert_eq!(expired.get_or_run(run).await, 2);
+        assert_eq!(expired.get_or_run(run).await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_check_timeout() {
+        let status = check(
+            "test",
+            Duration::from_millis(10),
+            ComponentStatus::Unhealthy,
+            std::future::pending(),
+        )
+        .await;
+
+        assert_eq!(status, ComponentStatus::Unhealthy);
+    }
+}
@@ -1,14 +1,18 @@
 use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::path::PathBuf;
+use std::sync::Mutex;
 use std::time::Instant;
 
-use rayon::iter::{Either, IntoParallelIterator, ParallelIterator};
+use ethers::types::H160;
+use rand::seq::IteratorRandom;
+use rand::Rng;
 use semaphore::cascading_merkle_tree::CascadingMerkleTree;
 use semaphore::generic_storage::{GenericStorage, MmapVec};
 use semaphore::merkle_tree::{Branch, Hasher};
 use semaphore::poseidon_tree::{PoseidonHash, Proof};
 use semaphore::Field;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
 
 use super::error::IdentityTreeError;
 use super::{Hash, LeafIndex, NodeIndex};
@@ -18,12 +22,21 @@ pub type Leaves = HashMap<LeafIndex, Hash>;
 // Node index to hash, 0 indexed from the root
 pub type StorageUpdates = HashMap<NodeIndex, Hash>;
 
+/// Maximum number of leaves in a single update unless configured otherwise, far above the largest batch the identity
+/// manager accepts
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100_000;
+
+/// Number of leaves applied to the tree between yields to the runtime when applying large updates
+pub const APPLY_CHUNK_SIZE: usize = 1_000;
+
 pub struct IdentityTree<S> {
     pub tree: CascadingMerkleTree<PoseidonHash, S>,
     pub tree_updates: BTreeMap<Root, StorageUpdates>,
     // Hashmap of root hash to nonce
     pub roots: HashMap<Hash, usize>,
     pub leaves: HashMap<Hash, u32>,
+    /// Maximum number of leaves in a single update, larger updates are rejected as malformed before any work is done
+    pub max_batch_size: usize,
 }
 
 impl IdentityTree<Vec<Hash>> {
@@ -35,6 +48,7 @@ impl IdentityTree<Vec<Hash>> {
             tree_updates: BTreeMap::new(),
             roots: HashMap::new(),
             leaves: HashMap::new(),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
         }
     }
 }
@@ -73,7 +87,16 @@ impl IdentityTree<MmapVec<Hash>> {
             tree
         };
 
-        // If the tree has leaves, restore the leaves hashmap
+        Ok(Self::from_tree(tree))
+    }
+}
+
+impl<S> IdentityTree<S>
+where
+    S: GenericStorage<Hash>,
+{
+    /// Adopts an existing tree as the canonical tree, restoring the leaves hashmap from its non-zero leaves
+    pub fn from_tree(tree: CascadingMerkleTree<PoseidonHash, S>) -> Self {
         let leaves = tree
             .leaves()
             .enumerate()
@@ -86,19 +109,20 @@ impl IdentityTree<MmapVec<Hash>> {
             })
             .collect::<HashMap<Hash, u32>>();
 
-        Ok(Self {
+        Self {
             tree,
             leaves,
             tree_updates: BTreeMap::new(),
             roots: HashMap::new(),
-        })
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+        }
+    }
+
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size;
+        self
     }
-}
 
-impl<S> IdentityTree<S>
-where
-    S: GenericStorage<Hash>,
-{
     /// Inserts a new leaf into the tree and updates the leaves hashmap
     /// Returns an error if the leaf already exists
     pub fn insert(
@@ -107,8 +131,11 @@ where
         leaf: Hash,
     ) -> Result<(), IdentityTreeError> {
         // Check if the leaf already exists
-        if self.leaves.contains_key(&leaf) {
-            return Err(IdentityTreeError::LeafAlreadyExists);
+        if let Some(existing_index) = self.leaves.get(&leaf) {
+            return Err(IdentityTreeError::LeafAlreadyExists {
+                leaf,
+                index: *existing_index,
+            });
         }
         self.leaves.insert(leaf, index);
 
@@ -133,6 +160,85 @@ where
         self.tree.extend_from_slice(&leaves);
     }
 
+    /// Checks that the leaves below the next leaf index among `leaves`, i.e. indices recycled after a deletion, are
+    /// empty, so that inserting them does not overwrite an identity
+    fn check_recycled_leaves(
+        &self,
+        leaves: &[(u32, Hash)],
+    ) -> Result<(), IdentityTreeError> {
+        let next_leaf_index = self.tree.num_leaves();
+
+        for (idx, _) in leaves
+            .iter()
+            .take_while(|(idx, _)| (*idx as usize) < next_leaf_index)
+        {
+            let leaf = self.tree.get_leaf(*idx as usize);
+            if leaf != Hash::ZERO {
+                return Err(IdentityTreeError::LeafNotEmpty {
+                    index: *idx,
+                    leaf,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Inserts leaves sorted by index, overwriting leaves below the next leaf index (i.e. indices recycled after a
+    /// deletion) and appending the others. Recycled leaves must have been checked with
+    /// [`Self::check_recycled_leaves`].
+    fn insert_leaves(&mut self, leaves: &[(u32, Hash)]) {
+        let next_leaf_index = self.tree.num_leaves();
+        let (recycled, appended) = leaves.split_at(
+            leaves
+                .partition_point(|(idx, _)| (*idx as usize) < next_leaf_index),
+        );
+
+        for (idx, leaf) in recycled {
+            self.leaves.insert(*leaf, *idx);
+            self.tree.set_leaf(*idx as usize, *leaf);
+        }
+
+        self.extend_from_slice(appended);
+    }
+
+    /// Builds the tree from leaves sorted by index, e.g. the flattened updates of a backfill. Leaves past the end of the
+    /// tree are appended in a single bulk extension, gaps being filled with empty leaves, rather than one at a time, and
+    /// only leaves within the tree are set individually. The resulting root is the same as applying every leaf in order.
+    pub fn build_from_sorted_leaves(&mut self, leaves: &[(u32, Hash)]) {
+        let next_leaf_index = self.tree.num_leaves();
+        let (existing, appended) = leaves.split_at(
+            leaves
+                .partition_point(|(idx, _)| (*idx as usize) < next_leaf_index),
+        );
+
+        for (idx, leaf) in existing {
+            let previous = self.tree.get_leaf(*idx as usize);
+            if previous != Hash::ZERO {
+                self.leaves.remove(&previous);
+            }
+            if *leaf != Hash::ZERO {
+                self.leaves.insert(*leaf, *idx);
+            }
+            self.tree.set_leaf(*idx as usize, *leaf);
+        }
+
+        let mut new_leaves = Vec::with_capacity(appended.len());
+        for (idx, leaf) in appended {
+            let position = next_leaf_index + new_leaves.len();
+            new_leaves.extend(
+                std::iter::repeat(Hash::ZERO)
+                    .take((*idx as usize).saturating_sub(position)),
+            );
+            if *leaf != Hash::ZERO {
+                self.leaves.insert(*leaf, *idx);
+            }
+            new_leaves.push(*leaf);
+        }
+
+        self.tree.extend_from_slice(&new_leaves);
+    }
+
     /// Removes a leaf from the tree and updates the leaves hashmap
     pub fn remove(&mut self, index: usize) {
         let leaf = self.tree.get_leaf(index);
@@ -140,12 +246,177 @@ where
         self.tree.set_leaf(index, Hash::ZERO);
     }
 
+    /// Validates and applies leaf updates directly to the tree
+    pub fn apply_leaf_updates(
+        &mut self,
+        leaf_updates: LeafUpdates,
+    ) -> Result<(), IdentityTreeError> {
+        self.validate_updates(&leaf_updates)?;
+
+        match leaf_updates {
+            LeafUpdates::Insert(leaves) => {
+                let leaves = sorted_leaves(leaves);
+                self.check_recycled_leaves(&leaves)?;
+                self.insert_leaves(&leaves);
+            }
+            LeafUpdates::Delete(leaves) => {
+                for (leaf_idx, _) in leaves {
+                    self.remove(leaf_idx.0 as usize);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Same as [`Self::apply_leaf_updates`], but applies the updates `chunk_size` leaves at a time, yielding to the runtime
+    /// between chunks so that a large batch does not monopolize a worker thread. The tree stays write-locked until the
+    /// whole batch is applied, so requests never see a root that is not onchain.
+    pub async fn apply_leaf_updates_chunked(
+        identity_tree: &RwLock<Self>,
+        leaf_updates: LeafUpdates,
+        chunk_size: usize,
+    ) -> Result<(), IdentityTreeError> {
+        let mut identity_tree = identity_tree.write().await;
+        identity_tree.validate_updates(&leaf_updates)?;
+
+        let (leaves, is_insert) = match leaf_updates {
+            LeafUpdates::Insert(leaves) => (sorted_leaves(leaves), true),
+            LeafUpdates::Delete(leaves) => (sorted_leaves(leaves), false),
+        };
+
+        // Checked before the first chunk is applied so that a rejected batch leaves the tree unchanged
+        if is_insert {
+            identity_tree.check_recycled_leaves(&leaves)?;
+        }
+
+        for chunk in leaves.chunks(chunk_size.max(1)) {
+            if is_insert {
+                identity_tree.insert_leaves(chunk);
+            } else {
+                for (leaf_idx, _) in chunk {
+                    identity_tree.remove(*leaf_idx as usize);
+                }
+            }
+
+            tokio::task::yield_now().await;
+        }
+
+        Ok(())
+    }
+
+    /// Maximum number of leaves the tree can hold
+    pub fn capacity(&self) -> usize {
+        1 << self.tree.depth()
+    }
+
+    /// Returns the identity commitments stored at leaf indices `[start, start + count)`, skipping empty leaves
+    pub fn find_identities_in_range(
+        &self,
+        start: usize,
+        count: usize,
+    ) -> Result<Vec<Hash>, IdentityTreeError> {
+        let capacity = self.capacity();
+        let end = start
+            .checked_add(count)
+            .filter(|end| *end <= capacity)
+            .ok_or(IdentityTreeError::InvalidLeafRange {
+                start,
+                count,
+                capacity,
+            })?;
+
+        // Leaves past the last inserted leaf are all empty
+        let end = end.min(self.tree.num_leaves());
+
+        Ok((start..end)
+            .map(|idx| self.tree.get_leaf(idx))
+            .filter(|leaf| *leaf != Hash::ZERO)
+            .collect())
+    }
+
+    fn check_leaf_index(&self, leaf_idx: u32) -> Result<(), IdentityTreeError> {
+        let capacity = self.capacity();
+        if leaf_idx as usize >= capacity {
+            return Err(IdentityTreeError::InvalidLeafIndex {
+                index: leaf_idx as usize,
+                capacity,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `leaf` was inserted at an index no greater than `max_leaf_index`, for proofs limited to identities
+    /// registered before a cutoff. Leaves not in the tree pass, since there is no proof to limit.
+    pub fn check_max_leaf_index(
+        &self,
+        leaf: Hash,
+        max_leaf_index: u32,
+    ) -> Result<(), IdentityTreeError> {
+        match self.leaves.get(&leaf) {
+            Some(&index) if index > max_leaf_index => {
+                Err(IdentityTreeError::LeafAfterCutoff {
+                    index,
+                    max_leaf_index,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that `leaf_updates` is within the maximum batch size and that every leaf index fits in the tree, so that
+    /// malformed updates decoded from the chain are rejected before any modification instead of panicking midway through
+    /// applying them or holding the tree for a long time
+    pub fn validate_updates(
+        &self,
+        leaf_updates: &LeafUpdates,
+    ) -> Result<(), IdentityTreeError> {
+        let updates = match leaf_updates {
+            LeafUpdates::Insert(updates) | LeafUpdates::Delete(updates) => {
+                updates
+            }
+        };
+
+        if updates.len() > self.max_batch_size {
+            return Err(IdentityTreeError::BatchTooLarge {
+                size: updates.len(),
+                max: self.max_batch_size,
+            });
+        }
+
+        for leaf_idx in updates.keys() {
+            self.check_leaf_index(leaf_idx.0)?;
+        }
+
+        Ok(())
+    }
+
     // Appends new leaf updates to the `leaves` hashmap and adds newly calculated storage nodes to `tree_updates`
     pub fn append_updates(
         &mut self,
         root: Root,
         leaf_updates: LeafUpdates,
     ) -> Result<(), IdentityTreeError> {
+        self.validate_updates(&leaf_updates)?;
+
+        // An update leaving the root unchanged, e.g. an empty batch, would only take up memory in `tree_updates`. Its
+        // nonce is still recorded so that the root can be looked up once it is bridged.
+        let latest_root = self
+            .tree_updates
+            .keys()
+            .next_back()
+            .map_or_else(|| self.tree.root(), |latest| latest.hash);
+        if latest_root == root.hash {
+            tracing::warn!(
+                root = ?root.hash,
+                nonce = root.nonce,
+                "Update does not change the latest root"
+            );
+            self.roots.insert(root.hash, root.nonce);
+            return Ok(());
+        }
+
         self.update_leaves(&leaf_updates);
 
         let updates = self.construct_storage_updates(leaf_updates, None)?;
@@ -163,8 +434,25 @@ where
                 }
             }
             LeafUpdates::Delete(updates) => {
-                for (_, val) in updates.iter() {
-                    self.leaves.remove(val);
+                // Deletions carry the zero leaf, so the deleted identities are looked up by index in the latest pending
+                // update, or the tree if the leaf has no pending update
+                let depth = self.tree.depth();
+                let latest_updates = self.tree_updates.values().next_back();
+
+                for idx in updates.keys() {
+                    let leaf = latest_updates
+                        .and_then(|updates| {
+                            updates
+                                .get(&leaf_to_storage_idx(idx.0, depth).into())
+                        })
+                        .copied()
+                        .unwrap_or_else(|| {
+                            self.tree.get_node(depth, idx.0 as usize)
+                        });
+
+                    if self.leaves.get(&leaf) == Some(&idx.0) {
+                        self.leaves.remove(&leaf);
+                    }
                 }
             }
         }
@@ -200,7 +488,9 @@ where
             if let Some(update) = self.tree_updates.get(root) {
                 update.clone()
             } else {
-                return Err(IdentityTreeError::RootNotFound);
+                return Err(IdentityTreeError::RootNotFound {
+                    root: root.hash,
+                });
             }
         } else {
             // Otherwise, get the most recent update
@@ -303,24 +593,9 @@ where
 
             leaf_updates.sort_by_key(|(idx, _)| *idx);
 
-            // Partition the leaf updates into insertions and deletions
-            let (insertions, deletions): (Vec<Hash>, Vec<usize>) = leaf_updates
-                .into_par_iter()
-                .partition_map(|(leaf_idx, value)| {
-                    if value != Hash::ZERO {
-                        Either::Left(value)
-                    } else {
-                        Either::Right(leaf_idx as usize)
-                    }
-                });
-
-            // Insert/delete leaves in the canonical tree
-            // Note that the leaves are inserted/removed from the leaves hashmap when the updates are first applied to tree_updates
-            self.tree.extend_from_slice(&insertions);
-
-            for leaf_idx in deletions {
-                self.tree.set_leaf(leaf_idx, Hash::ZERO);
-            }
+            // Leaves are set at their index, so that recycled indices are overwritten rather than appended, and deleted
+            // leaves are zeroed. The leaves hashmap was updated when the updates were first appended to tree_updates.
+            self.build_from_sorted_leaves(&leaf_updates);
         }
 
         // Split off tree updates at the new root
@@ -366,6 +641,64 @@ where
         }
     }
 
+    /// Checks the tree and the retained updates for corruption by verifying proofs for `num_leaves` random leaves
+    /// against the canonical root and up to `num_roots` random pending roots.
+    /// Returns an error identifying the first root and leaf whose proof does not verify.
+    pub fn self_test(
+        &self,
+        rng: &mut impl Rng,
+        num_roots: usize,
+        num_leaves: usize,
+    ) -> Result<(), IdentityTreeError> {
+        let tree_size = self.tree.num_leaves();
+        if tree_size == 0 {
+            return Ok(());
+        }
+
+        let depth = self.tree.depth();
+
+        let mut roots = vec![(self.tree.root(), None)];
+        roots.extend(
+            self.tree_updates
+                .iter()
+                .choose_multiple(rng, num_roots)
+                .into_iter()
+                .map(|(root, updates)| (root.hash, Some(updates))),
+        );
+
+        for (root, updates) in roots {
+            for _ in 0..num_leaves {
+                let index = rng.gen_range(0..tree_size) as u32;
+
+                // Proofs are constructed the same way they are served
+                let (leaf, proof) = match updates {
+                    Some(updates) => (
+                        updates
+                            .get(&leaf_to_storage_idx(index, depth).into())
+                            .copied()
+                            .unwrap_or_else(|| {
+                                self.tree.get_leaf(index as usize)
+                            }),
+                        self.construct_proof(index, Some(updates)),
+                    ),
+                    None => (
+                        self.tree.get_leaf(index as usize),
+                        self.tree.proof(index as usize),
+                    ),
+                };
+
+                if proof.root(leaf) != root {
+                    return Err(IdentityTreeError::ProofVerificationFailed {
+                        root,
+                        index,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Construct an inclusion proof for a given leaf at a specified root
     pub fn construct_proof_from_root(
         &self,
@@ -376,14 +709,62 @@ where
         let updates = self
             .tree_updates
             .get(root)
-            .ok_or(IdentityTreeError::RootNotFound)?;
+            .ok_or(IdentityTreeError::RootNotFound { root: root.hash })?;
+
+        Ok(self.construct_proof(leaf_idx, Some(updates)))
+    }
+
+    /// Construct a proof for an empty leaf, i.e. the merkle path showing that the leaf at `leaf_idx` holds the zero value.
+    /// If a root is provided, the proof is constructed from the specified root
+    /// Otherwise, the proof is constructed from the current canonical tree
+    pub fn empty_leaf_proof(
+        &self,
+        leaf_idx: u32,
+        root: Option<&Root>,
+    ) -> Result<InclusionProof, IdentityTreeError> {
+        self.check_leaf_index(leaf_idx)?;
+        let depth = self.tree.depth();
+
+        let (root_hash, updates) = match root {
+            Some(root) if root.hash != self.tree.root() => {
+                let updates = self.tree_updates.get(root).ok_or(
+                    IdentityTreeError::RootNotFound { root: root.hash },
+                )?;
+
+                (root.hash, Some(updates))
+            }
+            _ => (self.tree.root(), None),
+        };
+
+        let leaf = updates
+            .and_then(|updates| {
+                updates.get(&leaf_to_storage_idx(leaf_idx, depth).into())
+            })
+            .copied()
+            .unwrap_or_else(|| self.tree.get_node(depth, leaf_idx as usize));
+
+        if leaf != Hash::ZERO {
+            return Err(IdentityTreeError::LeafNotEmpty {
+                index: leaf_idx,
+                leaf,
+            });
+        }
+
+        let proof = self.construct_proof(leaf_idx, updates);
+        Ok(InclusionProof::new(root_hash, proof))
+    }
 
+    /// Traverse the tree from the leaf to the root, constructing the proof along the way with precedence for the updated node values
+    fn construct_proof(
+        &self,
+        leaf_idx: u32,
+        updates: Option<&StorageUpdates>,
+    ) -> Proof {
         // Convert the leaf index to a storage index for easier indexing
         let mut node_idx = leaf_to_storage_idx(leaf_idx, self.tree.depth());
 
         let mut proof: Vec<Branch<Hash>> = vec![];
 
-        // Traverse the tree from the leaf to the root, constructing the proof along the way with precedence for the updated node values
         while node_idx > 0 {
             let sibling_idx = if node_idx % 2 == 0 {
                 node_idx - 1
@@ -393,14 +774,13 @@ where
 
             // Check if the sibling is in the updates, otherwise get the node from the tree
             let sibling = updates
-                .get(&sibling_idx.into())
+                .and_then(|updates| updates.get(&sibling_idx.into()))
                 .copied()
-                .or_else(|| {
+                .unwrap_or_else(|| {
                     let (depth, offset) =
                         storage_idx_to_coords(sibling_idx as usize);
-                    Some(self.tree.get_node(depth, offset))
-                })
-                .expect("Could not find node in tree");
+                    self.tree.get_node(depth, offset)
+                });
 
             // Add the sibling to the proof and adjust the node index
             proof.push(if node_idx % 2 == 0 {
@@ -412,7 +792,7 @@ where
             node_idx = (node_idx - 1) / 2;
         }
 
-        Ok(semaphore::merkle_tree::Proof(proof))
+        semaphore::merkle_tree::Proof(proof)
     }
 
     // Computes the updated root hash from a list of new leaves
@@ -436,14 +816,28 @@ where
             root,
         )?;
 
-        let updated_root = storage_updates
-            .remove(&NodeIndex(0))
-            .ok_or(IdentityTreeError::RootNotFound)?;
+        let updated_root = storage_updates.remove(&NodeIndex(0)).ok_or(
+            IdentityTreeError::RootNotFound {
+                root: root.map_or_else(|| self.tree.root(), |root| root.hash),
+            },
+        )?;
 
         Ok(updated_root)
     }
 }
 
+/// Leaf indices and hashes of `leaves` sorted by index
+fn sorted_leaves(leaves: Leaves) -> Vec<(u32, Hash)> {
+    let mut leaves = leaves
+        .into_iter()
+        .map(|(idx, hash)| (idx.0, hash))
+        .collect::<Vec<_>>();
+
+    leaves.sort_by_key(|(idx, _)| *idx);
+
+    leaves
+}
+
 /// Flattens leaf updates into a single vector of leaf indices and hashes with precedence given to the latest updates
 pub fn flatten_leaf_updates(
     leaf_updates: BTreeMap<Root, LeafUpdates>,
@@ -465,6 +859,8 @@ pub fn flatten_leaf_updates(
     updates
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum LeafUpdates {
     Insert(Leaves),
     Delete(Leaves),
@@ -479,6 +875,20 @@ impl From<LeafUpdates> for Leaves {
     }
 }
 
+/// Hashes of empty subtrees of height 1 to `depth`, i.e. `H(0, 0)`, `H(H(0, 0), H(0, 0))`, ..., with the last hash being
+/// the root of an empty tree of the given depth. Hashes are computed once and cached for all subsequent calls.
+pub fn empty_subtree_hashes(depth: usize) -> Vec<Hash> {
+    static EMPTY_SUBTREE_HASHES: Mutex<Vec<Hash>> = Mutex::new(Vec::new());
+
+    let mut hashes = EMPTY_SUBTREE_HASHES.lock().unwrap();
+    while hashes.len() < depth {
+        let child = hashes.last().copied().unwrap_or(Hash::ZERO);
+        hashes.push(PoseidonHash::hash_node(&child, &child));
+    }
+
+    hashes[..depth].to_vec()
+}
+
 pub fn leaf_to_storage_idx(leaf_idx: u32, tree_depth: usize) -> u32 {
     let leaf_0 = (1 << tree_depth) - 1;
     leaf_0 + leaf_idx
@@ -515,7 +925,7 @@ impl PartialOrd for Root {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InclusionProof {
     pub root: Field,
@@ -543,6 +953,91 @@ impl InclusionProof {
 
         hash == self.root
     }
+
+    /// Same path in the sparse Merkle tree format expected by some verifiers, e.g. bridges and circuits
+    pub fn to_smt(&self, leaf: Field) -> SmtProof {
+        let path = self
+            .proof
+            .0
+            .iter()
+            .map(|branch| match branch {
+                Branch::Left(sibling) => SmtPathNode {
+                    sibling: *sibling,
+                    is_left: false,
+                },
+                Branch::Right(sibling) => SmtPathNode {
+                    sibling: *sibling,
+                    is_left: true,
+                },
+            })
+            .collect();
+
+        SmtProof {
+            root: self.root,
+            leaf,
+            path,
+        }
+    }
+}
+
+/// Inclusion proof as a leaf and the sibling hashes from the leaf up to the root
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtProof {
+    /// Root the path proves inclusion in, which depends on the requested `chainId` and on pending roots
+    pub root: Field,
+    pub leaf: Field,
+    pub path: Vec<SmtPathNode>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtPathNode {
+    pub sibling: Field,
+    /// True if the sibling is the left child, i.e. it is hashed before the node on the path
+    pub is_left: bool,
+}
+
+/// Inclusion proof with everything needed to verify it onchain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProofBundle {
+    pub identity_commitment: Hash,
+    /// Root the proof is valid for, i.e. the latest root on `chain_id`
+    pub root: Hash,
+    /// Index of the identity commitment in the tree, whose bits give the direction of each step of the path
+    pub leaf_index: u32,
+    /// Sibling hashes from the leaf up to the root
+    pub siblings: Vec<Hash>,
+    /// Identity manager or bridged World ID contract on `chain_id` holding `root`
+    pub contract_address: H160,
+    pub chain_id: u64,
+}
+
+impl ProofBundle {
+    pub fn new(
+        identity_commitment: Hash,
+        leaf_index: u32,
+        inclusion_proof: InclusionProof,
+        contract_address: H160,
+        chain_id: u64,
+    ) -> Self {
+        let siblings = inclusion_proof
+            .proof
+            .0
+            .iter()
+            .map(|branch| match branch {
+                Branch::Left(sibling) | Branch::Right(sibling) => *sibling,
+            })
+            .collect();
+
+        Self {
+            identity_commitment,
+            root: inclusion_proof.root,
+            leaf_index,
+            siblings,
+            contract_address,
+            chain_id,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -553,12 +1048,17 @@ mod test {
     use eyre::{eyre, ContextCompat};
     use rand::{Rng, SeedableRng};
     use semaphore::cascading_merkle_tree::CascadingMerkleTree;
-    use semaphore::merkle_tree::Branch;
+    use semaphore::merkle_tree::{Branch, Hasher};
     use semaphore::poseidon_tree::PoseidonHash;
+    use tokio::sync::RwLock;
 
-    use super::{leaf_to_storage_idx, IdentityTree, LeafUpdates, Root};
+    use super::{
+        leaf_to_storage_idx, IdentityTree, LeafUpdates, ProofBundle, Root,
+        SmtPathNode, APPLY_CHUNK_SIZE, DEFAULT_MAX_BATCH_SIZE,
+    };
+    use crate::tree::error::IdentityTreeError;
     use crate::tree::identity_tree::{
-        storage_idx_to_coords, storage_to_leaf_idx,
+        empty_subtree_hashes, storage_idx_to_coords, storage_to_leaf_idx,
     };
     use crate::tree::{Hash, LeafIndex};
 
@@ -601,6 +1101,29 @@ mod test {
         assert!(root_2 > root_3);
     }
 
+    #[test]
+    fn test_empty_subtree_hashes() {
+        for depth in [1, 10, 20] {
+            let tree = CascadingMerkleTree::<PoseidonHash>::new(
+                vec![],
+                depth,
+                &Hash::ZERO,
+            );
+
+            let hashes = empty_subtree_hashes(depth);
+            assert_eq!(hashes.len(), depth);
+            assert_eq!(hashes.last(), Some(&tree.root()));
+        }
+
+        assert_eq!(
+            empty_subtree_hashes(2),
+            vec![
+                PoseidonHash::hash_node(&Hash::ZERO, &Hash::ZERO),
+                empty_subtree_hashes(20)[1]
+            ]
+        );
+    }
+
     #[test]
     fn test_leaf_to_storage_idx() {
         for i in 0..1 << TREE_DEPTH {
@@ -669,6 +1192,38 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_from_tree() {
+        let mut leaves = generate_all_leaves();
+        leaves[1] = Hash::ZERO;
+
+        let tree: CascadingMerkleTree<PoseidonHash> =
+            CascadingMerkleTree::new_with_leaves(
+                vec![],
+                TREE_DEPTH,
+                &Hash::ZERO,
+                &leaves,
+            );
+        let expected_root = tree.root();
+
+        let identity_tree = IdentityTree::from_tree(tree);
+
+        assert_eq!(identity_tree.tree.root(), expected_root);
+        assert!(identity_tree.tree_updates.is_empty());
+
+        // Zero leaves are not indexed
+        assert_eq!(identity_tree.leaves.len(), leaves.len() - 1);
+        assert_eq!(identity_tree.leaves.get(&Hash::ZERO), None);
+        for (leaf_idx, leaf) in leaves.iter().enumerate() {
+            if *leaf != Hash::ZERO {
+                assert_eq!(
+                    identity_tree.leaves.get(leaf),
+                    Some(&(leaf_idx as u32))
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_remove() -> eyre::Result<()> {
         let mut identity_tree = IdentityTree::new(TREE_DEPTH);
@@ -760,6 +1315,404 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_self_test() -> eyre::Result<()> {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(42);
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+
+        let leaves = generate_all_leaves();
+        for (idx, leaf) in leaves[0..NUM_LEAVES / 2].iter().enumerate() {
+            identity_tree.insert(idx as u32, *leaf)?;
+        }
+
+        let updated_tree: CascadingMerkleTree<PoseidonHash> =
+            CascadingMerkleTree::new_with_leaves(
+                vec![],
+                TREE_DEPTH,
+                &Hash::ZERO,
+                &leaves,
+            );
+        let new_root = Root {
+            hash: updated_tree.root(),
+            nonce: 1,
+        };
+
+        let offset = NUM_LEAVES / 2;
+        let leaf_updates = leaves[offset..NUM_LEAVES]
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| (((idx + offset) as u32).into(), *value))
+            .collect::<HashMap<LeafIndex, Hash>>();
+        identity_tree
+            .append_updates(new_root, LeafUpdates::Insert(leaf_updates))?;
+
+        identity_tree.self_test(&mut rng, 1, NUM_LEAVES)?;
+
+        // Corrupt the retained updates for the pending root
+        for node in identity_tree
+            .tree_updates
+            .get_mut(&new_root)
+            .context("Missing tree updates")?
+            .values_mut()
+        {
+            *node = Hash::from(7);
+        }
+
+        assert!(matches!(
+            identity_tree.self_test(&mut rng, 1, NUM_LEAVES),
+            Err(IdentityTreeError::ProofVerificationFailed { root, .. }) if root == new_root.hash
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deletions_remove_identities() -> eyre::Result<()> {
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+        let leaves = generate_all_leaves();
+
+        identity_tree.apply_leaf_updates(LeafUpdates::Insert(
+            HashMap::from([(0.into(), leaves[0]), (1.into(), leaves[1])]),
+        ))?;
+
+        let root = |hash: u64| Root {
+            hash: Hash::from(hash),
+            nonce: hash as usize,
+        };
+        identity_tree.append_updates(
+            root(1),
+            LeafUpdates::Insert(HashMap::from([
+                (2.into(), leaves[2]),
+                (3.into(), leaves[3]),
+            ])),
+        )?;
+        // Deletes an identity of the tree and an identity only inserted by a pending update
+        identity_tree.append_updates(
+            root(2),
+            LeafUpdates::Delete(HashMap::from([
+                (0.into(), Hash::ZERO),
+                (3.into(), Hash::ZERO),
+            ])),
+        )?;
+
+        assert_eq!(
+            identity_tree.leaves,
+            HashMap::from([(leaves[1], 1), (leaves[2], 2)])
+        );
+
+        // Identities deleted from the tree itself are removed as well
+        identity_tree.apply_leaf_updates(LeafUpdates::Delete(
+            HashMap::from([(1.into(), Hash::ZERO)]),
+        ))?;
+        assert_eq!(identity_tree.leaves, HashMap::from([(leaves[2], 2)]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_from_sorted_leaves() -> eyre::Result<()> {
+        let depth = 10;
+        let leaves = infinite_leaves().take(600).collect::<Vec<_>>();
+
+        // Leaves 100 to 199 are deleted and leaf 450 is never set
+        let sorted_leaves = leaves
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| *idx != 450)
+            .map(|(idx, leaf)| {
+                let leaf = if (100..200).contains(&idx) {
+                    Hash::ZERO
+                } else {
+                    *leaf
+                };
+                (idx as u32, leaf)
+            })
+            .collect::<Vec<_>>();
+
+        let mut sequential = CascadingMerkleTree::<PoseidonHash>::new(
+            vec![],
+            depth,
+            &Hash::ZERO,
+        );
+        for idx in 0..600 {
+            let leaf = sorted_leaves
+                .iter()
+                .find(|(leaf_idx, _)| *leaf_idx == idx)
+                .map_or(Hash::ZERO, |(_, leaf)| *leaf);
+            sequential.push(leaf)?;
+        }
+
+        let mut identity_tree = IdentityTree::new(depth);
+        identity_tree.build_from_sorted_leaves(&sorted_leaves);
+        assert_eq!(identity_tree.tree.root(), sequential.root());
+        assert_eq!(identity_tree.tree.num_leaves(), 600);
+        assert_eq!(identity_tree.leaves.len(), 499);
+        assert_eq!(identity_tree.leaves.get(&leaves[599]), Some(&599));
+        assert!(!identity_tree.leaves.contains_key(&leaves[150]));
+
+        // Building on top of a populated tree overwrites existing leaves and appends the others
+        let mut identity_tree = IdentityTree::new(depth);
+        identity_tree.build_from_sorted_leaves(&sorted_leaves[..300]);
+        identity_tree.build_from_sorted_leaves(&[
+            (0, Hash::ZERO),
+            (150, leaves[150]),
+            (700, leaves[0]),
+        ]);
+        identity_tree.build_from_sorted_leaves(&sorted_leaves[300..]);
+
+        sequential.set_leaf(0, Hash::ZERO);
+        sequential.set_leaf(150, leaves[150]);
+        for _ in 600..700 {
+            sequential.push(Hash::ZERO)?;
+        }
+        sequential.push(leaves[0])?;
+
+        assert_eq!(identity_tree.tree.root(), sequential.root());
+        assert_eq!(identity_tree.leaves.get(&leaves[0]), Some(&700));
+        assert_eq!(identity_tree.leaves.get(&leaves[150]), Some(&150));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_unchanged_root() -> eyre::Result<()> {
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+        let root = Root {
+            hash: identity_tree.tree.root(),
+            nonce: 1,
+        };
+
+        identity_tree
+            .append_updates(root, LeafUpdates::Insert(HashMap::new()))?;
+
+        assert!(identity_tree.tree_updates.is_empty());
+        assert_eq!(identity_tree.roots.get(&root.hash), Some(&1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_updates() {
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+        let capacity = identity_tree.capacity();
+
+        let valid = LeafUpdates::Insert(HashMap::from([(
+            LeafIndex::from(capacity as u32 - 1),
+            Hash::from(1),
+        )]));
+        assert!(identity_tree.validate_updates(&valid).is_ok());
+
+        // Out of bounds updates are rejected without modifying the tree
+        let root = identity_tree.tree.root();
+        let out_of_bounds = LeafUpdates::Delete(HashMap::from([(
+            LeafIndex::from(capacity as u32),
+            Hash::ZERO,
+        )]));
+        let new_root = Root {
+            hash: Hash::from(2),
+            nonce: 1,
+        };
+
+        assert!(matches!(
+            identity_tree.append_updates(new_root, out_of_bounds),
+            Err(IdentityTreeError::InvalidLeafIndex { index, .. }) if index == capacity
+        ));
+        assert_eq!(identity_tree.tree.root(), root);
+        assert!(identity_tree.tree_updates.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_oversized_batch() {
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+        let root = identity_tree.tree.root();
+
+        let deletions = LeafUpdates::Delete(
+            (0..1_000_000u32)
+                .map(|idx| (LeafIndex::from(idx), Hash::ZERO))
+                .collect(),
+        );
+
+        assert!(matches!(
+            identity_tree.validate_updates(&deletions),
+            Err(IdentityTreeError::BatchTooLarge {
+                size: 1_000_000,
+                max: DEFAULT_MAX_BATCH_SIZE
+            })
+        ));
+        assert!(matches!(
+            identity_tree.apply_leaf_updates(deletions),
+            Err(IdentityTreeError::BatchTooLarge { .. })
+        ));
+        assert_eq!(identity_tree.tree.root(), root);
+
+        let mut identity_tree = identity_tree.with_max_batch_size(1);
+        let insertions = LeafUpdates::Insert(HashMap::from([
+            (LeafIndex::from(0), Hash::from(1)),
+            (LeafIndex::from(1), Hash::from(2)),
+        ]));
+        assert!(matches!(
+            identity_tree.append_updates(
+                Root {
+                    hash: Hash::from(3),
+                    nonce: 1
+                },
+                insertions
+            ),
+            Err(IdentityTreeError::BatchTooLarge { size: 2, max: 1 })
+        ));
+        assert!(identity_tree.tree_updates.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_apply_leaf_updates_chunked() -> eyre::Result<()> {
+        let batch_size = 10_000u32;
+        let tree_depth = 14;
+
+        let insertions = (0..batch_size)
+            .map(|idx| (LeafIndex::from(idx), Hash::from(idx + 1)))
+            .collect::<HashMap<_, _>>();
+        let deletions = (0..batch_size)
+            .step_by(3)
+            .map(|idx| (LeafIndex::from(idx), Hash::ZERO))
+            .collect::<HashMap<_, _>>();
+
+        let mut expected = IdentityTree::new(tree_depth);
+        expected.apply_leaf_updates(LeafUpdates::Insert(insertions.clone()))?;
+        expected.apply_leaf_updates(LeafUpdates::Delete(deletions.clone()))?;
+
+        let identity_tree = RwLock::new(IdentityTree::new(tree_depth));
+        IdentityTree::apply_leaf_updates_chunked(
+            &identity_tree,
+            LeafUpdates::Insert(insertions),
+            APPLY_CHUNK_SIZE,
+        )
+        .await?;
+        IdentityTree::apply_leaf_updates_chunked(
+            &identity_tree,
+            LeafUpdates::Delete(deletions),
+            APPLY_CHUNK_SIZE,
+        )
+        .await?;
+        let identity_tree = identity_tree.into_inner();
+
+        assert_eq!(identity_tree.tree.num_leaves(), batch_size as usize);
+        assert_eq!(identity_tree.tree.root(), expected.tree.root());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_leaf_updates_chunked_is_atomic() -> eyre::Result<()> {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let insertions = (0..1_000u32)
+            .map(|idx| (LeafIndex::from(idx), Hash::from(idx + 1)))
+            .collect::<HashMap<_, _>>();
+        let identity_tree = RwLock::new(IdentityTree::new(10));
+        let initial_root = identity_tree.read().await.tree.root();
+        let done = AtomicBool::new(false);
+
+        let apply = async {
+            let result = IdentityTree::apply_leaf_updates_chunked(
+                &identity_tree,
+                LeafUpdates::Insert(insertions),
+                100,
+            )
+            .await;
+            done.store(true, Ordering::SeqCst);
+            result
+        };
+        // Reads the root whenever the batch yields between chunks
+        let observe = async {
+            let mut roots = vec![];
+            while !done.load(Ordering::SeqCst) {
+                roots.push(identity_tree.read().await.tree.root());
+                tokio::task::yield_now().await;
+            }
+            roots
+        };
+
+        let (result, roots) = tokio::join!(apply, observe);
+        result?;
+        let final_root = identity_tree.read().await.tree.root();
+
+        assert!(!roots.is_empty());
+        assert!(roots
+            .iter()
+            .all(|root| *root == initial_root || *root == final_root));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recycled_indices() -> eyre::Result<()> {
+        let [a, b, c, d, e] = [1, 2, 3, 4, 5].map(Hash::from);
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+
+        identity_tree.apply_leaf_updates(LeafUpdates::Insert(
+            HashMap::from([
+                (LeafIndex::from(0), a),
+                (LeafIndex::from(1), b),
+                (LeafIndex::from(2), c),
+            ]),
+        ))?;
+        identity_tree.apply_leaf_updates(LeafUpdates::Delete(
+            HashMap::from([
+                (LeafIndex::from(0), Hash::ZERO),
+                (LeafIndex::from(2), Hash::ZERO),
+            ]),
+        ))?;
+        identity_tree.apply_leaf_updates(LeafUpdates::Insert(
+            HashMap::from([(LeafIndex::from(0), d), (LeafIndex::from(2), e)]),
+        ))?;
+
+        let mut expected_tree: CascadingMerkleTree<PoseidonHash> =
+            CascadingMerkleTree::new(vec![], TREE_DEPTH, &Hash::ZERO);
+        expected_tree.extend_from_slice(&[a, b, c]);
+        expected_tree.set_leaf(0, Hash::ZERO);
+        expected_tree.set_leaf(2, Hash::ZERO);
+        expected_tree.set_leaf(0, d);
+        expected_tree.set_leaf(2, e);
+
+        assert_eq!(identity_tree.tree.root(), expected_tree.root());
+        assert_eq!(identity_tree.tree.num_leaves(), 3);
+
+        // Deleted identities are dropped from the index and replaced by the recycled ones
+        assert_eq!(
+            identity_tree.leaves,
+            HashMap::from([(b, 1), (d, 0), (e, 2)])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_insert_into_occupied_leaf() -> eyre::Result<()> {
+        let [a, b, c] = [1, 2, 3].map(Hash::from);
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+
+        identity_tree.apply_leaf_updates(LeafUpdates::Insert(
+            HashMap::from([(LeafIndex::from(0), a), (LeafIndex::from(1), b)]),
+        ))?;
+        let root = identity_tree.tree.root();
+
+        let result = identity_tree.apply_leaf_updates(LeafUpdates::Insert(
+            HashMap::from([(LeafIndex::from(1), c), (LeafIndex::from(2), c)]),
+        ));
+
+        assert!(matches!(
+            result,
+            Err(IdentityTreeError::LeafNotEmpty { index: 1, leaf }) if leaf == b
+        ));
+
+        // The rejected batch is not partially applied
+        assert_eq!(identity_tree.tree.root(), root);
+        assert_eq!(identity_tree.tree.num_leaves(), 2);
+        assert_eq!(identity_tree.leaves, HashMap::from([(a, 0), (b, 1)]));
+
+        Ok(())
+    }
+
     #[test]
     fn test_apply_updates_to_root() -> eyre::Result<()> {
         let mut identity_tree = IdentityTree::new(TREE_DEPTH);
@@ -818,6 +1771,136 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_apply_updates_to_root_recycled_index() -> eyre::Result<()> {
+        let [a, b, c, d] = [1, 2, 3, 4].map(Hash::from);
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+        identity_tree.apply_leaf_updates(LeafUpdates::Insert(
+            HashMap::from([
+                (LeafIndex::from(0), a),
+                (LeafIndex::from(1), b),
+                (LeafIndex::from(2), c),
+            ]),
+        ))?;
+
+        let mut expected_tree: CascadingMerkleTree<PoseidonHash> =
+            CascadingMerkleTree::new(vec![], TREE_DEPTH, &Hash::ZERO);
+        expected_tree.extend_from_slice(&[a, b, c]);
+
+        // Index 0 is deleted, then recycled for a new identity while both updates are pending
+        expected_tree.set_leaf(0, Hash::ZERO);
+        let deleted = Root {
+            hash: expected_tree.root(),
+            nonce: 1,
+        };
+        identity_tree.append_updates(
+            deleted,
+            LeafUpdates::Delete(HashMap::from([(
+                LeafIndex::from(0),
+                Hash::ZERO,
+            )])),
+        )?;
+
+        expected_tree.set_leaf(0, d);
+        let recycled = Root {
+            hash: expected_tree.root(),
+            nonce: 2,
+        };
+        identity_tree.append_updates(
+            recycled,
+            LeafUpdates::Insert(HashMap::from([(LeafIndex::from(0), d)])),
+        )?;
+
+        identity_tree.apply_updates_to_root(&recycled);
+
+        assert_eq!(identity_tree.tree.root(), recycled.hash);
+        assert_eq!(identity_tree.tree.num_leaves(), 3);
+        assert_eq!(
+            identity_tree.leaves,
+            HashMap::from([(b, 1), (c, 2), (d, 0)])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_applied_roots_no_longer_return_proofs() -> eyre::Result<()> {
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+        let leaves = generate_all_leaves();
+
+        identity_tree.insert(0, leaves[0])?;
+
+        // Append one update per remaining leaf, each producing a pending root
+        let mut roots = vec![];
+        for (idx, leaf) in leaves.iter().enumerate().skip(1) {
+            let root = Root {
+                hash: CascadingMerkleTree::<PoseidonHash>::new_with_leaves(
+                    vec![],
+                    TREE_DEPTH,
+                    &Hash::ZERO,
+                    &leaves[..=idx],
+                )
+                .root(),
+                nonce: idx,
+            };
+            let updates = LeafUpdates::Insert(
+                vec![(LeafIndex(idx as u32), *leaf)].into_iter().collect(),
+            );
+
+            identity_tree.append_updates(root, updates)?;
+            roots.push(root);
+        }
+
+        // Applying the second root drops every pending root up to it
+        identity_tree.apply_updates_to_root(&roots[1]);
+        assert_eq!(identity_tree.tree_updates.len(), 1);
+
+        let result = identity_tree.inclusion_proof(leaves[1], Some(&roots[0]));
+        assert!(
+            matches!(result, Err(IdentityTreeError::RootNotFound { root }) if root == roots[0].hash),
+            "A dropped root must not return a proof"
+        );
+
+        // The applied root is now the canonical root and the later root is still pending
+        let proof = identity_tree
+            .inclusion_proof(leaves[1], Some(&roots[1]))?
+            .context("Missing proof")?;
+        assert_eq!(proof.root, roots[1].hash);
+
+        let proof = identity_tree
+            .inclusion_proof(leaves[3], Some(&roots[2]))?
+            .context("Missing proof")?;
+        assert_eq!(proof.root, roots[2].hash);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_max_leaf_index() -> eyre::Result<()> {
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+        let leaves = generate_all_leaves();
+
+        for (idx, leaf) in leaves[..3].iter().enumerate() {
+            identity_tree.insert(idx as u32, *leaf)?;
+        }
+
+        // The leaf exactly at the cutoff is included, the next one is not
+        identity_tree.check_max_leaf_index(leaves[0], 1)?;
+        identity_tree.check_max_leaf_index(leaves[1], 1)?;
+        assert!(matches!(
+            identity_tree.check_max_leaf_index(leaves[2], 1),
+            Err(IdentityTreeError::LeafAfterCutoff {
+                index: 2,
+                max_leaf_index: 1
+            })
+        ));
+
+        // Unknown leaves have no proof to refuse
+        identity_tree.check_max_leaf_index(leaves[3], 0)?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_compute_root() -> eyre::Result<()> {
         let mut identity_tree = IdentityTree::new(TREE_DEPTH);
@@ -855,6 +1938,137 @@ mod test {
     #[test]
     fn test_flatten_leaf_updates() {}
 
+    #[test]
+    fn test_find_identities_in_range() -> eyre::Result<()> {
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+        let leaves = generate_all_leaves();
+        for (idx, leaf) in leaves.iter().take(3).enumerate() {
+            identity_tree.insert(idx as u32, *leaf)?;
+        }
+        identity_tree.remove(1);
+
+        assert_eq!(
+            identity_tree.find_identities_in_range(0, NUM_LEAVES)?,
+            vec![leaves[0], leaves[2]]
+        );
+        assert_eq!(
+            identity_tree.find_identities_in_range(2, 2)?,
+            vec![leaves[2]]
+        );
+        assert!(identity_tree.find_identities_in_range(3, 1)?.is_empty());
+        assert!(identity_tree.find_identities_in_range(0, 0)?.is_empty());
+
+        assert!(matches!(
+            identity_tree.find_identities_in_range(1, NUM_LEAVES),
+            Err(IdentityTreeError::InvalidLeafRange { .. })
+        ));
+        assert!(matches!(
+            identity_tree.find_identities_in_range(usize::MAX, 2),
+            Err(IdentityTreeError::InvalidLeafRange { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_proof_bundle() -> eyre::Result<()> {
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+        let leaves = generate_all_leaves();
+        for (idx, leaf) in leaves.iter().enumerate() {
+            identity_tree.insert(idx as u32, *leaf)?;
+        }
+
+        let inclusion_proof = identity_tree
+            .inclusion_proof(leaves[1], None)?
+            .context("Missing inclusion proof")?;
+        let bundle = ProofBundle::new(
+            leaves[1],
+            1,
+            inclusion_proof,
+            ethers::types::H160::repeat_byte(0x11),
+            10,
+        );
+
+        assert_eq!(bundle.root, identity_tree.tree.root());
+        // Leaf 1 is the right child of its parent, whose sibling is the subtree of leaves 2 and 3
+        assert_eq!(
+            bundle.siblings,
+            vec![leaves[0], PoseidonHash::hash_node(&leaves[2], &leaves[3])]
+        );
+
+        let json = serde_json::to_value(&bundle)?;
+        assert_eq!(
+            json.as_object()
+                .context("Expected an object")?
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+            vec![
+                "chainId",
+                "contractAddress",
+                "identityCommitment",
+                "leafIndex",
+                "root",
+                "siblings"
+            ]
+        );
+        assert_eq!(
+            json["contractAddress"],
+            "0x1111111111111111111111111111111111111111"
+        );
+        assert_eq!(json["chainId"], 10);
+        assert_eq!(json["leafIndex"], 1);
+        assert_eq!(serde_json::from_value::<ProofBundle>(json)?, bundle);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_smt_proof() -> eyre::Result<()> {
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+        let leaves = generate_all_leaves();
+        for (idx, leaf) in leaves.iter().enumerate() {
+            identity_tree.insert(idx as u32, *leaf)?;
+        }
+
+        let inclusion_proof = identity_tree
+            .inclusion_proof(leaves[1], None)?
+            .context("Missing inclusion proof")?;
+        let smt_proof = inclusion_proof.to_smt(leaves[1]);
+
+        assert_eq!(smt_proof.root, identity_tree.tree.root());
+        assert_eq!(smt_proof.leaf, leaves[1]);
+        // Leaf 1 is the right child of its parent, whose parent is the left child of the root
+        assert_eq!(
+            smt_proof.path,
+            vec![
+                SmtPathNode {
+                    sibling: leaves[0],
+                    is_left: true,
+                },
+                SmtPathNode {
+                    sibling: PoseidonHash::hash_node(&leaves[2], &leaves[3]),
+                    is_left: false,
+                },
+            ]
+        );
+
+        let root = smt_proof.path.iter().fold(smt_proof.leaf, |hash, node| {
+            if node.is_left {
+                PoseidonHash::hash_node(&node.sibling, &hash)
+            } else {
+                PoseidonHash::hash_node(&hash, &node.sibling)
+            }
+        });
+        assert_eq!(root, identity_tree.tree.root());
+
+        let json = serde_json::to_value(&smt_proof)?;
+        assert_eq!(json["path"][0]["is_left"], true);
+        assert_eq!(json["leaf"], serde_json::to_value(leaves[1])?);
+
+        Ok(())
+    }
+
     #[test]
     fn test_inclusion_proof() -> eyre::Result<()> {
         let mut identity_tree = IdentityTree::new(TREE_DEPTH);
@@ -956,6 +2170,66 @@ mod test {
     #[test]
     fn test_construct_proof_from_root() {}
 
+    #[test]
+    fn test_empty_leaf_proof() -> eyre::Result<()> {
+        let mut identity_tree = IdentityTree::new(TREE_DEPTH);
+
+        let leaves: Vec<_> = infinite_leaves().take(2).collect();
+        identity_tree.insert(0, leaves[0])?;
+
+        let proof = identity_tree.empty_leaf_proof(2, None)?;
+        assert_eq!(proof.root, identity_tree.tree.root());
+        assert!(proof.verify(Hash::ZERO));
+
+        // Occupied leaves are rejected
+        assert!(matches!(
+            identity_tree.empty_leaf_proof(0, None),
+            Err(IdentityTreeError::LeafNotEmpty { index: 0, leaf }) if leaf == leaves[0]
+        ));
+
+        assert!(matches!(
+            identity_tree.empty_leaf_proof(NUM_LEAVES as u32, None),
+            Err(IdentityTreeError::InvalidLeafIndex { .. })
+        ));
+
+        // Leaves that are only occupied at a pending root are rejected at that root
+        let root_01 = {
+            let mut tree = CascadingMerkleTree::<PoseidonHash>::new(
+                vec![],
+                TREE_DEPTH,
+                &Hash::ZERO,
+            );
+            tree.push(leaves[0])?;
+            tree.push(leaves[1])?;
+
+            Root {
+                hash: tree.root(),
+                nonce: 1,
+            }
+        };
+
+        identity_tree.append_updates(
+            root_01,
+            LeafUpdates::Insert(
+                vec![(1.into(), leaves[1])]
+                    .into_iter()
+                    .collect::<HashMap<LeafIndex, Hash>>(),
+            ),
+        )?;
+
+        assert!(identity_tree.empty_leaf_proof(1, None)?.verify(Hash::ZERO));
+        assert!(matches!(
+            identity_tree.empty_leaf_proof(1, Some(&root_01)),
+            Err(IdentityTreeError::LeafNotEmpty { index: 1, .. })
+        ));
+
+        let proof = identity_tree.empty_leaf_proof(3, Some(&root_01))?;
+        assert_eq!(proof.root, root_01.hash);
+        assert!(proof.verify(Hash::ZERO));
+
+        Ok(())
+    }
+
     #[test]
     fn test_mmap_cache() -> eyre::Result<()> {
         let path = PathBuf::from("tree_cache");
@@ -0,0 +1,131 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Socket options used when binding the server listeners
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ListenerOptions {
+    /// Sets `SO_REUSEPORT` so that a new instance can bind the same port before the old instance releases it
+    pub reuse_port: bool,
+}
+
+/// Binds the listeners for `addr`.
+///
+/// `SO_REUSEADDR` is always set. When `addr` is the unspecified IPv6 address, the socket accepts both IPv4 and IPv6
+/// connections. On platforms that do not allow disabling `IPV6_V6ONLY`, a second IPv4 listener is bound on the same port.
+pub fn bind_listeners(
+    addr: SocketAddr,
+    options: ListenerOptions,
+) -> io::Result<Vec<TcpListener>> {
+    let dual_stack =
+        matches!(addr, SocketAddr::V6(v6) if v6.ip().is_unspecified());
+
+    let (listener, v6_only) = bind_listener(addr, options, dual_stack)?;
+
+    let mut listeners = vec![listener];
+
+    if dual_stack && v6_only {
+        // Bind to the same port the IPv6 listener got, in case `addr` requested an ephemeral port
+        let port = listeners[0].local_addr()?.port();
+        let v4_addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+
+        tracing::info!(
+            ?v4_addr,
+            "Dual-stack sockets not supported, binding a separate IPv4 listener"
+        );
+
+        let (v4_listener, _) = bind_listener(v4_addr, options, false)?;
+        listeners.push(v4_listener);
+    }
+
+    Ok(listeners)
+}
+
+/// Binds a single listener, returning whether it is restricted to IPv6 connections when `dual_stack` was requested
+fn bind_listener(
+    addr: SocketAddr,
+    options: ListenerOptions,
+    dual_stack: bool,
+) -> io::Result<(TcpListener, bool)> {
+    let socket = Socket::new(
+        Domain::for_address(addr),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+
+    socket.set_reuse_address(true)?;
+
+    if options.reuse_port {
+        set_reuse_port(&socket)?;
+    }
+
+    let v6_only = dual_stack && socket.set_only_v6(false).is_err();
+
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    Ok((socket.into(), v6_only))
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_ephemeral_port() -> eyre::Result<()> {
+        let listeners =
+            bind_listeners("127.0.0.1:0".parse()?, ListenerOptions::default())?;
+
+        assert_eq!(listeners.len(), 1);
+        assert_ne!(listeners[0].local_addr()?.port(), 0);
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_reuse_port_allows_binding_twice() -> eyre::Result<()> {
+        let options = ListenerOptions { reuse_port: true };
+
+        let first = bind_listeners("127.0.0.1:0".parse()?, options)?;
+        let addr = first[0].local_addr()?;
+
+        let second = bind_listeners(addr, options)?;
+        assert_eq!(second[0].local_addr()?, addr);
+
+        // Without SO_REUSEPORT the port is still taken by the other listeners
+        assert!(bind_listeners(addr, ListenerOptions::default()).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    #[test]
+    fn test_reuse_port_unsupported() -> eyre::Result<()> {
+        let err = bind_listeners(
+            "127.0.0.1:0".parse()?,
+            ListenerOptions { reuse_port: true },
+        )
+        .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        Ok(())
+    }
+}
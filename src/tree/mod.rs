@@ -1,23 +1,36 @@
 pub mod block_scanner;
+pub mod blocklist;
+pub mod cadence;
 pub mod config;
 pub mod error;
+pub mod handoff;
+pub mod health;
 pub mod identity_tree;
+pub mod listener;
+pub mod progress;
+pub mod readiness;
+pub mod redact;
+pub mod replay;
 pub mod service;
+pub mod sink;
+pub mod snapshot;
+pub mod status;
+pub mod subgraph;
 pub mod tree_manager;
 
 use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
 use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use ethers::providers::Middleware;
-use ethers::types::{Log, U256};
-use rayon::iter::{Either, IntoParallelIterator, ParallelIterator};
-use ruint::Uint;
-use semaphore::generic_storage::MmapVec;
-use semaphore::lazy_merkle_tree::LazyMerkleTree;
-use semaphore::merkle_tree::Hasher;
+use ethers::types::{Log, H160};
+use semaphore::generic_storage::{GenericStorage, MmapVec};
+use semaphore::lazy_merkle_tree::{LazyMerkleTree, VersionMarker};
+use semaphore::merkle_tree::{Branch, Hasher};
 use semaphore::poseidon_tree::PoseidonHash;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Receiver;
@@ -25,37 +38,104 @@ use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 use tokio::time::Instant;
 use tracing::instrument;
-
-use self::error::WorldTreeError;
-use self::identity_tree::{IdentityTree, InclusionProof, LeafUpdates, Root};
+use world_tree_macros::instrument_async_methods;
+
+use self::block_scanner::{BlockScanner, ProviderStatus};
+use self::cadence::BatchCadence;
+use self::error::{IdentityTreeError, WorldTreeError};
+use self::identity_tree::{
+    empty_subtree_hashes, IdentityTree, InclusionProof, LeafUpdates,
+    ProofBundle, Root, APPLY_CHUNK_SIZE, DEFAULT_MAX_BATCH_SIZE,
+};
+use self::progress::SyncProgress;
+use self::redact::LoggableIdentity;
+use self::replay::UpdateRecorder;
+use self::sink::{
+    notify_observers, TreeUpdateSummary, UpdateObserver, OBSERVER_TIMEOUT,
+};
+use self::status::{ServiceStatus, StatusTracker};
 use self::tree_manager::{
     extract_identity_updates, BridgedTree, CanonicalTree, TreeManager,
+    BLOCK_SCANNER_SLEEP_TIME,
 };
-use crate::abi::IBridgedWorldID;
+use crate::abi::ContractReader;
 use crate::tree::identity_tree::flatten_leaf_updates;
 
+/// Default capacity of the channels between the tree managers and the tasks applying their updates
+pub const DEFAULT_LOG_QUEUE_DEPTH: usize = 1024;
+/// Number of pending roots sampled by each self-test, in addition to the canonical root
+pub const SELF_TEST_ROOTS: usize = 4;
+/// Number of leaves whose proofs are verified against each sampled root by each self-test
+pub const SELF_TEST_LEAVES: usize = 16;
+/// Number of times the canonical root is compared with the chain before a mismatch is reported
+pub const CONSISTENCY_CHECK_ATTEMPTS: usize = 3;
+/// Number of leaves compared by [`diff_trees`] between checks of whether the remaining leaves of both trees are empty
+const DIFF_EMPTY_CHECK_INTERVAL: usize = 1024;
+/// Interval between checks of the sync progress while waiting for a block to be applied
+const BLOCK_APPLIED_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 pub type PoseidonTree<Version> = LazyMerkleTree<PoseidonHash, Version>;
 pub type Hash = <PoseidonHash as Hasher>::Hash;
 
+/// Work done by [`WorldTree::sync_to_head`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncSummary {
+    /// Number of canonical blocks scanned for events
+    pub blocks_scanned: u64,
+    pub events_processed: usize,
+    /// Number of leaves inserted or deleted by the processed events
+    pub leaves_processed: usize,
+}
+
 /// The `WorldTree` syncs and maintains the state of the onchain Merkle tree representing all unique humans across multiple chains
 /// and is also able to deliver an inclusion proof for a given identity commitment across any tracked chain
-pub struct WorldTree<M: Middleware + 'static> {
+/// The tree is backed by a memory mapped cache file unless built from another storage with [`WorldTree::from_identity_tree`]
+pub struct WorldTree<M: Middleware + 'static, S = MmapVec<Hash>> {
     /// The identity tree is the main data structure that holds the state of the tree including latest roots, leaves, and an in-memory representation of the tree
-    pub identity_tree: Arc<RwLock<IdentityTree<MmapVec<Hash>>>>,
+    pub identity_tree: Arc<RwLock<IdentityTree<S>>>,
+    /// Depth of the identity tree, fixed at construction so that it can be read without locking the tree
+    pub tree_depth: usize,
     /// Responsible for listening to state changes to the tree on mainnet
     pub canonical_tree_manager: TreeManager<M, CanonicalTree>,
     /// Responsible for listening to state changes state changes to bridged WorldIDs
     pub bridged_tree_manager: Vec<TreeManager<M, BridgedTree>>,
     /// Mapping of chain Id -> root hash, representing the latest root for each chain
     pub chain_state: Arc<RwLock<HashMap<u64, Root>>>,
-    /// Flag to indicate if the tree is synced to the latest block on startup. Once the tree is initially synced to the chain tip, this field is set to true
-    pub synced: AtomicBool,
+    /// Current lifecycle state of the service. Transitions from `Syncing` to `Serving` once the tree is initially synced to the chain tip,
+    /// and to `Unhealthy` if any of the tasks keeping the tree up to date exits
+    pub status: Arc<StatusTracker>,
+    /// Capacity of the channels between the tree managers and the tasks applying their updates to the tree
+    pub log_queue_depth: usize,
+    /// Called with a summary of every update applied to the canonical tree, in registration order
+    pub update_observers: Vec<Arc<dyn UpdateObserver>>,
+    /// Interval between self-tests verifying proofs for random leaves and retained roots, disabled if `None`
+    pub self_test_interval: Option<Duration>,
+    /// Number of canonical blocks synced between checks of the canonical root against the chain, disabled if zero
+    pub consistency_check_interval_blocks: u64,
+    /// Times at which recent insertion batches were received, used to estimate when new identities become provable
+    pub batch_cadence: Arc<BatchCadence>,
+    /// File backing the tree, `None` if the tree was not restored from a cache
+    pub cache_file: Option<PathBuf>,
+    /// Number of synthetic identities inserted into an empty tree when no events are found onchain, for local development
+    pub seed_identities: usize,
+    /// Independent provider only used to cross-check the canonical root delivered by the primary provider
+    pub verification_middleware: Option<Arc<M>>,
+    /// Number of canonical batches received between cross-checks against `verification_middleware`
+    pub verification_interval_batches: u64,
+    /// Number of canonical batches received since the service started
+    pub canonical_batches: Arc<AtomicU64>,
+    /// Maximum number of leaves in a single update, larger updates decoded from the chain are rejected as malformed
+    pub max_batch_size: usize,
+    /// Records every canonical update received from the chain for offline [`replay`](replay::replay), disabled if `None`
+    pub update_recorder: Option<Arc<UpdateRecorder>>,
 }
 
 impl<M> WorldTree<M>
 where
     M: Middleware + 'static,
 {
+    /// Restores the tree from `cache`, or creates the cache if it does not exist yet.
+    /// Syncing resumes from the block the canonical tree manager was created with.
     pub fn new(
         tree_depth: usize,
         canonical_tree_manager: TreeManager<M, CanonicalTree>,
@@ -64,16 +144,141 @@ where
     ) -> Result<Self, WorldTreeError<M>> {
         let identity_tree =
             IdentityTree::new_with_cache(tree_depth, cache.to_owned())?;
+        let last_synced_block = canonical_tree_manager
+            .block_scanner
+            .next_block
+            .load(Ordering::SeqCst);
+
+        let mut world_tree = Self::from_identity_tree(
+            tree_depth,
+            identity_tree,
+            last_synced_block,
+            canonical_tree_manager,
+            bridged_tree_manager,
+        )?;
+        world_tree.cache_file = Some(cache.to_owned());
+
+        Ok(world_tree)
+    }
+}
+
+#[instrument_async_methods]
+impl<M, S> WorldTree<M, S>
+where
+    M: Middleware + 'static,
+    S: GenericStorage<Hash> + Send + Sync + 'static,
+{
+    /// Constructs a `WorldTree` from an already built identity tree (e.g. a restored snapshot or a tree populated in tests).
+    /// The canonical tree manager resumes scanning from `last_synced_block`, like the block passed to [`TreeManager::new`],
+    /// bridged tree managers resume from the block they were created with.
+    pub fn from_identity_tree(
+        tree_depth: usize,
+        identity_tree: IdentityTree<S>,
+        last_synced_block: u64,
+        canonical_tree_manager: TreeManager<M, CanonicalTree>,
+        bridged_tree_manager: Vec<TreeManager<M, BridgedTree>>,
+    ) -> Result<Self, WorldTreeError<M>> {
+        let actual = identity_tree.tree.depth();
+        if actual != tree_depth {
+            return Err(WorldTreeError::TreeDepthMismatch {
+                expected: tree_depth,
+                actual,
+            });
+        }
+
+        canonical_tree_manager
+            .block_scanner
+            .next_block
+            .store(last_synced_block, Ordering::SeqCst);
 
         Ok(Self {
             identity_tree: Arc::new(RwLock::new(identity_tree)),
+            tree_depth,
             canonical_tree_manager,
             bridged_tree_manager,
             chain_state: Arc::new(RwLock::new(HashMap::new())),
-            synced: AtomicBool::new(false),
+            status: Arc::new(StatusTracker::new(ServiceStatus::Syncing)),
+            log_queue_depth: DEFAULT_LOG_QUEUE_DEPTH,
+            update_observers: vec![],
+            self_test_interval: None,
+            consistency_check_interval_blocks: 0,
+            batch_cadence: Arc::new(BatchCadence::default()),
+            cache_file: None,
+            seed_identities: 0,
+            verification_middleware: None,
+            verification_interval_batches: 0,
+            canonical_batches: Arc::new(AtomicU64::new(0)),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            update_recorder: None,
         })
     }
 
+    /// Sets the capacity of the channels between the tree managers and the tasks applying their updates.
+    /// Once a channel is full, the tree manager stops scanning for new logs until updates are consumed.
+    pub fn with_log_queue_depth(mut self, log_queue_depth: usize) -> Self {
+        self.log_queue_depth = log_queue_depth;
+        self
+    }
+
+    /// Registers `observer` to be called with a summary of every update applied to the canonical tree, after the
+    /// observers registered before it. An [`UpdatePublisher`](sink::UpdatePublisher) queues summaries for an external sink.
+    pub fn with_update_observer(
+        mut self,
+        observer: Arc<dyn UpdateObserver>,
+    ) -> Self {
+        self.update_observers.push(observer);
+        self
+    }
+
+    /// Sets the interval between self-tests, a zero interval disables them like `None`
+    pub fn with_self_test_interval(
+        mut self,
+        self_test_interval: Option<Duration>,
+    ) -> Self {
+        self.self_test_interval =
+            self_test_interval.filter(|interval| !interval.is_zero());
+        self
+    }
+
+    pub fn with_consistency_check_interval_blocks(
+        mut self,
+        consistency_check_interval_blocks: u64,
+    ) -> Self {
+        self.consistency_check_interval_blocks =
+            consistency_check_interval_blocks;
+        self
+    }
+
+    pub fn with_seed_identities(mut self, seed_identities: usize) -> Self {
+        self.seed_identities = seed_identities;
+        self
+    }
+
+    /// Sets the maximum number of leaves in a single update, larger updates decoded from the chain are rejected as malformed.
+    /// The limit is passed to the identity tree once syncing starts.
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// Records every canonical update received from the chain with `recorder`, starting with the updates of the initial sync
+    pub fn with_update_recorder(mut self, recorder: UpdateRecorder) -> Self {
+        self.update_recorder = Some(Arc::new(recorder));
+        self
+    }
+
+    /// Cross-checks the canonical root against `middleware` every `interval_batches` canonical batches.
+    /// The provider is never used to sync the tree, so it only serves a handful of requests.
+    pub fn with_verification_provider(
+        mut self,
+        middleware: Arc<M>,
+        interval_batches: u64,
+    ) -> Self {
+        self.verification_middleware = Some(middleware);
+        self.verification_interval_batches = interval_batches;
+        self
+    }
+
     /// Spawns tasks to synchronize the state of the world tree and listen for state changes across all chains
     pub async fn spawn(
         &self,
@@ -90,9 +295,9 @@ where
         );
 
         let (leaf_updates_tx, leaf_updates_rx) =
-            tokio::sync::mpsc::channel(100);
+            tokio::sync::mpsc::channel(self.log_queue_depth);
         let (bridged_root_tx, bridged_root_rx) =
-            tokio::sync::mpsc::channel(100);
+            tokio::sync::mpsc::channel(self.log_queue_depth);
 
         // Spawn the tree managers to listen to the canonical and bridged trees for updates
         let mut handles = vec![];
@@ -111,9 +316,214 @@ where
         // Spawn a task to handle canonical updates, appending new identity updates to `pending_updates` as they arrive
         handles.push(self.handle_canonical_updates(leaf_updates_rx));
 
+        if let Some(self_test_interval) = self.self_test_interval {
+            handles.push(self.spawn_self_test(self_test_interval));
+        }
+
+        if self.consistency_check_interval_blocks > 0 {
+            handles.push(self.spawn_consistency_check(
+                self.consistency_check_interval_blocks,
+            ));
+        }
+
+        if let Some(middleware) = &self.verification_middleware {
+            if self.verification_interval_batches > 0 {
+                handles.push(self.spawn_verification_check(
+                    middleware.clone(),
+                    self.verification_interval_batches,
+                ));
+            }
+        }
+
         Ok(handles)
     }
 
+    /// Spawns a task checking the canonical root against the chain every `interval_blocks` synced blocks
+    fn spawn_consistency_check(
+        &self,
+        interval_blocks: u64,
+    ) -> JoinHandle<Result<(), WorldTreeError<M>>> {
+        let identity_manager = ContractReader::new(
+            self.canonical_tree_manager.address,
+            self.canonical_tree_manager.block_scanner.middleware.clone(),
+        );
+        let chain_id = self.canonical_tree_manager.chain_id;
+        let block_scanner = self.canonical_tree_manager.block_scanner.clone();
+        let chain_state = self.chain_state.clone();
+
+        tokio::spawn(async move {
+            let mut last_checked_block = block_scanner.last_synced_block();
+
+            loop {
+                tokio::time::sleep(Duration::from_secs(
+                    BLOCK_SCANNER_SLEEP_TIME,
+                ))
+                .await;
+
+                let last_synced_block = block_scanner.last_synced_block();
+                if last_synced_block < last_checked_block + interval_blocks {
+                    continue;
+                }
+                last_checked_block = last_synced_block;
+
+                match verify_canonical_root(
+                    &identity_manager,
+                    chain_id,
+                    &block_scanner,
+                    &chain_state,
+                )
+                .await
+                {
+                    Ok(()) => {
+                        tracing::debug!(
+                            block = last_synced_block,
+                            "Canonical root matches the chain"
+                        );
+                    }
+                    Err(err @ WorldTreeError::RootMismatch { .. }) => {
+                        tracing::error!(%err, "Canonical root diverged from the chain");
+                        metrics::increment_counter!(
+                            "world_tree_root_mismatch_total"
+                        );
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            ?err,
+                            "Failed to check the canonical root"
+                        );
+                    }
+                }
+            }
+        })
+    }
+
+    /// Spawns a task checking the canonical root against an independent provider every `interval_batches` canonical batches,
+    /// marking the service as unhealthy if the primary provider delivered a root the independent provider does not report
+    fn spawn_verification_check(
+        &self,
+        middleware: Arc<M>,
+        interval_batches: u64,
+    ) -> JoinHandle<Result<(), WorldTreeError<M>>> {
+        let identity_manager = ContractReader::new(
+            self.canonical_tree_manager.address,
+            middleware,
+        );
+        let chain_id = self.canonical_tree_manager.chain_id;
+        let block_scanner = self.canonical_tree_manager.block_scanner.clone();
+        let chain_state = self.chain_state.clone();
+        let canonical_batches = self.canonical_batches.clone();
+        let status = self.status.clone();
+
+        tokio::spawn(async move {
+            let mut last_checked_batch =
+                canonical_batches.load(Ordering::SeqCst);
+
+            loop {
+                tokio::time::sleep(Duration::from_secs(
+                    BLOCK_SCANNER_SLEEP_TIME,
+                ))
+                .await;
+
+                let batches = canonical_batches.load(Ordering::SeqCst);
+                if batches < last_checked_batch + interval_batches {
+                    continue;
+                }
+                last_checked_batch = batches;
+
+                match verify_canonical_root(
+                    &identity_manager,
+                    chain_id,
+                    &block_scanner,
+                    &chain_state,
+                )
+                .await
+                {
+                    Ok(()) => {
+                        tracing::debug!(
+                            batches,
+                            "Canonical root matches the verification provider"
+                        );
+                    }
+                    Err(err @ WorldTreeError::RootMismatch { .. }) => {
+                        tracing::error!(%err, "Canonical root does not match the verification provider, the primary provider may be faulty");
+                        metrics::increment_counter!(
+                            "world_tree_verification_mismatch_total"
+                        );
+                        status.set(ServiceStatus::Unhealthy);
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            ?err,
+                            "Failed to check the canonical root against the verification provider"
+                        );
+                    }
+                }
+            }
+        })
+    }
+
+    /// Checks that the latest canonical root matches the `latestRoot` of the identity manager at the last synced block
+    pub async fn verify_integrity(&self) -> Result<(), WorldTreeError<M>> {
+        let identity_manager = ContractReader::new(
+            self.canonical_tree_manager.address,
+            self.canonical_tree_manager.block_scanner.middleware.clone(),
+        );
+
+        verify_canonical_root(
+            &identity_manager,
+            self.canonical_tree_manager.chain_id,
+            &self.canonical_tree_manager.block_scanner,
+            &self.chain_state,
+        )
+        .await
+    }
+
+    /// Spawns a task periodically verifying proofs for a bounded sample of leaves and retained roots,
+    /// marking the service as unhealthy if any proof does not verify
+    fn spawn_self_test(
+        &self,
+        interval: Duration,
+    ) -> JoinHandle<Result<(), WorldTreeError<M>>> {
+        let identity_tree = self.identity_tree.clone();
+        let status = self.status.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            // The first tick completes immediately, skip it so that the first test runs after a full interval
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+
+                let identity_tree = identity_tree.clone().read_owned().await;
+                let result = tokio::task::spawn_blocking(move || {
+                    identity_tree.self_test(
+                        &mut rand::thread_rng(),
+                        SELF_TEST_ROOTS,
+                        SELF_TEST_LEAVES,
+                    )
+                })
+                .await;
+
+                match result {
+                    Ok(Ok(())) => {
+                        metrics::increment_counter!("world_tree_self_test", "result" => "pass");
+                    }
+                    Ok(Err(err)) => {
+                        tracing::error!(%err, "Self-test failed, the tree may be corrupted");
+                        metrics::increment_counter!("world_tree_self_test", "result" => "fail");
+                        status.set(ServiceStatus::Unhealthy);
+                    }
+                    Err(err) => {
+                        tracing::error!(?err, "Self-test panicked");
+                        metrics::increment_counter!("world_tree_self_test", "result" => "fail");
+                        status.set(ServiceStatus::Unhealthy);
+                    }
+                }
+            }
+        })
+    }
+
     /// All updates are added to `pending_updates` and the mainnet root is updated with the latest root
     fn handle_canonical_updates(
         &self,
@@ -137,18 +547,31 @@ where
         let canonical_chain_id = self.canonical_tree_manager.chain_id;
         let identity_tree = self.identity_tree.clone();
         let chain_state = self.chain_state.clone();
+        let batch_cadence = self.batch_cadence.clone();
+        let canonical_batches = self.canonical_batches.clone();
+        let update_recorder = self.update_recorder.clone();
 
         // If we are monitoring bridged chains, apply canonical updates to tree updates until the root is bridged to all chains
-        tokio::spawn(async move {
+        tokio::spawn(unhealthy_on_exit(self.status.clone(), async move {
             while let Some((new_root, leaf_updates)) =
                 leaf_updates_rx.recv().await
             {
+                metrics::decrement_gauge!("world_tree_log_queue_depth", 1.0, "queue" => "leaf_updates");
+                if let LeafUpdates::Insert(leaves) = &leaf_updates {
+                    batch_cadence.record_now(leaves.len());
+                }
                 tracing::info!(
                     ?new_root,
                     "Leaf updates received, appending tree updates"
                 );
+                record_update(
+                    update_recorder.as_deref(),
+                    new_root.hash,
+                    &leaf_updates,
+                );
                 let mut identity_tree = identity_tree.write().await;
 
+                // A rejected batch can't be skipped, since every later root builds on it
                 identity_tree.append_updates(new_root, leaf_updates)?;
 
                 // Update the root for the canonical chain
@@ -156,10 +579,12 @@ where
                     .write()
                     .await
                     .insert(canonical_chain_id, new_root);
+                canonical_batches.fetch_add(1, Ordering::SeqCst);
+                metrics::increment_counter!("world_tree_events_processed_total", "chain_id" => canonical_chain_id.to_string());
             }
 
             Err(WorldTreeError::LeafChannelClosed)
-        })
+        }))
     }
 
     // Applies canonical updates to the tree as they arrive
@@ -171,48 +596,61 @@ where
         let identity_tree = self.identity_tree.clone();
         let chain_state: Arc<RwLock<HashMap<u64, Root>>> =
             self.chain_state.clone();
+        let update_observers = self.update_observers.clone();
+        let batch_cadence = self.batch_cadence.clone();
+        let canonical_batches = self.canonical_batches.clone();
+        let update_recorder = self.update_recorder.clone();
 
-        tokio::spawn(async move {
+        tokio::spawn(unhealthy_on_exit(self.status.clone(), async move {
             while let Some((new_root, leaf_updates)) =
                 leaf_updates_rx.recv().await
             {
+                metrics::decrement_gauge!("world_tree_log_queue_depth", 1.0, "queue" => "leaf_updates");
+                if let LeafUpdates::Insert(leaves) = &leaf_updates {
+                    batch_cadence.record_now(leaves.len());
+                }
                 tracing::info!(
                     ?new_root,
                     "Leaf updates received, applying to the canonical tree"
                 );
+                record_update(
+                    update_recorder.as_deref(),
+                    new_root.hash,
+                    &leaf_updates,
+                );
 
-                match leaf_updates {
-                    LeafUpdates::Insert(leaves) => {
-                        let mut identity_tree = identity_tree.write().await;
-
-                        // Sort the leaf updates by index
-                        let mut leaves = leaves
-                            .into_iter()
-                            .map(|(idx, hash)| (idx.0, hash))
-                            .collect::<Vec<_>>();
-
-                        leaves.sort_by_key(|(idx, _)| *idx);
-
-                        identity_tree.extend_from_slice(&leaves);
-                    }
-                    LeafUpdates::Delete(leaves) => {
-                        let mut identity_tree = identity_tree.write().await;
-
-                        for (leaf_idx, _) in leaves {
-                            identity_tree.remove(leaf_idx.0 as usize);
-                        }
-                    }
-                }
+                let previous_root = identity_tree.read().await.tree.root();
+
+                // Applied in chunks so that a large batch does not monopolize a worker thread
+                IdentityTree::apply_leaf_updates_chunked(
+                    &identity_tree,
+                    leaf_updates,
+                    APPLY_CHUNK_SIZE,
+                )
+                .await?;
+
+                let identity_tree = identity_tree.read().await;
+                let summary = TreeUpdateSummary::new(
+                    previous_root,
+                    identity_tree.tree.root(),
+                    identity_tree.tree.num_leaves(),
+                );
+                drop(identity_tree);
 
-                // Update the root for the canonical chain
+                // Update the root for the canonical chain before observers run, so that they see the updated state
                 chain_state
                     .write()
                     .await
                     .insert(canonical_chain_id, new_root);
+                canonical_batches.fetch_add(1, Ordering::SeqCst);
+                metrics::increment_counter!("world_tree_events_processed_total", "chain_id" => canonical_chain_id.to_string());
+
+                notify_observers(&update_observers, &summary, OBSERVER_TIMEOUT)
+                    .await;
             }
 
             Err(WorldTreeError::LeafChannelClosed)
-        })
+        }))
     }
 
     /// Spawns a task to handle updates to the bridged trees
@@ -223,19 +661,20 @@ where
     ) -> JoinHandle<Result<(), WorldTreeError<M>>> {
         let identity_tree = self.identity_tree.clone();
         let chain_state = self.chain_state.clone();
+        let update_observers = self.update_observers.clone();
 
-        tokio::spawn(async move {
+        tokio::spawn(unhealthy_on_exit(self.status.clone(), async move {
             while let Some((chain_id, bridged_root)) =
                 bridged_root_rx.recv().await
             {
+                metrics::decrement_gauge!("world_tree_log_queue_depth", 1.0, "queue" => "bridged_roots");
                 tracing::info!(?chain_id, root = ?bridged_root, "Bridged root received");
 
                 let mut identity_tree = identity_tree.write().await;
-                // We can use expect here because the root will always be in tree updates before the root is bridged to other chains
-                let root_nonce = identity_tree
-                    .roots
-                    .get(&bridged_root)
-                    .expect("Could not get root update");
+                // The root is in tree updates before it is bridged to other chains, unless the canonical task failed to append it
+                let root_nonce = identity_tree.roots.get(&bridged_root).ok_or(
+                    IdentityTreeError::RootNotFound { root: bridged_root },
+                )?;
                 let new_root = Root {
                     hash: bridged_root,
                     nonce: *root_nonce,
@@ -257,6 +696,7 @@ where
 
                 // If only one chain contains the oldest root the root update is for the oldest chain
                 // apply the updates to the oldest root
+                let mut summary = None;
                 if oldest_chain_ids.len() == 1
                     && chain_id == oldest_chain_ids[0]
                 {
@@ -266,15 +706,34 @@ where
                         "Applying updates to the canonical tree"
                     );
 
+                    let previous_root = identity_tree.tree.root();
                     identity_tree.apply_updates_to_root(oldest_root);
+
+                    summary = Some(TreeUpdateSummary::new(
+                        previous_root,
+                        identity_tree.tree.root(),
+                        identity_tree.tree.num_leaves(),
+                    ));
                 }
 
                 // Update chain state with the new root
                 chain_state.insert(chain_id, new_root);
+                drop(chain_state);
+                drop(identity_tree);
+
+                if let Some(summary) = summary {
+                    notify_observers(
+                        &update_observers,
+                        &summary,
+                        OBSERVER_TIMEOUT,
+                    )
+                    .await;
+                }
+                metrics::increment_counter!("world_tree_events_processed_total", "chain_id" => chain_id.to_string());
             }
 
             Err(WorldTreeError::BridgedRootChannelClosed)
-        })
+        }))
     }
 
     /// Fetches the latest root for all bridged chains
@@ -287,7 +746,7 @@ where
             self.bridged_tree_manager
                 .iter()
                 .map(|tree_manager| async move {
-                    let bridged_world_id = IBridgedWorldID::new(
+                    let bridged_world_id = ContractReader::new(
                         tree_manager.address,
                         tree_manager.block_scanner.middleware.clone(),
                     );
@@ -300,10 +759,8 @@ where
                         .map_err(WorldTreeError::MiddlewareError)?
                         .as_u64();
 
-                    let root: U256 = bridged_world_id
-                        .latest_root()
-                        .block(block_number)
-                        .await?;
+                    let root =
+                        bridged_world_id.latest_root(block_number).await?;
 
                     // Set the latest block number for the tree manager
                     tree_manager.block_scanner.next_block.store(
@@ -313,7 +770,7 @@ where
 
                     Result::<_, WorldTreeError<M>>::Ok((
                         tree_manager.chain_id,
-                        Uint::<256, 4>::from_limbs(root.0),
+                        root,
                     ))
                 });
 
@@ -334,11 +791,22 @@ where
     }
 
     /// Syncs the world tree to the latest block on mainnet, updating the canonical tree and bridged trees from identity updates extracted from logs
-    #[instrument(skip(self))]
-    pub async fn sync_to_head(&self) -> Result<(), WorldTreeError<M>> {
+    pub async fn sync_to_head(&self) -> Result<SyncSummary, WorldTreeError<M>> {
+        self.identity_tree.write().await.max_batch_size = self.max_batch_size;
+
+        let next_block = &self.canonical_tree_manager.block_scanner.next_block;
+        let first_block = next_block.load(Ordering::SeqCst);
+
+        if self.update_recorder.is_some()
+            && self.identity_tree.read().await.tree.num_leaves() > 0
+        {
+            tracing::warn!("Recording updates on top of a restored tree, the recording can't be replayed from an empty tree");
+        }
+
         // Get logs from the canonical tree on mainnet
         tracing::info!("Getting canonical logs");
         let logs = self.get_canonical_logs().await?;
+        let no_events = logs.is_empty();
 
         tracing::info!("Extracting identity updates from logs");
         // Extract identity updates from the logs and build the tree from the updates
@@ -348,23 +816,161 @@ where
         )
         .await?;
 
+        for (root, leaf_updates) in &identity_updates {
+            record_update(
+                self.update_recorder.as_deref(),
+                root.hash,
+                leaf_updates,
+            );
+        }
+
+        let leaves_processed = identity_updates
+            .values()
+            .map(|updates| match updates {
+                LeafUpdates::Insert(leaves) | LeafUpdates::Delete(leaves) => {
+                    leaves.len()
+                }
+            })
+            .sum();
+
         self.build_tree_from_updates(identity_updates).await?;
 
-        self.synced.store(true, Ordering::SeqCst);
+        if no_events && self.seed_identities > 0 {
+            self.seed_synthetic_identities().await?;
+        }
+
+        self.status.set(ServiceStatus::Serving);
+
+        Ok(SyncSummary {
+            blocks_scanned: next_block
+                .load(Ordering::SeqCst)
+                .saturating_sub(first_block),
+            events_processed: logs.len(),
+            leaves_processed,
+        })
+    }
+
+    /// Inserts `seed_identities` synthetic identity commitments into an empty tree and sets the latest root of every chain
+    /// to the resulting root. The seeded tree does not match the chain, so this is only meant for local development.
+    async fn seed_synthetic_identities(&self) -> Result<(), WorldTreeError<M>> {
+        let mut identity_tree = self.identity_tree.write().await;
+
+        if identity_tree.tree.num_leaves() > 0 {
+            tracing::warn!(
+                "Tree is not empty, skipping seeding synthetic identities"
+            );
+            return Ok(());
+        }
+
+        let capacity = identity_tree.capacity();
+        let num_identities = u32::try_from(self.seed_identities)
+            .ok()
+            .filter(|num_identities| *num_identities as usize <= capacity)
+            .ok_or(IdentityTreeError::InvalidLeafRange {
+                start: 0,
+                count: self.seed_identities,
+                capacity,
+            })?;
+
+        // Commitments start at one since zero is the empty leaf
+        let leaves = (1..=num_identities)
+            .map(|i| (LeafIndex::from(i - 1), Hash::from(i)))
+            .collect();
+        identity_tree.apply_leaf_updates(LeafUpdates::Insert(leaves))?;
+
+        let root = Root {
+            hash: identity_tree.tree.root(),
+            nonce: 0,
+        };
+        for chain_root in self.chain_state.write().await.values_mut() {
+            *chain_root = root;
+        }
+
+        tracing::info!(
+            num_identities = self.seed_identities,
+            root = ?root.hash,
+            "Seeded tree with synthetic identities"
+        );
 
         Ok(())
     }
 
+    /// Whether every canonical update emitted up to `block` has been applied to the tree
+    pub fn canonical_block_applied(&self, block: u64) -> bool {
+        let queue_progress = &self.canonical_tree_manager.queue_progress;
+
+        // The block is loaded first since updates are counted as sent before the block they were found in is recorded
+        queue_progress.sent_through_block.load(Ordering::SeqCst) >= block
+            && self.canonical_batches.load(Ordering::SeqCst)
+                >= queue_progress.sent.load(Ordering::SeqCst)
+    }
+
+    /// Waits up to `timeout` for every canonical update emitted up to `block` to be applied to the tree, returning whether
+    /// the block was reached. Returns at once if `block` is past the head last observed from the canonical provider,
+    /// since it can't be reached before the next poll of the chain.
+    pub async fn wait_for_canonical_block(
+        &self,
+        block: u64,
+        timeout: Duration,
+    ) -> bool {
+        let head_block = self
+            .canonical_tree_manager
+            .block_scanner
+            .head_block
+            .load(Ordering::SeqCst);
+        if block > head_block {
+            return false;
+        }
+
+        let wait = async {
+            while !self.canonical_block_applied(block) {
+                tokio::time::sleep(BLOCK_APPLIED_POLL_INTERVAL).await;
+            }
+        };
+
+        tokio::time::timeout(timeout, wait).await.is_ok()
+    }
+
+    /// Returns the last block scanned for each monitored chain, keyed by chain ID
+    pub fn last_synced_blocks(&self) -> BTreeMap<u64, u64> {
+        self.provider_statuses()
+            .into_iter()
+            .map(|(chain_id, status)| (chain_id, status.last_synced_block))
+            .collect()
+    }
+
+    /// Returns the chain head observed from the provider of each monitored chain and the last block processed from it, keyed by chain ID
+    pub fn provider_statuses(&self) -> BTreeMap<u64, ProviderStatus> {
+        let canonical = (
+            self.canonical_tree_manager.chain_id,
+            &self.canonical_tree_manager.block_scanner,
+        );
+        let bridged = self.bridged_tree_manager.iter().map(|tree_manager| {
+            (tree_manager.chain_id, &tree_manager.block_scanner)
+        });
+
+        std::iter::once(canonical)
+            .chain(bridged)
+            .map(|(chain_id, block_scanner)| {
+                (chain_id, block_scanner.provider_status())
+            })
+            .collect()
+    }
+
     async fn get_canonical_logs(&self) -> Result<Vec<Log>, WorldTreeError<M>> {
         let identity_tree = self.identity_tree.read().await;
 
         // Get all logs from the mainnet tree starting from the last synced block, up to the chain tip
-        let all_logs = self
-            .canonical_tree_manager
-            .block_scanner
-            .next()
+        let block_scanner = &self.canonical_tree_manager.block_scanner;
+        let mut progress =
+            SyncProgress::new(block_scanner.next_block.load(Ordering::SeqCst));
+        let all_logs = block_scanner
+            .next_with_progress(|block, head_block| {
+                progress.update(block, head_block)
+            })
             .await
             .map_err(WorldTreeError::MiddlewareError)?;
+        progress.finish();
         if all_logs.is_empty() {
             return Err(WorldTreeError::CanonicalLogsNotFound);
         }
@@ -493,59 +1099,33 @@ where
     }
 
     /// Builds the canonical tree from identity updates
+    #[instrument(skip(self, identity_updates))]
     pub async fn build_canonical_tree(
         &self,
         identity_updates: BTreeMap<Root, LeafUpdates>,
     ) -> Result<(), WorldTreeError<M>> {
         let mut identity_tree = self.identity_tree.write().await;
 
-        // Flatten the leaves and build the canonical tree
-        let flattened_leaves = flatten_leaf_updates(identity_updates);
-
-        // Update the latest leaves
-        for (idx, hash) in flattened_leaves.iter() {
-            if hash != &Hash::ZERO {
-                identity_tree.leaves.insert(*hash, idx.into());
-            } else {
-                identity_tree.leaves.remove(hash);
-            }
+        for leaf_updates in identity_updates.values() {
+            identity_tree.validate_updates(leaf_updates)?;
         }
 
-        // Build the tree from leaves
-        tracing::info!(num_new_leaves = ?flattened_leaves.len(), "Building the canonical tree");
-
-        // If the tree is empty, we insert all of the canonical leaf updates
-        if identity_tree.tree.num_leaves() == 0 {
-            let canonical_leaves = flattened_leaves
-                .iter()
-                .map(|(_, hash)| *hash)
-                .collect::<Vec<_>>();
+        // Flatten the leaves and build the canonical tree
+        let flattened_leaves = flatten_leaf_updates(identity_updates)
+            .into_iter()
+            .map(|(idx, hash)| (idx.0, hash))
+            .collect::<Vec<_>>();
 
-            identity_tree.tree.extend_from_slice(&canonical_leaves);
-        } else {
-            // If the tree is already populated, we insert the new insertions
-            // and then apply the deletions by setting the leaf idx to Hash::ZERO
-
-            // Sort leaf updates into insertions and deletions
-            let (insertions, deletions): (Vec<Hash>, Vec<usize>) =
-                flattened_leaves.into_par_iter().partition_map(
-                    |(leaf_idx, value)| {
-                        if value != Hash::ZERO {
-                            Either::Left(value)
-                        } else {
-                            Either::Right(leaf_idx.0 as usize)
-                        }
-                    },
-                );
+        tracing::info!(num_new_leaves = ?flattened_leaves.len(), "Building the canonical tree");
+        let start = Instant::now();
 
-            // Extend the tree with the new insertions
-            identity_tree.tree.extend_from_slice(&insertions);
+        identity_tree.build_from_sorted_leaves(&flattened_leaves);
 
-            // Update the tree with the deletions
-            for leaf_idx in deletions {
-                identity_tree.tree.set_leaf(leaf_idx, Hash::ZERO);
-            }
-        }
+        tracing::info!(
+            num_leaves = identity_tree.tree.num_leaves(),
+            elapsed = ?start.elapsed(),
+            "Built the canonical tree"
+        );
 
         Ok(())
     }
@@ -586,15 +1166,45 @@ where
         }
     }
 
+    /// Returns the root of the canonical tree, i.e. the latest root bridged to all chains.
+    /// The root is cached by the tree, so this only holds the read lock long enough to copy it,
+    /// but it waits for any update being applied to the tree to complete.
+    pub async fn current_root(&self) -> Hash {
+        self.identity_tree.read().await.tree.root()
+    }
+
+    /// Returns the identity commitments stored at leaf indices `[start, start + count)` of the canonical tree,
+    /// skipping empty leaves
+    pub async fn find_identities_in_range(
+        &self,
+        start: usize,
+        count: usize,
+    ) -> Result<Vec<Hash>, WorldTreeError<M>> {
+        let identities = self
+            .identity_tree
+            .read()
+            .await
+            .find_identities_in_range(start, count)?;
+
+        Ok(identities)
+    }
+
     /// Returns an inclusion proof for a given identity commitment.
     /// If a chain ID is provided, the proof is generated for the given chain.
+    /// If a maximum leaf index is provided, identities inserted at a higher index are refused with `LeafAfterCutoff`.
+    #[instrument(
+        skip(self, identity_commitment),
+        fields(identity_commitment = ?LoggableIdentity(identity_commitment))
+    )]
     pub async fn inclusion_proof(
         &self,
         identity_commitment: Hash,
         chain_id: Option<ChainId>,
+        max_leaf_index: Option<u32>,
     ) -> Result<Option<InclusionProof>, WorldTreeError<M>> {
-        if !self.synced.load(Ordering::SeqCst) {
-            return Err(WorldTreeError::TreeNotSynced);
+        let status = self.status.get();
+        if status != ServiceStatus::Serving {
+            return Err(WorldTreeError::ServiceUnavailable(status));
         }
 
         let chain_state = self.chain_state.read().await;
@@ -602,32 +1212,131 @@ where
         let root = if let Some(chain_id) = chain_id {
             let root = chain_state
                 .get(&chain_id)
-                .ok_or(WorldTreeError::ChainIdNotFound)?;
+                .ok_or(WorldTreeError::ChainIdNotFound { chain_id })?;
 
             Some(root)
         } else {
             None
         };
 
-        let inclusion_proof = self
+        let identity_tree = self.identity_tree.read().await;
+        if let Some(max_leaf_index) = max_leaf_index {
+            identity_tree
+                .check_max_leaf_index(identity_commitment, max_leaf_index)?;
+        }
+        let inclusion_proof =
+            identity_tree.inclusion_proof(identity_commitment, root)?;
+
+        Ok(inclusion_proof)
+    }
+
+    /// Returns an inclusion proof for a given identity commitment along with the leaf index, the flattened siblings and the
+    /// contract holding the proof root, i.e. everything needed to verify the proof onchain.
+    /// If a chain ID is provided, the proof is generated for the given chain, otherwise for the canonical chain.
+    #[instrument(
+        skip(self, identity_commitment),
+        fields(identity_commitment = ?LoggableIdentity(identity_commitment))
+    )]
+    pub async fn proof_bundle(
+        &self,
+        identity_commitment: Hash,
+        chain_id: Option<ChainId>,
+    ) -> Result<Option<ProofBundle>, WorldTreeError<M>> {
+        let status = self.status.get();
+        if status != ServiceStatus::Serving {
+            return Err(WorldTreeError::ServiceUnavailable(status));
+        }
+
+        let chain_id =
+            chain_id.unwrap_or(ChainId(self.canonical_tree_manager.chain_id));
+        let contract_address = self
+            .contract_address(chain_id)
+            .ok_or(WorldTreeError::ChainIdNotFound { chain_id })?;
+
+        let chain_state = self.chain_state.read().await;
+        let root = chain_state
+            .get(&chain_id)
+            .ok_or(WorldTreeError::ChainIdNotFound { chain_id })?;
+
+        // Look the proof and leaf index up under the same lock so that they are consistent
+        let identity_tree = self.identity_tree.read().await;
+        let Some(inclusion_proof) =
+            identity_tree.inclusion_proof(identity_commitment, Some(root))?
+        else {
+            return Ok(None);
+        };
+        let leaf_index = identity_tree.leaves[&identity_commitment];
+
+        Ok(Some(ProofBundle::new(
+            identity_commitment,
+            leaf_index,
+            inclusion_proof,
+            contract_address,
+            *chain_id,
+        )))
+    }
+
+    /// Address of the identity manager or bridged World ID contract on `chain_id`
+    fn contract_address(&self, chain_id: ChainId) -> Option<H160> {
+        if *chain_id == self.canonical_tree_manager.chain_id {
+            return Some(self.canonical_tree_manager.address);
+        }
+
+        self.bridged_tree_manager
+            .iter()
+            .find(|tree_manager| tree_manager.chain_id == *chain_id)
+            .map(|tree_manager| tree_manager.address)
+    }
+
+    /// Returns a proof for the empty leaf at `leaf_idx`, for use as a non-inclusion artifact.
+    /// If a chain ID is provided, the proof is constructed from the latest root on the specified chain.
+    /// If no chain ID is provided, the proof is constructed from the latest root bridged to all chains.
+    pub async fn empty_leaf_proof(
+        &self,
+        leaf_idx: u32,
+        chain_id: Option<ChainId>,
+    ) -> Result<InclusionProof, WorldTreeError<M>> {
+        let status = self.status.get();
+        if status != ServiceStatus::Serving {
+            return Err(WorldTreeError::ServiceUnavailable(status));
+        }
+
+        let chain_state = self.chain_state.read().await;
+
+        let root = if let Some(chain_id) = chain_id {
+            let root = chain_state
+                .get(&chain_id)
+                .ok_or(WorldTreeError::ChainIdNotFound { chain_id })?;
+
+            Some(root)
+        } else {
+            None
+        };
+
+        let proof = self
             .identity_tree
             .read()
             .await
-            .inclusion_proof(identity_commitment, root)?;
+            .empty_leaf_proof(leaf_idx, root)?;
 
-        Ok(inclusion_proof)
+        Ok(proof)
     }
 
     /// Computes the updated root given a set of identity commitments.
     /// If a chain ID is provided, the updated root is calculated from the latest root on the specified chain.
     /// If no chain ID is provided, the updated root is calculated from the latest root bridged to all chains.
+    #[instrument(
+        skip(self, identity_commitements),
+        fields(num_identities = identity_commitements.len())
+    )]
     pub async fn compute_root(
         &self,
         identity_commitements: &[Hash],
         chain_id: Option<ChainId>,
     ) -> Result<Hash, WorldTreeError<M>> {
-        if !self.synced.load(Ordering::SeqCst) {
-            return Err(WorldTreeError::TreeNotSynced);
+        let status = self.status.get();
+        if status != ServiceStatus::Serving {
+            return Err(WorldTreeError::ServiceUnavailable(status));
         }
 
         let chain_state = self.chain_state.read().await;
@@ -635,7 +1344,7 @@ where
         let root = if let Some(chain_id) = chain_id {
             let root = chain_state
                 .get(&chain_id)
-                .ok_or(WorldTreeError::ChainIdNotFound)?;
+                .ok_or(WorldTreeError::ChainIdNotFound { chain_id })?;
 
             Some(root)
         } else {
@@ -652,6 +1361,127 @@ where
     }
 }
 
+/// Compares the latest canonical root with the root reported by `identity_manager` at the last synced block.
+///
+/// Logs are applied to the tree asynchronously, so the local root may briefly lag behind the last synced block.
+/// A mismatch is only reported if it persists across several attempts.
+async fn verify_canonical_root<M: Middleware + 'static>(
+    identity_manager: &ContractReader<M>,
+    chain_id: u64,
+    block_scanner: &BlockScanner<M>,
+    chain_state: &RwLock<HashMap<u64, Root>>,
+) -> Result<(), WorldTreeError<M>> {
+    let mut attempt = 1;
+    loop {
+        let block = block_scanner.last_synced_block();
+        let onchain = identity_manager.latest_root(block).await?;
+
+        let local = chain_state
+            .read()
+            .await
+            .get(&chain_id)
+            .map(|root| root.hash)
+            .unwrap_or_default();
+
+        if local == onchain {
+            return Ok(());
+        }
+
+        if attempt == CONSISTENCY_CHECK_ATTEMPTS {
+            return Err(WorldTreeError::RootMismatch {
+                block,
+                onchain,
+                local,
+            });
+        }
+
+        attempt += 1;
+        tokio::time::sleep(Duration::from_secs(BLOCK_SCANNER_SLEEP_TIME)).await;
+    }
+}
+
+/// Appends an update to the recording if enabled. A failed write is logged rather than stopping the sync, replaying the
+/// recording then diverges at the missing update.
+fn record_update(
+    recorder: Option<&UpdateRecorder>,
+    root: Hash,
+    leaf_updates: &LeafUpdates,
+) {
+    if let Some(recorder) = recorder {
+        if let Err(error) = recorder.record(root, leaf_updates) {
+            tracing::error!(?error, ?root, "Failed to record update");
+        }
+    }
+}
+
+/// Marks the service as unhealthy once `task` exits, since tasks keeping the tree up to date are expected to run for the lifetime of the service
+async fn unhealthy_on_exit<T>(
+    status: Arc<StatusTracker>,
+    task: impl Future<Output = T>,
+) -> T {
+    let output = task.await;
+    status.set(ServiceStatus::Unhealthy);
+    output
+}
+
+/// Leaves that differ between two trees of the same depth, as `(index, leaf in a, leaf in b)` in increasing index order.
+/// The scan stops once the remaining leaves of both trees are empty, so the cost depends on the number of leaves
+/// inserted rather than the capacity of the trees.
+pub fn diff_trees<V: VersionMarker>(
+    a: &PoseidonTree<V>,
+    b: &PoseidonTree<V>,
+) -> Result<Vec<(usize, Hash, Hash)>, IdentityTreeError> {
+    if a.depth() != b.depth() {
+        return Err(IdentityTreeError::DepthMismatch {
+            left: a.depth(),
+            right: b.depth(),
+        });
+    }
+
+    let mut diff = vec![];
+    for (index, (a_leaf, b_leaf)) in a.leaves().zip(b.leaves()).enumerate() {
+        if index % DIFF_EMPTY_CHECK_INTERVAL == 0
+            && is_empty_from(a, index)
+            && is_empty_from(b, index)
+        {
+            break;
+        }
+
+        if a_leaf != b_leaf {
+            diff.push((index, a_leaf, b_leaf));
+        }
+    }
+
+    Ok(diff)
+}
+
+/// Whether the leaf at `index` and every leaf after it are empty, i.e. every subtree to the right of the path from the
+/// leaf to the root is an empty subtree
+fn is_empty_from<V: VersionMarker>(
+    tree: &PoseidonTree<V>,
+    index: usize,
+) -> bool {
+    let empty_subtrees = empty_subtree_hashes(tree.depth());
+
+    tree.get_leaf(index) == Hash::ZERO
+        && tree
+            .proof(index)
+            .0
+            .iter()
+            .enumerate()
+            .all(|(height, branch)| match branch {
+                // The sibling is the subtree to the right of the path
+                Branch::Left(sibling) => {
+                    let empty = match height {
+                        0 => Hash::ZERO,
+                        height => empty_subtrees[height - 1],
+                    };
+                    *sibling == empty
+                }
+                Branch::Right(_) => true,
+            })
+}
+
 macro_rules! primitive_newtype {
     (pub struct $outer:ident($tname:ty)) => {
         #[derive(
@@ -710,3 +1540,581 @@ macro_rules! primitive_newtype {
 primitive_newtype!(pub struct ChainId(u64));
 primitive_newtype!(pub struct NodeIndex(u32));
 primitive_newtype!(pub struct LeafIndex(u32));
+
+#[cfg(all(test, feature = "mock-middleware"))]
+mod tests {
+    use ethers::abi::AbiEncode;
+    use ethers::types::{Block, Bytes, Filter, U256};
+    use eyre::ContextCompat;
+    use semaphore::lazy_merkle_tree::Canonical;
+
+    use super::block_scanner::BlockRangeFilter;
+    use super::*;
+    use crate::testing::MockMiddleware;
+
+    const CHAIN_ID: u64 = 1;
+
+    async fn block_scanner() -> eyre::Result<BlockScanner<MockMiddleware>> {
+        let middleware = MockMiddleware::builder().chain_id(CHAIN_ID).build();
+
+        Ok(
+            BlockScanner::new(Arc::new(middleware), 100, 100, Filter::new())
+                .await?,
+        )
+    }
+
+    fn chain_state(root: Hash) -> RwLock<HashMap<u64, Root>> {
+        RwLock::new(HashMap::from([(
+            CHAIN_ID,
+            Root {
+                hash: root,
+                nonce: 1,
+            },
+        )]))
+    }
+
+    fn latest_root_result(root: u64) -> Bytes {
+        U256::from(root).encode().into()
+    }
+
+    #[tokio::test]
+    async fn test_verify_canonical_root() -> eyre::Result<()> {
+        let verification_middleware = MockMiddleware::builder()
+            .call_result(latest_root_result(42))
+            .build();
+        let identity_manager = ContractReader::new(
+            H160::zero(),
+            Arc::new(verification_middleware),
+        );
+
+        verify_canonical_root(
+            &identity_manager,
+            CHAIN_ID,
+            &block_scanner().await?,
+            &chain_state(Hash::from(42)),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_verify_canonical_root_detects_lying_primary(
+    ) -> eyre::Result<()> {
+        // The primary delivered a root that the independent provider never reports
+        let mut builder = MockMiddleware::builder();
+        for _ in 0..CONSISTENCY_CHECK_ATTEMPTS {
+            builder = builder.call_result(latest_root_result(42));
+        }
+        let identity_manager =
+            ContractReader::new(H160::zero(), Arc::new(builder.build()));
+
+        let result = verify_canonical_root(
+            &identity_manager,
+            CHAIN_ID,
+            &block_scanner().await?,
+            &chain_state(Hash::from(7)),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(WorldTreeError::RootMismatch {
+                block: 99,
+                onchain,
+                local,
+            }) if onchain == Hash::from(42) && local == Hash::from(7)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_verification_mismatch_marks_unhealthy() -> eyre::Result<()> {
+        let cache_file = std::env::temp_dir()
+            .join(format!("world-tree-verification-{}", std::process::id()));
+        let mut builder = MockMiddleware::builder();
+        for _ in 0..CONSISTENCY_CHECK_ATTEMPTS {
+            builder = builder.call_result(latest_root_result(42));
+        }
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?;
+        world_tree.status.set(ServiceStatus::Serving);
+
+        let handle =
+            world_tree.spawn_verification_check(Arc::new(builder.build()), 1);
+        // Let the task record the batches received before it was spawned
+        tokio::task::yield_now().await;
+        world_tree.canonical_batches.fetch_add(1, Ordering::SeqCst);
+
+        // Every attempt reports a root other than the one delivered by the primary provider
+        tokio::time::timeout(Duration::from_secs(60), async {
+            while world_tree.status.get() != ServiceStatus::Unhealthy {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        })
+        .await?;
+        handle.abort();
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ensure_creation_block() -> eyre::Result<()> {
+        let middleware = MockMiddleware::builder()
+            .chain_id(CHAIN_ID)
+            .chain_id(CHAIN_ID)
+            .block(Some(Block::default()))
+            .block(None)
+            .build();
+        let tree_manager = TreeManager::<_, CanonicalTree>::new(
+            H160::zero(),
+            100,
+            100,
+            BlockRangeFilter::default(),
+            Arc::new(middleware),
+        )
+        .await?;
+
+        tree_manager.ensure_creation_block(100).await?;
+
+        // A creation block past the chain head is a configuration error
+        let result = tree_manager.ensure_creation_block(1_000_000).await;
+        assert!(matches!(
+            result,
+            Err(WorldTreeError::BlockNotFound(1_000_000))
+        ));
+        assert!(result.unwrap_err().is_config_error());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ensure_tree_depth() -> eyre::Result<()> {
+        let middleware = MockMiddleware::builder()
+            .chain_id(CHAIN_ID)
+            .chain_id(CHAIN_ID)
+            .call_result(U256::from(30).encode().into())
+            .call_result(U256::from(20).encode().into())
+            .build();
+        let tree_manager = TreeManager::<_, CanonicalTree>::new(
+            H160::zero(),
+            100,
+            100,
+            BlockRangeFilter::default(),
+            Arc::new(middleware),
+        )
+        .await?;
+
+        tree_manager.ensure_tree_depth(30).await?;
+
+        // A contract deployed with another depth is a configuration error
+        let result = tree_manager.ensure_tree_depth(30).await;
+        assert!(matches!(
+            result,
+            Err(WorldTreeError::TreeDepthMismatch {
+                expected: 30,
+                actual: 20,
+            })
+        ));
+        assert!(result.unwrap_err().is_config_error());
+
+        Ok(())
+    }
+
+    async fn canonical_tree_manager(
+    ) -> eyre::Result<TreeManager<MockMiddleware, CanonicalTree>> {
+        let middleware = MockMiddleware::builder()
+            .chain_id(CHAIN_ID)
+            .chain_id(CHAIN_ID)
+            .build();
+
+        Ok(TreeManager::new(
+            H160::zero(),
+            100,
+            100,
+            BlockRangeFilter::default(),
+            Arc::new(middleware),
+        )
+        .await?)
+    }
+
+    #[tokio::test]
+    async fn test_ensure_chain_id() -> eyre::Result<()> {
+        let tree_manager = canonical_tree_manager().await?;
+
+        tree_manager.ensure_chain_id(Some(CHAIN_ID))?;
+        tree_manager.ensure_chain_id(None)?;
+
+        // A provider connected to another network is a configuration error
+        let result = tree_manager.ensure_chain_id(Some(11155111));
+        assert!(matches!(
+            result,
+            Err(WorldTreeError::ChainIdMismatch {
+                expected: 11155111,
+                actual: CHAIN_ID,
+                ..
+            })
+        ));
+        assert!(result.unwrap_err().is_config_error());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_from_identity_tree() -> eyre::Result<()> {
+        let world_tree = WorldTree::from_identity_tree(
+            20,
+            IdentityTree::new(20),
+            500,
+            canonical_tree_manager().await?,
+            vec![],
+        )?;
+
+        assert_eq!(world_tree.tree_depth, 20);
+        assert_eq!(
+            world_tree
+                .canonical_tree_manager
+                .block_scanner
+                .next_block
+                .load(Ordering::SeqCst),
+            500
+        );
+
+        // A tree built with another depth than the configured one is rejected
+        let result = WorldTree::from_identity_tree(
+            20,
+            IdentityTree::new(10),
+            500,
+            canonical_tree_manager().await?,
+            vec![],
+        );
+        assert!(matches!(
+            result,
+            Err(WorldTreeError::TreeDepthMismatch {
+                expected: 20,
+                actual: 10,
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_trees() -> eyre::Result<()> {
+        let depth = 12;
+        let mut a = PoseidonTree::<Canonical>::new(depth, Hash::ZERO);
+        let mut b = PoseidonTree::<Canonical>::new(depth, Hash::ZERO);
+        assert!(diff_trees(&a, &b)?.is_empty());
+
+        for i in 0..3000 {
+            a = a.update_with_mutation(i, &Hash::from(i + 1));
+            b = b.update_with_mutation(i, &Hash::from(i + 1));
+        }
+        // Differences past the first check interval are found, including leaves only set in one tree
+        b = b.update_with_mutation(1500, &Hash::from(7));
+        a = a.update_with_mutation(3000, &Hash::from(3001));
+
+        assert_eq!(
+            diff_trees(&a, &b)?,
+            vec![
+                (1500, Hash::from(1501), Hash::from(7)),
+                (3000, Hash::from(3001), Hash::ZERO),
+            ]
+        );
+
+        let c = PoseidonTree::<Canonical>::new(depth + 1, Hash::ZERO);
+        assert!(matches!(
+            diff_trees(&a, &c),
+            Err(IdentityTreeError::DepthMismatch {
+                left: 12,
+                right: 13
+            })
+        ));
+
+        Ok(())
+    }
+
+    /// Records each summary along with the canonical root in the chain state when the observer is called
+    struct ChainStateObserver {
+        chain_state: Arc<RwLock<HashMap<u64, Root>>>,
+        tx: tokio::sync::mpsc::UnboundedSender<(
+            TreeUpdateSummary,
+            Option<Root>,
+        )>,
+    }
+
+    #[async_trait::async_trait]
+    impl UpdateObserver for ChainStateObserver {
+        async fn on_update(&self, summary: &TreeUpdateSummary) {
+            let root = self.chain_state.read().await.get(&CHAIN_ID).copied();
+            self.tx.send((summary.clone(), root)).ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_canonical_updates_notifies_observers(
+    ) -> eyre::Result<()> {
+        let cache_file = std::env::temp_dir()
+            .join(format!("world-tree-observers-{}", std::process::id()));
+        let identity = Hash::from(1);
+        let leaf_updates = LeafUpdates::Insert(HashMap::from([(
+            LeafIndex::from(0),
+            identity,
+        )]));
+
+        let mut expected_tree = IdentityTree::new(10);
+        let previous_root = expected_tree.tree.root();
+        expected_tree.apply_leaf_updates(leaf_updates.clone())?;
+        let new_root = Root {
+            hash: expected_tree.tree.root(),
+            nonce: 1,
+        };
+
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?;
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let observer = ChainStateObserver {
+            chain_state: world_tree.chain_state.clone(),
+            tx,
+        };
+        let world_tree = world_tree.with_update_observer(Arc::new(observer));
+
+        let (leaf_updates_tx, leaf_updates_rx) = tokio::sync::mpsc::channel(1);
+        let handle = world_tree.apply_canonical_updates(leaf_updates_rx);
+        leaf_updates_tx.send((new_root, leaf_updates)).await?;
+
+        let (summary, chain_root) =
+            tokio::time::timeout(Duration::from_secs(10), rx.recv())
+                .await?
+                .context("Observer was not called")?;
+        handle.abort();
+
+        assert_eq!(summary.previous_root, previous_root);
+        assert_eq!(summary.root, new_root.hash);
+        assert_eq!(summary.num_leaves, 1);
+        // The chain state is updated before observers are called
+        assert_eq!(chain_root, Some(new_root));
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_canonical_updates_records_updates() -> eyre::Result<()>
+    {
+        let cache_file = std::env::temp_dir()
+            .join(format!("world-tree-recorder-{}", std::process::id()));
+        let recording = std::env::temp_dir().join(format!(
+            "world-tree-recorded-updates-{}",
+            std::process::id()
+        ));
+        let leaf_updates = LeafUpdates::Insert(HashMap::from([(
+            LeafIndex::from(0),
+            Hash::from(1),
+        )]));
+
+        let mut expected_tree = IdentityTree::new(10);
+        expected_tree.apply_leaf_updates(leaf_updates.clone())?;
+        let new_root = Root {
+            hash: expected_tree.tree.root(),
+            nonce: 1,
+        };
+
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?
+                .with_update_recorder(UpdateRecorder::open(&recording)?);
+
+        let (leaf_updates_tx, leaf_updates_rx) = tokio::sync::mpsc::channel(1);
+        let handle = world_tree.apply_canonical_updates(leaf_updates_rx);
+        leaf_updates_tx.send((new_root, leaf_updates)).await?;
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while world_tree.canonical_batches.load(Ordering::SeqCst) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        handle.abort();
+
+        // The recording rebuilds the same tree offline
+        let report = replay::replay(
+            10,
+            std::io::BufReader::new(std::fs::File::open(&recording)?),
+        )?;
+        assert_eq!(report.num_updates, 1);
+        assert_eq!(report.final_root, new_root.hash);
+        assert_eq!(report.divergence, None);
+
+        std::fs::remove_file(&cache_file)?;
+        std::fs::remove_file(&recording)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_seed_identities_exceeding_capacity() -> eyre::Result<()> {
+        let cache_file = std::env::temp_dir()
+            .join(format!("world-tree-seed-{}", std::process::id()));
+        let world_tree =
+            crate::testing::mock_world_tree(2, &cache_file, |builder| builder)
+                .await?
+                .with_seed_identities(5);
+
+        assert!(matches!(
+            world_tree.seed_synthetic_identities().await,
+            Err(WorldTreeError::IdentityTreeError(
+                IdentityTreeError::InvalidLeafRange {
+                    start: 0,
+                    count: 5,
+                    capacity: 4,
+                }
+            ))
+        ));
+        assert_eq!(world_tree.identity_tree.read().await.tree.num_leaves(), 0);
+
+        let world_tree = world_tree.with_seed_identities(4);
+        world_tree.seed_synthetic_identities().await?;
+        assert_eq!(world_tree.identity_tree.read().await.tree.num_leaves(), 4);
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oversized_batch_stops_canonical_updates() -> eyre::Result<()>
+    {
+        let cache_file = std::env::temp_dir()
+            .join(format!("world-tree-oversized-{}", std::process::id()));
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?;
+        world_tree.identity_tree.write().await.max_batch_size = 1;
+        let new_root = Root {
+            hash: Hash::from(42),
+            nonce: 1,
+        };
+
+        let (leaf_updates_tx, leaf_updates_rx) = tokio::sync::mpsc::channel(1);
+        let handle = world_tree.append_canonical_updates(leaf_updates_rx);
+        leaf_updates_tx
+            .send((
+                new_root,
+                LeafUpdates::Insert(HashMap::from([
+                    (LeafIndex::from(0), Hash::from(1)),
+                    (LeafIndex::from(1), Hash::from(2)),
+                ])),
+            ))
+            .await?;
+
+        // The batch is not skipped, the task exits and the service is marked as unhealthy
+        assert!(matches!(
+            handle.await?,
+            Err(WorldTreeError::IdentityTreeError(
+                IdentityTreeError::BatchTooLarge { size: 2, max: 1 }
+            ))
+        ));
+        assert_eq!(world_tree.status.get(), ServiceStatus::Unhealthy);
+
+        // A bridged chain reporting the rejected root fails the bridged task rather than panicking
+        let (bridged_root_tx, bridged_root_rx) = tokio::sync::mpsc::channel(1);
+        let handle = world_tree.handle_bridged_updates(bridged_root_rx);
+        bridged_root_tx.send((10, new_root.hash)).await?;
+
+        assert!(matches!(
+            handle.await?,
+            Err(WorldTreeError::IdentityTreeError(
+                IdentityTreeError::RootNotFound { root }
+            )) if root == new_root.hash
+        ));
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_canonical_block_applied() -> eyre::Result<()> {
+        let cache_file = std::env::temp_dir()
+            .join(format!("world-tree-block-applied-{}", std::process::id()));
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?;
+        let queue_progress = &world_tree.canonical_tree_manager.queue_progress;
+
+        // Two updates sent for logs up to block 100, one applied
+        queue_progress.sent.store(2, Ordering::SeqCst);
+        queue_progress
+            .sent_through_block
+            .store(100, Ordering::SeqCst);
+        world_tree.canonical_batches.store(1, Ordering::SeqCst);
+        assert!(!world_tree.canonical_block_applied(50));
+
+        world_tree.canonical_batches.store(2, Ordering::SeqCst);
+        assert!(world_tree.canonical_block_applied(50));
+        assert!(world_tree.canonical_block_applied(100));
+        // Logs past block 100 may still hold updates
+        assert!(!world_tree.canonical_block_applied(101));
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_canonical_block() -> eyre::Result<()> {
+        let cache_file = std::env::temp_dir()
+            .join(format!("world-tree-wait-block-{}", std::process::id()));
+        let world_tree = Arc::new(
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?,
+        );
+        let timeout = Duration::from_secs(5);
+        world_tree
+            .canonical_tree_manager
+            .block_scanner
+            .head_block
+            .store(200, Ordering::SeqCst);
+        world_tree
+            .canonical_tree_manager
+            .queue_progress
+            .sent_through_block
+            .store(100, Ordering::SeqCst);
+
+        // A block past the observed head is not waited for
+        let start = Instant::now();
+        assert!(!world_tree.wait_for_canonical_block(201, timeout).await);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // A block not synced in time is waited for until the timeout
+        let start = Instant::now();
+        assert!(!world_tree.wait_for_canonical_block(150, timeout).await);
+        assert!(start.elapsed() >= timeout);
+
+        // A block synced while waiting ends the wait
+        let sync = tokio::spawn({
+            let world_tree = world_tree.clone();
+            async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                world_tree
+                    .canonical_tree_manager
+                    .queue_progress
+                    .sent_through_block
+                    .store(150, Ordering::SeqCst);
+            }
+        });
+        assert!(world_tree.wait_for_canonical_block(150, timeout).await);
+        sync.await?;
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+}
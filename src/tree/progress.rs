@@ -0,0 +1,92 @@
+#[cfg(feature = "cli")]
+use std::io::IsTerminal;
+
+#[cfg(feature = "cli")]
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Reports the progress of the initial sync. With the `cli` feature and stderr attached to a terminal, progress is drawn
+/// as a progress bar, otherwise it is logged whenever another percent of the blocks is scanned.
+pub struct SyncProgress {
+    /// First block scanned by the sync
+    first_block: u64,
+    /// Last percentage logged
+    logged_percent: Option<u64>,
+    #[cfg(feature = "cli")]
+    bar: Option<ProgressBar>,
+}
+
+impl SyncProgress {
+    pub fn new(first_block: u64) -> Self {
+        #[cfg(feature = "cli")]
+        let bar = std::io::stderr().is_terminal().then(|| {
+            let bar = ProgressBar::new(0);
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "Syncing: [{bar:40}] {percent}% {msg}",
+                )
+                .expect("Invalid progress bar template")
+                .progress_chars("=> "),
+            );
+            bar
+        });
+
+        Self {
+            first_block,
+            logged_percent: None,
+            #[cfg(feature = "cli")]
+            bar,
+        }
+    }
+
+    /// Records that every block up to `block` out of `head_block` was scanned
+    pub fn update(&mut self, block: u64, head_block: u64) {
+        let total = head_block.saturating_sub(self.first_block).max(1);
+        let scanned = block.saturating_sub(self.first_block).min(total);
+
+        #[cfg(feature = "cli")]
+        if let Some(bar) = &self.bar {
+            bar.set_length(total);
+            bar.set_position(scanned);
+            bar.set_message(format!("block {block}/{head_block}"));
+            return;
+        }
+
+        let percent = scanned * 100 / total;
+        if self.logged_percent.map_or(true, |logged| percent > logged) {
+            self.logged_percent = Some(percent);
+            tracing::info!(block, head_block, percent, "Syncing");
+        }
+    }
+
+    pub fn finish(self) {
+        #[cfg(feature = "cli")]
+        if let Some(bar) = self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+// The progress bar replaces the logs when tests run in a terminal with the `cli` feature
+#[cfg(all(test, not(feature = "cli")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logs_each_percent_once() {
+        let mut progress = SyncProgress::new(1000);
+
+        progress.update(1001, 2000);
+        assert_eq!(progress.logged_percent, Some(0));
+
+        progress.update(1015, 2000);
+        assert_eq!(progress.logged_percent, Some(1));
+
+        progress.update(1019, 2000);
+        assert_eq!(progress.logged_percent, Some(1));
+
+        progress.update(2000, 2000);
+        assert_eq!(progress.logged_percent, Some(100));
+
+        progress.finish();
+    }
+}
@@ -0,0 +1,401 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ethers::providers::Middleware;
+use serde::{Deserialize, Serialize};
+
+use super::cadence::unix_timestamp;
+use super::health::{CachedCheck, HEALTH_CHECK_TIMEOUT};
+use super::WorldTree;
+
+/// Default maximum number of blocks the tree may lag behind the head of any monitored chain while ready
+pub const DEFAULT_MAX_BLOCK_LAG: u64 = 20;
+/// Default maximum time since the last insertion batch was applied before the `last_update` check fails
+pub const DEFAULT_MAX_UPDATE_AGE: Duration = Duration::from_secs(60 * 60);
+/// Time the results of the `provider` and `persistence` checks are reused for, so that frequent probes don't each
+/// cost an RPC request and a disk write
+pub const CACHED_CHECK_TTL: Duration = Duration::from_secs(5);
+
+/// Number of persistence probe files written by this process, used to give each probe a unique name
+static PERSISTENCE_PROBES: AtomicUsize = AtomicUsize::new(0);
+
+/// Precondition for the service to be ready, registered in a [`ReadinessRegistry`]
+#[async_trait]
+pub trait ReadinessCheck<M: Middleware + 'static>:
+    Send + Sync + 'static
+{
+    /// Returns a short message describing the state checked, as `Err` if the check fails
+    async fn check(&self, world_tree: &WorldTree<M>) -> Result<String, String>;
+}
+
+/// Whether a failing check makes the service unready
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// A failure makes the service unready
+    Hard,
+    /// A failure is reported as a warning without affecting readiness
+    Soft,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    /// A soft check failed
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckReport {
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadinessReport {
+    /// True if no hard check failed
+    pub ready: bool,
+    pub checks: BTreeMap<String, CheckReport>,
+}
+
+impl ReadinessReport {
+    pub fn new(
+        results: impl IntoIterator<
+            Item = (String, Severity, Result<String, String>),
+        >,
+    ) -> Self {
+        let checks = results
+            .into_iter()
+            .map(|(name, severity, result)| {
+                let report = match (result, severity) {
+                    (Ok(message), _) => CheckReport {
+                        status: CheckStatus::Pass,
+                        message,
+                    },
+                    (Err(message), Severity::Soft) => CheckReport {
+                        status: CheckStatus::Warn,
+                        message,
+                    },
+                    (Err(message), Severity::Hard) => CheckReport {
+                        status: CheckStatus::Fail,
+                        message,
+                    },
+                };
+                (name, report)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let ready = checks
+            .values()
+            .all(|check| check.status != CheckStatus::Fail);
+
+        Self { ready, checks }
+    }
+}
+
+struct RegisteredCheck<M: Middleware + 'static> {
+    name: String,
+    severity: Severity,
+    check: Arc<dyn ReadinessCheck<M>>,
+}
+
+/// Checks run by the readiness endpoint. Features can register their own checks without changing the endpoint.
+pub struct ReadinessRegistry<M: Middleware + 'static> {
+    checks: Vec<RegisteredCheck<M>>,
+}
+
+impl<M: Middleware + 'static> Default for ReadinessRegistry<M> {
+    fn default() -> Self {
+        Self { checks: vec![] }
+    }
+}
+
+impl<M: Middleware + 'static> ReadinessRegistry<M> {
+    /// Registry with the built-in checks:
+    ///
+    /// * `sync` - the initial sync completed and no task keeping the tree up to date failed
+    /// * `block_lag` - the tree is at most `max_block_lag` blocks behind the head of every monitored chain
+    /// * `last_update` - an insertion batch was applied within `max_update_age` (soft, since batches can be sparse)
+    /// * `provider` - the canonical provider responds
+    /// * `persistence` - the directory of the tree cache is writable, if the tree is backed by a cache
+    pub fn with_default_checks(
+        max_block_lag: u64,
+        max_update_age: Duration,
+    ) -> Self {
+        let mut registry = Self::default();
+        registry.register("sync", Severity::Hard, SyncCheck);
+        registry.register(
+            "block_lag",
+            Severity::Hard,
+            BlockLagCheck { max_block_lag },
+        );
+        registry.register(
+            "last_update",
+            Severity::Soft,
+            LastUpdateCheck { max_update_age },
+        );
+        registry.register(
+            "provider",
+            Severity::Hard,
+            ProviderCheck {
+                last: CachedCheck::new(CACHED_CHECK_TTL),
+            },
+        );
+        registry.register(
+            "persistence",
+            Severity::Hard,
+            PersistenceCheck {
+                last: CachedCheck::new(CACHED_CHECK_TTL),
+            },
+        );
+        registry
+    }
+
+    /// Registers `check` under `name`, replacing any check previously registered under the same name
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        severity: Severity,
+        check: impl ReadinessCheck<M>,
+    ) {
+        let name = name.into();
+        self.checks.retain(|registered| registered.name != name);
+        self.checks.push(RegisteredCheck {
+            name,
+            severity,
+            check: Arc::new(check),
+        });
+    }
+
+    /// Changes the severity of the check registered under `name`, returning false if there is no such check
+    pub fn set_severity(&mut self, name: &str, severity: Severity) -> bool {
+        match self.checks.iter_mut().find(|check| check.name == name) {
+            Some(check) => {
+                check.severity = severity;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs every check concurrently, each with its own timeout
+    pub async fn run(&self, world_tree: &WorldTree<M>) -> ReadinessReport {
+        let results = futures::future::join_all(self.checks.iter().map(
+            |registered| async {
+                let result = tokio::time::timeout(
+                    HEALTH_CHECK_TIMEOUT,
+                    registered.check.check(world_tree),
+                )
+                .await
+                .unwrap_or_else(|_| {
+                    Err(format!("Timed out after {HEALTH_CHECK_TIMEOUT:?}"))
+                });
+
+                (registered.name.clone(), registered.severity, result)
+            },
+        ))
+        .await;
+
+        ReadinessReport::new(results)
+    }
+}
+
+struct SyncCheck;
+
+#[async_trait]
+impl<M: Middleware + 'static> ReadinessCheck<M> for SyncCheck {
+    async fn check(&self, world_tree: &WorldTree<M>) -> Result<String, String> {
+        let status = world_tree.status.get();
+
+        if world_tree.status.is_serving() {
+            Ok(format!("Service is {status}"))
+        } else {
+            Err(format!("Service is {status}"))
+        }
+    }
+}
+
+struct BlockLagCheck {
+    max_block_lag: u64,
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> ReadinessCheck<M> for BlockLagCheck {
+    async fn check(&self, world_tree: &WorldTree<M>) -> Result<String, String> {
+        let (chain_id, lag) = world_tree
+            .provider_statuses()
+            .into_iter()
+            .map(|(chain_id, status)| (chain_id, status.lag()))
+            .max_by_key(|(_, lag)| *lag)
+            .unwrap_or_default();
+
+        let message = format!("Chain {chain_id} is {lag} blocks behind");
+        if lag <= self.max_block_lag {
+            Ok(message)
+        } else {
+            Err(message)
+        }
+    }
+}
+
+struct LastUpdateCheck {
+    max_update_age: Duration,
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> ReadinessCheck<M> for LastUpdateCheck {
+    async fn check(&self, world_tree: &WorldTree<M>) -> Result<String, String> {
+        let Some(age) = world_tree
+            .batch_cadence
+            .estimate(unix_timestamp())
+            .seconds_since_last_batch
+        else {
+            return Ok("No insertion batch since startup".to_owned());
+        };
+
+        let message = format!("Last insertion batch applied {age}s ago");
+        if age <= self.max_update_age.as_secs() {
+            Ok(message)
+        } else {
+            Err(message)
+        }
+    }
+}
+
+struct ProviderCheck {
+    last: CachedCheck<Result<String, String>>,
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> ReadinessCheck<M> for ProviderCheck {
+    async fn check(&self, world_tree: &WorldTree<M>) -> Result<String, String> {
+        self.last
+            .get_or_run(|| async {
+                world_tree
+                    .canonical_tree_manager
+                    .block_scanner
+                    .middleware
+                    .get_block_number()
+                    .await
+                    .map(|head| {
+                        format!("Canonical provider is at block {head}")
+                    })
+                    .map_err(|err| format!("Canonical provider failed: {err}"))
+            })
+            .await
+    }
+}
+
+struct PersistenceCheck {
+    last: CachedCheck<Result<String, String>>,
+}
+
+#[async_trait]
+impl<M: Middleware + 'static> ReadinessCheck<M> for PersistenceCheck {
+    async fn check(&self, world_tree: &WorldTree<M>) -> Result<String, String> {
+        let Some(cache_file) = &world_tree.cache_file else {
+            return Ok("Tree is not backed by a cache".to_owned());
+        };
+        let dir = match cache_file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+            _ => PathBuf::from("."),
+        };
+
+        self.last.get_or_run(|| check_writable(dir)).await
+    }
+}
+
+/// Writes and removes a probe file in `dir`, since permissions alone don't account for read-only mounts
+async fn check_writable(dir: PathBuf) -> Result<String, String> {
+    let probe = probe_path(&dir);
+    tokio::task::spawn_blocking(move || {
+        std::fs::write(&probe, b"")?;
+        std::fs::remove_file(&probe)
+    })
+    .await
+    .map_err(|err| err.to_string())?
+    .map(|()| format!("{dir:?} is writable"))
+    .map_err(|err| format!("{dir:?} is not writable: {err}"))
+}
+
+/// Path of a probe file in `dir`, unique across processes and concurrent checks so that no probe removes another's file
+fn probe_path(dir: &Path) -> PathBuf {
+    dir.join(format!(
+        ".world-tree-readiness-{}-{}",
+        std::process::id(),
+        PERSISTENCE_PROBES.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_writable() {
+        let dir = std::env::temp_dir();
+        assert_ne!(probe_path(&dir), probe_path(&dir));
+
+        let results = futures::future::join_all(
+            (0..8).map(|_| check_writable(dir.clone())),
+        )
+        .await;
+        assert!(results.iter().all(Result::is_ok), "{results:?}");
+
+        assert!(check_writable(dir.join("missing")).await.is_err());
+    }
+
+    #[test]
+    fn test_soft_checks_only_warn() -> eyre::Result<()> {
+        let report = ReadinessReport::new([
+            (
+                "sync".to_owned(),
+                Severity::Hard,
+                Ok("Service is serving".to_owned()),
+            ),
+            (
+                "last_update".to_owned(),
+                Severity::Soft,
+                Err("Last insertion batch applied 7200s ago".to_owned()),
+            ),
+        ]);
+        assert!(report.ready);
+        assert_eq!(report.checks["last_update"].status, CheckStatus::Warn);
+
+        assert_eq!(
+            serde_json::to_value(&report)?,
+            serde_json::json!({
+                "ready": true,
+                "checks": {
+                    "last_update": {
+                        "status": "warn",
+                        "message": "Last insertion batch applied 7200s ago",
+                    },
+                    "sync": { "status": "pass", "message": "Service is serving" },
+                },
+            })
+        );
+
+        let report = ReadinessReport::new([
+            (
+                "sync".to_owned(),
+                Severity::Hard,
+                Err("Service is syncing".to_owned()),
+            ),
+            (
+                "provider".to_owned(),
+                Severity::Hard,
+                Ok("Canonical provider is at block 1".to_owned()),
+            ),
+        ]);
+        assert!(!report.ready);
+        assert_eq!(report.checks["sync"].status, CheckStatus::Fail);
+
+        Ok(())
+    }
+}
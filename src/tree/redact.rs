@@ -0,0 +1,194 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use sha2::{Digest, Sha256};
+
+use super::Hash;
+
+/// Number of hex characters of the keyed hash kept when redacting an identity commitment
+pub const REDACTED_HEX_LEN: usize = 8;
+
+static REDACT_IDENTITIES: AtomicBool = AtomicBool::new(false);
+
+/// Key mixed into redacted commitments so that they cannot be matched against known commitments
+static REDACTION_KEY: OnceLock<[u8; 32]> = OnceLock::new();
+
+/// Sets whether identity commitments are redacted from all log, trace and error output of the process
+pub fn set_redact_identities(redact: bool) {
+    REDACT_IDENTITIES.store(redact, Ordering::Relaxed);
+}
+
+pub fn redact_identities() -> bool {
+    REDACT_IDENTITIES.load(Ordering::Relaxed)
+}
+
+/// Identity commitment formatted for logs and traces.
+///
+/// Formats like the raw `Hash` unless redaction is enabled, in which case only a truncated hash of the commitment keyed
+/// with a per-process random key is shown. The same commitment is formatted identically for the lifetime of the process,
+/// so its requests can still be correlated.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LoggableIdentity(pub Hash);
+
+impl LoggableIdentity {
+    fn redacted(&self) -> String {
+        let key = REDACTION_KEY.get_or_init(rand::random);
+
+        let digest = Sha256::new()
+            .chain_update(key)
+            .chain_update(self.0.to_be_bytes::<32>())
+            .finalize();
+
+        let mut redacted = hex::encode(digest);
+        redacted.truncate(REDACTED_HEX_LEN);
+        format!("redacted:{redacted}")
+    }
+}
+
+impl fmt::Display for LoggableIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if redact_identities() {
+            write!(f, "{}", self.redacted())
+        } else {
+            write!(f, "{:#x}", self.0)
+        }
+    }
+}
+
+impl fmt::Debug for LoggableIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if redact_identities() {
+            write!(f, "{}", self.redacted())
+        } else {
+            fmt::Debug::fmt(&self.0, f)
+        }
+    }
+}
+
+/// Helpers for tests depending on whether identities are redacted
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::io;
+    use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+    use tracing::subscriber::DefaultGuard;
+
+    use super::{redact_identities, set_redact_identities};
+
+    /// Held by every test changing the process wide redaction setting, so that they don't race
+    static REDACTION_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Sets whether identities are redacted while the guard is alive, restoring the previous setting when dropped, even
+    /// if the test panics
+    pub(crate) struct RedactionGuard {
+        previous: bool,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl RedactionGuard {
+        pub(crate) fn set(redact: bool) -> Self {
+            let lock = REDACTION_LOCK
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let previous = redact_identities();
+            set_redact_identities(redact);
+
+            Self {
+                previous,
+                _lock: lock,
+            }
+        }
+    }
+
+    impl Drop for RedactionGuard {
+        fn drop(&mut self) {
+            set_redact_identities(self.previous);
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub(crate) struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl LogBuffer {
+        pub(crate) fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Captures the logs of the current thread into the returned buffer until the guard is dropped
+    pub(crate) fn capture_logs() -> (LogBuffer, DefaultGuard) {
+        let buffer = LogBuffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .finish();
+
+        (buffer, tracing::subscriber::set_default(subscriber))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{capture_logs, RedactionGuard};
+    use super::*;
+
+    fn captured_logs(identity_commitment: Hash) -> String {
+        let (buffer, _guard) = capture_logs();
+
+        {
+            let span = tracing::info_span!(
+                "inclusion_proof",
+                identity_commitment = %LoggableIdentity(identity_commitment)
+            );
+            let _enter = span.enter();
+
+            tracing::info!(
+                identity_commitment = ?LoggableIdentity(identity_commitment),
+                "Inclusion proof requested"
+            );
+        }
+
+        buffer.contents()
+    }
+
+    #[test]
+    fn test_unredacted_identities() {
+        let _redaction = RedactionGuard::set(false);
+        let identity_commitment = Hash::from(0xdead_beef_u64) << 200;
+
+        let logs = captured_logs(identity_commitment);
+        assert!(logs.contains(&format!("{identity_commitment:#x}")));
+        assert!(logs.contains(&format!("{identity_commitment:?}")));
+    }
+
+    #[test]
+    fn test_redact_identities() {
+        let _redaction = RedactionGuard::set(true);
+        let identity_commitment = Hash::from(0xdead_beef_u64) << 200;
+        let full_hex = hex::encode(identity_commitment.to_be_bytes::<32>());
+
+        let logs = captured_logs(identity_commitment);
+        let redacted = LoggableIdentity(identity_commitment).to_string();
+
+        assert!(!logs.contains(&full_hex));
+        assert!(!logs.contains(&format!("{identity_commitment:#x}")));
+        assert!(!logs.contains("deadbeef"));
+        assert!(logs.contains(&redacted));
+        assert_eq!(redacted.len(), "redacted:".len() + REDACTED_HEX_LEN);
+
+        // The same commitment is always redacted to the same value
+        assert_eq!(LoggableIdentity(identity_commitment).to_string(), redacted);
+    }
+}
@@ -0,0 +1,257 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+
+use super::identity_tree::{IdentityTree, LeafUpdates};
+use super::Hash;
+
+/// Update applied to the canonical tree, recorded as a single line of a JSON lines file.
+///
+/// Unlike [`TreeUpdateSummary`](super::sink::TreeUpdateSummary), which only carries roots and counts for observers and
+/// sinks, a recorded update holds the leaf updates themselves, which are needed to rebuild the tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedUpdate {
+    /// Root of the canonical tree after the update was applied
+    pub root: Hash,
+    pub leaf_updates: LeafUpdates,
+}
+
+/// Appends every canonical update received from the chain to a JSON lines file, producing the input of [`replay`].
+///
+/// Replay rebuilds the tree from scratch, so only a recording started while syncing into an empty tree can be replayed.
+#[derive(Debug)]
+pub struct UpdateRecorder {
+    file: Mutex<File>,
+}
+
+impl UpdateRecorder {
+    /// Opens `path` for appending, creating it if it does not exist
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends `leaf_updates`, resulting in the canonical `root`, as a single line written at once so that a crash never
+    /// leaves a partial line behind
+    pub fn record(
+        &self,
+        root: Hash,
+        leaf_updates: &LeafUpdates,
+    ) -> eyre::Result<()> {
+        let update = RecordedUpdate {
+            root,
+            leaf_updates: leaf_updates.clone(),
+        };
+        let mut line = serde_json::to_vec(&update)?;
+        line.push(b'\n');
+
+        self.file.lock().unwrap().write_all(&line)?;
+
+        Ok(())
+    }
+}
+
+/// First update whose resulting root does not match the recorded root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    /// Zero based index of the update in the log
+    pub index: usize,
+    pub expected: Hash,
+    pub actual: Hash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Number of updates applied, including the divergent update if any
+    pub num_updates: usize,
+    pub final_root: Hash,
+    pub divergence: Option<Divergence>,
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Updates applied: {}", self.num_updates)?;
+        writeln!(f, "Final root: {:#x}", self.final_root)?;
+
+        match &self.divergence {
+            Some(divergence) => write!(
+                f,
+                "Diverged at update {}: expected root {:#x}, computed {:#x}",
+                divergence.index, divergence.expected, divergence.actual
+            ),
+            None => write!(f, "All roots match"),
+        }
+    }
+}
+
+/// Rebuilds a fresh tree from a log of recorded updates, checking the root after every update.
+///
+/// Updates are applied with the same code path as the live service. Replay stops at the first divergence.
+pub fn replay(
+    tree_depth: usize,
+    updates: impl BufRead,
+) -> eyre::Result<ReplayReport> {
+    let mut identity_tree = IdentityTree::new(tree_depth);
+    let mut num_updates = 0;
+
+    for (index, line) in updates.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let update: RecordedUpdate = serde_json::from_str(&line)
+            .wrap_err_with(|| {
+                format!("Invalid update on line {}", index + 1)
+            })?;
+
+        identity_tree
+            .apply_leaf_updates(update.leaf_updates)
+            .wrap_err_with(|| {
+                format!("Failed to apply update {num_updates}")
+            })?;
+
+        let actual = identity_tree.tree.root();
+        num_updates += 1;
+
+        if actual != update.root {
+            return Ok(ReplayReport {
+                num_updates,
+                final_root: actual,
+                divergence: Some(Divergence {
+                    index: num_updates - 1,
+                    expected: update.root,
+                    actual,
+                }),
+            });
+        }
+    }
+
+    Ok(ReplayReport {
+        num_updates,
+        final_root: identity_tree.tree.root(),
+        divergence: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use semaphore::cascading_merkle_tree::CascadingMerkleTree;
+    use semaphore::poseidon_tree::PoseidonHash;
+
+    use super::*;
+    use crate::tree::LeafIndex;
+
+    const TREE_DEPTH: usize = 10;
+
+    fn recorded_updates() -> Vec<RecordedUpdate> {
+        let mut tree = CascadingMerkleTree::<PoseidonHash>::new(
+            vec![],
+            TREE_DEPTH,
+            &Hash::ZERO,
+        );
+
+        let insertions = (0..4u32)
+            .map(|idx| (LeafIndex::from(idx), Hash::from(idx + 1)))
+            .collect::<HashMap<_, _>>();
+        tree.extend_from_slice(&[1, 2, 3, 4].map(Hash::from));
+        let insert = RecordedUpdate {
+            root: tree.root(),
+            leaf_updates: LeafUpdates::Insert(insertions),
+        };
+
+        tree.set_leaf(1, Hash::ZERO);
+        let delete = RecordedUpdate {
+            root: tree.root(),
+            leaf_updates: LeafUpdates::Delete(HashMap::from([(
+                LeafIndex::from(1),
+                Hash::ZERO,
+            )])),
+        };
+
+        vec![insert, delete]
+    }
+
+    fn to_json_lines(updates: &[RecordedUpdate]) -> String {
+        updates
+            .iter()
+            .map(|update| serde_json::to_string(update).unwrap() + "\n")
+            .collect()
+    }
+
+    #[test]
+    fn test_replay() -> eyre::Result<()> {
+        let updates = recorded_updates();
+
+        let report = replay(TREE_DEPTH, to_json_lines(&updates).as_bytes())?;
+
+        assert_eq!(
+            report,
+            ReplayReport {
+                num_updates: 2,
+                final_root: updates[1].root,
+                divergence: None,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_recorded_updates() -> eyre::Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("world-tree-recording-{}", std::process::id()));
+        let updates = recorded_updates();
+
+        let recorder = UpdateRecorder::open(&path)?;
+        for update in &updates {
+            recorder.record(update.root, &update.leaf_updates)?;
+        }
+        drop(recorder);
+
+        let report =
+            replay(TREE_DEPTH, std::io::BufReader::new(File::open(&path)?))?;
+        assert_eq!(report.num_updates, 2);
+        assert_eq!(report.divergence, None);
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_divergence() -> eyre::Result<()> {
+        let mut updates = recorded_updates();
+        let expected = updates[0].root;
+        updates[0].root = Hash::from(1);
+
+        let report = replay(TREE_DEPTH, to_json_lines(&updates).as_bytes())?;
+
+        assert_eq!(
+            report.divergence,
+            Some(Divergence {
+                index: 0,
+                expected: Hash::from(1),
+                actual: expected,
+            })
+        );
+        assert_eq!(report.num_updates, 1);
+
+        Ok(())
+    }
+}
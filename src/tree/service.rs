@@ -1,22 +1,84 @@
-use std::net::SocketAddr;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-use axum::extract::{Query, State};
-use axum::http::StatusCode;
-use axum::{middleware, Json};
-use axum_middleware::logging;
+use axum::extract::{ConnectInfo, FromRef, Query, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderMap, HeaderValue, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{middleware, Json, Router};
+use axum_middleware::host::{self, AllowedHosts};
+use axum_middleware::logging::{self, BodyLogging};
+use axum_middleware::request_id::{self, RequestIdConfig};
 use ethers::providers::Middleware;
+use semaphore::identity::Identity;
 use serde::{Deserialize, Serialize};
 use tokio::task::JoinHandle;
 
-use super::error::WorldTreeError;
+use super::block_scanner::ProviderStatus;
+use super::blocklist::Blocklist;
+use super::cadence::{unix_timestamp, InclusionEta};
+use super::error::{
+    ErrorCatalog, ErrorCode, WorldTreeError, ERROR_CODE_HEADER,
+};
+use super::health::{
+    check_health, CachedCheck, ComponentStatus, HealthReport, RPC_CHECK_TTL,
+};
+use super::identity_tree::{empty_subtree_hashes, ProofBundle, SmtProof};
+use super::listener::{bind_listeners, ListenerOptions};
+use super::readiness::{
+    CheckReport, CheckStatus, ReadinessCheck, ReadinessRegistry, Severity,
+    DEFAULT_MAX_BLOCK_LAG, DEFAULT_MAX_UPDATE_AGE,
+};
+use super::redact::{redact_identities, LoggableIdentity};
+use super::status::ServiceStatus;
+use super::tree_manager::BLOCK_SCANNER_SLEEP_TIME;
 use super::{ChainId, Hash, InclusionProof, WorldTree};
 
+/// Default time to wait for in-flight requests to complete once shutdown begins
+pub const DEFAULT_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+/// Default time readiness checks fail once shutdown begins before the server stops accepting new connections
+pub const DEFAULT_SHUTDOWN_DRAIN_DELAY: Duration = Duration::from_secs(5);
+/// Default time an inclusion proof request with `min_block` waits for the block to be synced
+pub const DEFAULT_READ_AFTER_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Service that keeps the World Tree synced with `WorldIDIdentityManager` and exposes an API endpoint to serve inclusion proofs for a given World ID.
 
 pub struct InclusionProofService<M: Middleware + 'static> {
     /// In-memory representation of the merkle tree containing all verified World IDs.
     pub world_tree: Arc<WorldTree<M>>,
+    /// Maximum time to wait for in-flight requests to complete after a shutdown signal is received.
+    pub shutdown_grace_period: Duration,
+    /// Time readiness checks fail after a shutdown signal is received before the server stops accepting new connections
+    pub shutdown_drain_delay: Duration,
+    /// Whether to take the client IP from the `X-Forwarded-For` header set by a reverse proxy
+    pub trust_proxy: bool,
+    /// Socket options for the server listeners
+    pub listener_options: ListenerOptions,
+    /// Header and format of the request ID assigned to every request
+    pub request_id: RequestIdConfig,
+    /// Routes served alongside the built-in endpoints on the same server
+    pub additional_router: Option<Router>,
+    /// Client IP ranges rejected with 403 before reaching any handler
+    pub blocklist: Option<Arc<Blocklist>>,
+    /// Hosts requests may be addressed to, others are rejected with 421 before reaching any handler
+    pub allowed_hosts: AllowedHosts,
+    /// Maximum time an inclusion proof request waits for the block given in `min_block` to be synced
+    pub read_after_write_timeout: Duration,
+    /// Checks reported by `/readyz`
+    pub readiness: ReadinessRegistry<M>,
+}
+
+/// Handle to a running `InclusionProofService`
+pub struct ServiceHandle<M: Middleware + 'static> {
+    /// Addresses the server is listening on
+    pub local_addrs: Vec<SocketAddr>,
+    /// Handles for the spawned tasks
+    pub handles: Vec<JoinHandle<Result<(), WorldTreeError<M>>>>,
 }
 
 impl<M> InclusionProofService<M>
@@ -24,11 +86,115 @@ where
     M: Middleware,
 {
     pub fn new(world_tree: Arc<WorldTree<M>>) -> Self {
-        Self { world_tree }
+        Self {
+            world_tree,
+            shutdown_grace_period: DEFAULT_SHUTDOWN_GRACE_PERIOD,
+            shutdown_drain_delay: DEFAULT_SHUTDOWN_DRAIN_DELAY,
+            trust_proxy: false,
+            listener_options: ListenerOptions::default(),
+            request_id: RequestIdConfig::default(),
+            additional_router: None,
+            blocklist: None,
+            allowed_hosts: AllowedHosts::Any,
+            read_after_write_timeout: DEFAULT_READ_AFTER_WRITE_TIMEOUT,
+            readiness: ReadinessRegistry::with_default_checks(
+                DEFAULT_MAX_BLOCK_LAG,
+                DEFAULT_MAX_UPDATE_AGE,
+            ),
+        }
+    }
+
+    /// Sets the maximum time to wait for in-flight requests to complete after a shutdown signal is received.
+    pub fn with_shutdown_grace_period(
+        mut self,
+        grace_period: Duration,
+    ) -> Self {
+        self.shutdown_grace_period = grace_period;
+        self
+    }
+
+    /// Sets the time readiness checks fail after a shutdown signal is received before the server stops accepting new
+    /// connections, giving load balancers time to stop routing traffic to the instance. A zero delay drains immediately.
+    pub fn with_shutdown_drain_delay(mut self, drain_delay: Duration) -> Self {
+        self.shutdown_drain_delay = drain_delay;
+        self
+    }
+
+    /// Sets whether the client IP logged for each request is taken from the `X-Forwarded-For` header.
+    /// Only enable this when the service is reachable exclusively through a trusted reverse proxy, otherwise clients can spoof their IP.
+    pub fn with_trust_proxy(mut self, trust_proxy: bool) -> Self {
+        self.trust_proxy = trust_proxy;
+        self
+    }
+
+    /// Sets the socket options used when binding the server listeners.
+    pub fn with_listener_options(mut self, options: ListenerOptions) -> Self {
+        self.listener_options = options;
+        self
+    }
+
+    /// Sets the header request IDs are read from and echoed in, and how they are generated when a request does not carry one.
+    pub fn with_request_id_config(mut self, config: RequestIdConfig) -> Self {
+        self.request_id = config;
+        self
+    }
+
+    /// Mounts the routes of `router` on the same server as the built-in endpoints, e.g. to serve `/api/myapp` from an
+    /// application embedding the service. Requests to these routes go through the same logging and request ID middleware.
+    ///
+    /// # Panics
+    ///
+    /// Serving panics if `router` has a route that overlaps with a built-in endpoint.
+    pub fn with_router(mut self, router: Router) -> Self {
+        self.additional_router = Some(router);
+        self
+    }
+
+    /// Rejects requests from client IPs in `blocklist` with 403 Forbidden. The client IP is resolved the same way as for
+    /// logging, see [`Self::with_trust_proxy`]. The blocklist is reloaded every time the process receives SIGHUP.
+    pub fn with_blocklist(mut self, blocklist: Arc<Blocklist>) -> Self {
+        self.blocklist = Some(blocklist);
+        self
+    }
+
+    /// Rejects requests whose `Host` header is not one of `allowed_hosts` with 421 Misdirected Request, to guard against
+    /// host header injection. Requests to any host are accepted by default.
+    pub fn with_allowed_hosts(mut self, allowed_hosts: AllowedHosts) -> Self {
+        self.allowed_hosts = allowed_hosts;
+        self
+    }
+
+    /// Sets the maximum time an inclusion proof request waits for the block given in `min_block` to be synced before
+    /// responding with 202 Accepted.
+    pub fn with_read_after_write_timeout(mut self, timeout: Duration) -> Self {
+        self.read_after_write_timeout = timeout;
+        self
+    }
+
+    /// Replaces the checks reported by `/readyz`, e.g. to change the thresholds of the built-in checks.
+    pub fn with_readiness_registry(
+        mut self,
+        registry: ReadinessRegistry<M>,
+    ) -> Self {
+        self.readiness = registry;
+        self
+    }
+
+    /// Adds `check` to the checks reported by `/readyz` under `name`. Failing `Severity::Hard` checks make the
+    /// service unready, failing `Severity::Soft` checks are only reported as warnings.
+    pub fn with_readiness_check(
+        mut self,
+        name: impl Into<String>,
+        severity: Severity,
+        check: impl ReadinessCheck<M>,
+    ) -> Self {
+        self.readiness.register(name, severity, check);
+        self
     }
 
     /// Spawns an axum server and exposes an API endpoint to serve inclusion proofs for requested identity commitments.
     /// This function spawns a task to sync and maintain the state of the world tree across all monitored chains.
+    /// The server shuts down gracefully once the process receives SIGINT or SIGTERM.
     ///
     /// # Arguments
     ///
@@ -36,43 +202,284 @@ where
     ///
     /// # Returns
     ///
-    /// Vector of `JoinHandle`s for the spawned tasks.
+    /// `ServiceHandle` with the bound addresses and `JoinHandle`s for the spawned tasks.
     pub async fn serve(
         self,
         addr: SocketAddr,
-    ) -> eyre::Result<Vec<JoinHandle<Result<(), WorldTreeError<M>>>>> {
+    ) -> eyre::Result<ServiceHandle<M>> {
+        self.serve_with_shutdown(addr, shutdown_signal()).await
+    }
+
+    /// Same as [`Self::serve`], but shuts the server down once `signal` resolves instead of listening for process signals.
+    ///
+    /// When shutdown begins, the readiness endpoint immediately starts failing so that load balancers drain traffic,
+    /// while the liveness endpoint keeps passing. The server keeps accepting connections for `shutdown_drain_delay`, then
+    /// in-flight requests are given `shutdown_grace_period` to complete before the server is aborted. The server task is the only returned task that completes successfully, once shutdown is done.
+    pub async fn serve_with_shutdown(
+        self,
+        addr: SocketAddr,
+        signal: impl Future<Output = ()> + Send + 'static,
+    ) -> eyre::Result<ServiceHandle<M>> {
         let mut handles = vec![];
 
+        let world_tree = self.world_tree.clone();
+        let listener_options = self.listener_options;
+        let grace_period = self.shutdown_grace_period;
+        let drain_delay = self.shutdown_drain_delay;
+
+        #[cfg(unix)]
+        if let Some(blocklist) = &self.blocklist {
+            blocklist.clone().spawn_reload_on_sighup();
+        }
+
         // Initialize a new router and spawn the server
         tracing::info!(?addr, "Initializing axum server");
+        let (router, shutting_down) = self.into_router();
 
-        let router = axum::Router::new()
-            .route("/inclusionProof", axum::routing::post(inclusion_proof))
-            .route("/computeRoot", axum::routing::post(compute_root))
-            .route("/health", axum::routing::get(health))
-            .layer(middleware::from_fn(logging::middleware))
-            .with_state(self.world_tree.clone());
+        let listeners = bind_listeners(addr, listener_options)?;
+        let local_addrs = listeners
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        tracing::info!(
+            ?local_addrs,
+            reuse_port = listener_options.reuse_port,
+            "Server listening"
+        );
+
+        let (shutdown_tx, mut shutdown_rx) = tokio::sync::watch::channel(());
 
         let server_handle = tokio::spawn(async move {
             tracing::info!("Spawning server");
-            axum::Server::bind(&addr)
-                .serve(router.into_make_service())
-                .await?;
+            let signal = async move {
+                signal.await;
+                shutdown_tx.send_replace(());
+            };
+
+            run_server(
+                listeners,
+                router,
+                signal,
+                drain_delay,
+                grace_period,
+                shutting_down,
+            )
+            .await?;
 
             Ok(())
         });
 
-        // Spawn a task to sync and maintain the state of the world tree
+        // Spawn a task to sync and maintain the state of the world tree, giving up if shutdown begins during the initial sync
         tracing::info!("Spawning world tree");
-        handles.extend(self.world_tree.spawn().await?);
+        tokio::select! {
+            world_tree_handles = world_tree.spawn() => {
+                handles.extend(world_tree_handles?);
+            }
+            _ = shutdown_rx.changed() => {
+                tracing::info!("Shutdown started before the world tree finished syncing");
+            }
+        }
 
         handles.push(server_handle);
 
-        Ok(handles)
+        Ok(ServiceHandle {
+            local_addrs,
+            handles,
+        })
+    }
+
+    /// Builds the router serving the built-in endpoints and the routes added with [`Self::with_router`] behind the host,
+    /// blocklist, request ID and logging middleware. The health and readiness probes are exempt from the host check,
+    /// since probes address the pod IP. Also returns the flag set once graceful shutdown begins.
+    fn into_router(self) -> (Router, Arc<AtomicBool>) {
+        let state = AppState {
+            world_tree: self.world_tree,
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            trust_proxy: self.trust_proxy,
+            blocklist: self.blocklist,
+            read_after_write_timeout: self.read_after_write_timeout,
+            readiness: Arc::new(self.readiness),
+            rpc_check: Arc::new(CachedCheck::new(RPC_CHECK_TTL)),
+        };
+        let shutting_down = state.shutting_down.clone();
+        // Request bodies hold identity commitments
+        let body_logging = if redact_identities() {
+            BodyLogging::Redacted
+        } else {
+            BodyLogging::Full
+        };
+
+        let probes = axum::Router::new()
+            .route("/health", axum::routing::get(health::<M>))
+            .route("/ready", axum::routing::get(ready::<M>))
+            .route("/readyz", axum::routing::get(readyz::<M>))
+            .with_state(state.clone());
+
+        let router = axum::Router::new()
+            .route("/inclusionProof", axum::routing::post(inclusion_proof::<M>))
+            .route("/computeRoot", axum::routing::post(compute_root::<M>))
+            .route("/proofBundle", axum::routing::post(proof_bundle::<M>))
+            .route("/emptyLeafProof", axum::routing::get(empty_leaf_proof::<M>))
+            .route("/syncStatus", axum::routing::get(sync_status::<M>))
+            .route("/inclusionEta", axum::routing::get(inclusion_eta::<M>))
+            .route("/zeroHashes", axum::routing::get(zero_hashes::<M>))
+            .route("/errors", axum::routing::get(errors))
+            .with_state(state.clone())
+            .merge(self.additional_router.unwrap_or_default())
+            .layer(middleware::from_fn_with_state(
+                self.allowed_hosts,
+                host::middleware,
+            ))
+            .merge(probes)
+            .layer(middleware::map_response(label_rejections))
+            .layer(middleware::from_fn_with_state(
+                body_logging,
+                logging::middleware,
+            ))
+            .layer(middleware::from_fn_with_state(
+                self.request_id.clone(),
+                request_id::middleware,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state,
+                reject_blocked_ips::<M>,
+            ));
+
+        (router, shutting_down)
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// State shared with all request handlers
+pub struct AppState<M: Middleware + 'static> {
+    pub world_tree: Arc<WorldTree<M>>,
+    /// Set once graceful shutdown begins so that readiness checks start failing
+    pub shutting_down: Arc<AtomicBool>,
+    /// Whether to take the client IP from the `X-Forwarded-For` header
+    pub trust_proxy: bool,
+    /// Client IP ranges rejected before reaching any handler
+    pub blocklist: Option<Arc<Blocklist>>,
+    /// Maximum time to wait for the block requested with `min_block` to be synced
+    pub read_after_write_timeout: Duration,
+    /// Checks reported by `/readyz`
+    pub readiness: Arc<ReadinessRegistry<M>>,
+    /// Last result of the RPC check reported by `/health`
+    pub rpc_check: Arc<CachedCheck<ComponentStatus>>,
+}
+
+impl<M: Middleware + 'static> Clone for AppState<M> {
+    fn clone(&self) -> Self {
+        Self {
+            world_tree: self.world_tree.clone(),
+            shutting_down: self.shutting_down.clone(),
+            trust_proxy: self.trust_proxy,
+            blocklist: self.blocklist.clone(),
+            read_after_write_timeout: self.read_after_write_timeout,
+            readiness: self.readiness.clone(),
+            rpc_check: self.rpc_check.clone(),
+        }
+    }
+}
+
+impl<M: Middleware + 'static> FromRef<AppState<M>> for Arc<WorldTree<M>> {
+    fn from_ref(state: &AppState<M>) -> Self {
+        state.world_tree.clone()
+    }
+}
+
+/// Serves `router` on `listeners` until `signal` resolves, then sets `shutting_down` and keeps serving for `drain_delay`
+/// before it stops accepting new connections and waits up to `grace_period` for in-flight requests to complete.
+async fn run_server(
+    listeners: Vec<TcpListener>,
+    router: Router,
+    signal: impl Future<Output = ()>,
+    drain_delay: Duration,
+    grace_period: Duration,
+    shutting_down: Arc<AtomicBool>,
+) -> hyper::Result<()> {
+    let (drain_tx, drain_rx) = tokio::sync::watch::channel(());
+
+    let servers = listeners
+        .into_iter()
+        .map(|listener| {
+            let mut drain_rx = drain_rx.clone();
+            Ok(axum::Server::from_tcp(listener)?
+                .serve(
+                    router
+                        .clone()
+                        .into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(async move {
+                    drain_rx.changed().await.ok();
+                }))
+        })
+        .collect::<hyper::Result<Vec<_>>>()?;
+
+    let server = async {
+        futures::future::try_join_all(servers).await?;
+        Ok::<_, hyper::Error>(())
+    };
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => return result,
+        _ = signal => {}
+    }
+
+    tracing::info!(
+        ?drain_delay,
+        "Shutdown signal received, failing readiness checks"
+    );
+    shutting_down.store(true, Ordering::SeqCst);
+
+    // Keep accepting connections until load balancers notice the failing readiness checks and stop routing traffic here
+    tokio::select! {
+        result = &mut server => return result,
+        _ = tokio::time::sleep(drain_delay) => {}
+    }
+
+    tracing::info!(?grace_period, "Draining requests");
+    drain_tx.send_replace(());
+
+    match tokio::time::timeout(grace_period, server).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::warn!(
+                ?grace_period,
+                "Shutdown grace period elapsed, aborting in-flight requests"
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Resolves once the process receives SIGINT or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install SIGINT handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(
+            tokio::signal::unix::SignalKind::terminate(),
+        )
+        .expect("Failed to install SIGTERM handler")
+        .recv()
+        .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct InclusionProofRequest {
     pub identity_commitment: Hash,
@@ -84,9 +491,15 @@ impl InclusionProofRequest {
             identity_commitment,
         }
     }
+
+    /// Request for the proof of the commitment of `identity`. `Field` and `Hash` are the same type, so the commitment
+    /// is serialized as the usual hex string.
+    pub fn from_identity(identity: &Identity) -> InclusionProofRequest {
+        Self::new(identity.commitment())
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct ComputeRootRequest {
     pub identity_commitments: Vec<Hash>,
@@ -106,26 +519,339 @@ pub struct ChainIdQueryParams {
     chain_id: Option<ChainId>,
 }
 
-#[tracing::instrument(level = "debug", skip(world_tree))]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct InclusionProofQueryParams {
+    chain_id: Option<ChainId>,
+    /// Canonical block the proof must reflect, e.g. the block of a `registerIdentities` transaction just submitted
+    #[serde(alias = "min_block")]
+    min_block: Option<u64>,
+    /// Highest leaf index to serve proofs for, refusing identities registered after a cutoff with 404
+    max_leaf_index: Option<u32>,
+    #[serde(default, alias = "proof_format")]
+    proof_format: ProofFormat,
+}
+
+/// Serialization of the path of an inclusion proof, the path itself is the same for every format
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofFormat {
+    /// `semaphore::poseidon_tree::Proof`, with the root
+    #[default]
+    Poseidon,
+    /// Leaf and sibling hashes with their side, see [`SmtProof`]
+    Smt,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum FormattedInclusionProof {
+    Poseidon(InclusionProof),
+    Smt(SmtProof),
+}
+
+#[tracing::instrument(
+    level = "debug",
+    skip(state, remote_addr, headers, req),
+    fields(client_ip)
+)]
 pub async fn inclusion_proof<M: Middleware + 'static>(
+    State(state): State<AppState<M>>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query_params): Query<InclusionProofQueryParams>,
+    Json(req): Json<InclusionProofRequest>,
+) -> Result<
+    (StatusCode, HeaderMap, Json<Option<FormattedInclusionProof>>),
+    WorldTreeError<M>,
+> {
+    let client_ip = client_ip(remote_addr, &headers, state.trust_proxy);
+    tracing::Span::current()
+        .record("client_ip", tracing::field::display(client_ip));
+    tracing::info!(
+        %client_ip,
+        identity_commitment = ?LoggableIdentity(req.identity_commitment),
+        chain_id = ?query_params.chain_id,
+        min_block = ?query_params.min_block,
+        proof_format = ?query_params.proof_format,
+        "Inclusion proof requested"
+    );
+
+    // Wait for the block the client expects to be reflected in the proof, e.g. the block of an insertion it just submitted
+    if let Some(min_block) = query_params.min_block {
+        if !state
+            .world_tree
+            .wait_for_canonical_block(min_block, state.read_after_write_timeout)
+            .await
+        {
+            tracing::info!(
+                min_block,
+                timeout = ?state.read_after_write_timeout,
+                "Requested block not synced in time"
+            );
+
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                RETRY_AFTER,
+                HeaderValue::from(BLOCK_SCANNER_SLEEP_TIME),
+            );
+            return Ok((StatusCode::ACCEPTED, headers, Json(None)));
+        }
+    }
+
+    let chain_id = query_params.chain_id;
+    let inclusion_proof = state
+        .world_tree
+        .inclusion_proof(
+            req.identity_commitment,
+            chain_id,
+            query_params.max_leaf_index,
+        )
+        .await?
+        .map(|proof| match query_params.proof_format {
+            ProofFormat::Poseidon => FormattedInclusionProof::Poseidon(proof),
+            ProofFormat::Smt => FormattedInclusionProof::Smt(
+                proof.to_smt(req.identity_commitment),
+            ),
+        });
+
+    Ok((StatusCode::OK, HeaderMap::new(), Json(inclusion_proof)))
+}
+
+#[tracing::instrument(level = "debug", skip(world_tree, req))]
+pub async fn proof_bundle<M: Middleware + 'static>(
     State(world_tree): State<Arc<WorldTree<M>>>,
     Query(query_params): Query<ChainIdQueryParams>,
     Json(req): Json<InclusionProofRequest>,
-) -> Result<(StatusCode, Json<Option<InclusionProof>>), WorldTreeError<M>> {
-    let chain_id = query_params.chain_id;
-    let inclusion_proof = world_tree
-        .inclusion_proof(req.identity_commitment, chain_id)
+) -> Result<(StatusCode, Json<Option<ProofBundle>>), WorldTreeError<M>> {
+    let proof_bundle = world_tree
+        .proof_bundle(req.identity_commitment, query_params.chain_id)
         .await?;
 
-    Ok((StatusCode::OK, Json(inclusion_proof)))
+    Ok((StatusCode::OK, Json(proof_bundle)))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct EmptyLeafProofQueryParams {
+    index: u32,
+    chain_id: Option<ChainId>,
+}
+
+/// Proof that the leaf at `index` is empty. This is not an inclusion proof for any identity, `leaf` is always zero.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct EmptyLeafProof {
+    pub index: u32,
+    pub leaf: Hash,
+    #[serde(flatten)]
+    pub proof: InclusionProof,
+}
+
+#[tracing::instrument(level = "debug", skip(world_tree))]
+pub async fn empty_leaf_proof<M: Middleware + 'static>(
+    State(world_tree): State<Arc<WorldTree<M>>>,
+    Query(query_params): Query<EmptyLeafProofQueryParams>,
+) -> Result<(StatusCode, Json<EmptyLeafProof>), WorldTreeError<M>> {
+    let proof = world_tree
+        .empty_leaf_proof(query_params.index, query_params.chain_id)
+        .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(EmptyLeafProof {
+            index: query_params.index,
+            leaf: Hash::ZERO,
+            proof,
+        }),
+    ))
+}
+
+/// Returns the IP of the client that sent the request.
+///
+/// When `trust_proxy` is set, the right-most address in `X-Forwarded-For` is used, i.e. the peer address appended by the
+/// trusted proxy. Entries to its left are sent by the client and can't be trusted. Falls back to the peer address if the
+/// header is missing or malformed.
+fn client_ip(
+    remote_addr: SocketAddr,
+    headers: &HeaderMap,
+    trust_proxy: bool,
+) -> IpAddr {
+    if trust_proxy {
+        let forwarded_ip = headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+
+        if let Some(ip) = forwarded_ip {
+            return ip;
+        }
+    }
+
+    remote_addr.ip()
+}
+
+/// Rejects requests from blocklisted client IPs before they reach the logging middleware or any handler
+async fn reject_blocked_ips<M: Middleware + 'static, B>(
+    State(state): State<AppState<M>>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(blocklist) = &state.blocklist {
+        let ip = client_ip(remote_addr, request.headers(), state.trust_proxy);
+
+        if blocklist.is_blocked(ip) {
+            tracing::debug!(%ip, "Rejected request from blocklisted IP");
+            return ErrorCode::Forbidden.into_response();
+        }
+    }
+
+    next.run(request).await
+}
+
+/// Adds the error code header to the rejections of the host check and of the query and body extractors, which respond
+/// before any handler runs. Body rejections answered with 413, 415 or 422 are reported as 400 like the others, as listed
+/// in the catalog.
+async fn label_rejections(mut response: Response) -> Response {
+    if response.headers().contains_key(ERROR_CODE_HEADER) {
+        return response;
+    }
+
+    let error_code = match response.status() {
+        StatusCode::MISDIRECTED_REQUEST => ErrorCode::MisdirectedRequest,
+        StatusCode::BAD_REQUEST
+        | StatusCode::PAYLOAD_TOO_LARGE
+        | StatusCode::UNSUPPORTED_MEDIA_TYPE
+        | StatusCode::UNPROCESSABLE_ENTITY => ErrorCode::InvalidRequest,
+        _ => return response,
+    };
+
+    *response.status_mut() = error_code.http_status();
+    response.headers_mut().insert(
+        ERROR_CODE_HEADER,
+        HeaderValue::from_static(error_code.code()),
+    );
+
+    response
+}
+
+/// Liveness check reporting the health of each component. Only fails if a component is unhealthy, since a degraded
+/// service keeps serving proofs for the last synced state.
+#[tracing::instrument(level = "debug", skip(state))]
+pub async fn health<M: Middleware + 'static>(
+    State(state): State<AppState<M>>,
+) -> (StatusCode, Json<HealthReport>) {
+    let report = check_health(&state.world_tree, &state.rpc_check).await;
+
+    let status_code = match report.status {
+        ComponentStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+        ComponentStatus::Ok | ComponentStatus::Degraded => StatusCode::OK,
+    };
+
+    (status_code, Json(report))
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    pub status: ServiceStatus,
+    /// Last block scanned for each monitored chain, keyed by chain ID
+    pub last_synced_blocks: BTreeMap<u64, u64>,
+    /// Chain head observed from the provider of each monitored chain, keyed by chain ID
+    pub providers: BTreeMap<u64, ProviderStatus>,
 }
 
-#[tracing::instrument(level = "debug")]
-pub async fn health() -> StatusCode {
-    StatusCode::OK
+#[tracing::instrument(level = "debug", skip(world_tree))]
+pub async fn sync_status<M: Middleware + 'static>(
+    State(world_tree): State<Arc<WorldTree<M>>>,
+) -> Json<SyncStatus> {
+    Json(SyncStatus {
+        status: world_tree.status.get(),
+        last_synced_blocks: world_tree.last_synced_blocks(),
+        providers: world_tree.provider_statuses(),
+    })
 }
 
+/// Estimates when identities submitted now will be provable, based on the cadence of recent insertion batches
 #[tracing::instrument(level = "debug", skip(world_tree))]
+pub async fn inclusion_eta<M: Middleware + 'static>(
+    State(world_tree): State<Arc<WorldTree<M>>>,
+) -> Json<InclusionEta> {
+    Json(world_tree.batch_cadence.estimate(unix_timestamp()))
+}
+
+/// Hashes of empty subtrees of each height up to the depth of the tree, the last one being the root of an empty tree
+#[tracing::instrument(level = "debug", skip(world_tree))]
+pub async fn zero_hashes<M: Middleware + 'static>(
+    State(world_tree): State<Arc<WorldTree<M>>>,
+) -> Json<Vec<Hash>> {
+    Json(empty_subtree_hashes(world_tree.tree_depth))
+}
+
+/// Catalog of every error code the API can respond with, as found in the `x-error-code` header of error responses
+pub async fn errors() -> Json<ErrorCatalog> {
+    Json(ErrorCatalog::new())
+}
+
+/// Readiness check, passes once the tree is synced and fails as soon as shutdown begins
+#[tracing::instrument(level = "debug", skip(state))]
+pub async fn ready<M: Middleware + 'static>(
+    State(state): State<AppState<M>>,
+) -> StatusCode {
+    if state.shutting_down.load(Ordering::SeqCst)
+        || !state.world_tree.status.is_serving()
+    {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ReadyzQueryParams {
+    /// Any value other than 0 includes the result of every check in the response
+    verbose: Option<u8>,
+}
+
+/// Readiness check running every registered [`ReadinessCheck`], failing if any hard check fails or shutdown has begun.
+/// With `?verbose=1`, the response lists the status and message of each check.
+#[tracing::instrument(level = "debug", skip(state))]
+pub async fn readyz<M: Middleware + 'static>(
+    State(state): State<AppState<M>>,
+    Query(query_params): Query<ReadyzQueryParams>,
+) -> Response {
+    let mut report = state.readiness.run(&state.world_tree).await;
+
+    if state.shutting_down.load(Ordering::SeqCst) {
+        report.ready = false;
+        report.checks.insert(
+            "shutdown".to_owned(),
+            CheckReport {
+                status: CheckStatus::Fail,
+                message: "Shutdown in progress".to_owned(),
+            },
+        );
+    }
+
+    let status_code = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    if query_params.verbose.unwrap_or_default() != 0 {
+        (status_code, Json(report)).into_response()
+    } else {
+        status_code.into_response()
+    }
+}
+
+#[tracing::instrument(level = "debug", skip(world_tree, req))]
 pub async fn compute_root<M: Middleware + 'static>(
     State(world_tree): State<Arc<WorldTree<M>>>,
     Query(query_params): Query<ChainIdQueryParams>,
@@ -138,3 +864,553 @@ pub async fn compute_root<M: Middleware + 'static>(
 
     Ok((StatusCode::OK, Json(updated_root)))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    /// Serves the router of `service` on a local port for the rest of the test
+    #[cfg(feature = "mock-middleware")]
+    fn spawn_service<M: Middleware + 'static>(
+        service: InclusionProofService<M>,
+    ) -> eyre::Result<SocketAddr> {
+        let (router, shutting_down) = service.into_router();
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(run_server(
+            vec![listener],
+            router,
+            std::future::pending(),
+            DEFAULT_SHUTDOWN_DRAIN_DELAY,
+            DEFAULT_SHUTDOWN_GRACE_PERIOD,
+            shutting_down,
+        ));
+
+        Ok(addr)
+    }
+
+    /// State of a service with the default settings, without any readiness check
+    #[cfg(feature = "mock-middleware")]
+    fn app_state<M: Middleware + 'static>(
+        world_tree: WorldTree<M>,
+    ) -> AppState<M> {
+        AppState {
+            world_tree: Arc::new(world_tree),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            trust_proxy: false,
+            blocklist: None,
+            read_after_write_timeout: DEFAULT_READ_AFTER_WRITE_TIMEOUT,
+            readiness: Arc::new(ReadinessRegistry::default()),
+            rpc_check: Arc::new(CachedCheck::new(RPC_CHECK_TTL)),
+        }
+    }
+
+    #[cfg(feature = "mock-middleware")]
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir()
+            .join(format!("world-tree-service-{name}-{}", std::process::id()))
+    }
+
+    #[test]
+    fn test_request_from_identity() -> eyre::Result<()> {
+        let mut secret = *b"world tree identity secret";
+        let identity = Identity::from_secret(&mut secret, None);
+
+        let request = InclusionProofRequest::from_identity(&identity);
+        assert_eq!(request.identity_commitment, identity.commitment());
+
+        // A serialized `Field` is accepted as the identity commitment
+        let body = serde_json::json!({
+            "identityCommitment": identity.commitment(),
+        });
+        assert_eq!(
+            serde_json::from_value::<InclusionProofRequest>(body)?,
+            request
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let remote_addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let remote_ip = remote_addr.ip();
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(client_ip(remote_addr, &headers, true), remote_ip);
+
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.7, 10.0.0.2"),
+        );
+        assert_eq!(client_ip(remote_addr, &headers, false), remote_ip);
+        assert_eq!(
+            client_ip(remote_addr, &headers, true),
+            "10.0.0.2".parse::<IpAddr>().unwrap()
+        );
+
+        // Addresses sent by the client are ignored in favour of the one appended by the proxy
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("1.2.3.4, 203.0.113.7"),
+        );
+        assert_eq!(
+            client_ip(remote_addr, &headers, true),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+
+        // Malformed header falls back to the peer address
+        headers.insert("x-forwarded-for", HeaderValue::from_static("unknown"));
+        assert_eq!(client_ip(remote_addr, &headers, true), remote_ip);
+    }
+
+    #[cfg(feature = "mock-middleware")]
+    #[tokio::test]
+    async fn test_blocklist_ignores_spoofed_forwarded_for() -> eyre::Result<()>
+    {
+        let cache_file = temp_path("blocklist-cache");
+        let blocklist_path = temp_path("blocklist");
+        std::fs::write(&blocklist_path, "203.0.113.7\n")?;
+
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?;
+        let addr = spawn_service(
+            InclusionProofService::new(Arc::new(world_tree))
+                .with_trust_proxy(true)
+                .with_blocklist(Arc::new(Blocklist::load(&blocklist_path)?)),
+        )?;
+
+        let client = reqwest::Client::new();
+        let status = |forwarded_for: &'static str| {
+            let request = client
+                .get(format!("http://{addr}/errors"))
+                .header("x-forwarded-for", forwarded_for);
+            async move { Ok::<_, eyre::Report>(request.send().await?.status()) }
+        };
+
+        // A blocked client sending its own header is identified by the address appended by the proxy
+        assert_eq!(
+            status("1.2.3.4, 203.0.113.7").await?,
+            reqwest::StatusCode::FORBIDDEN
+        );
+        // Addresses to the left of the one appended by the proxy are ignored
+        assert_eq!(
+            status("203.0.113.7, 198.51.100.1").await?,
+            reqwest::StatusCode::OK
+        );
+
+        std::fs::remove_file(&cache_file)?;
+        std::fs::remove_file(&blocklist_path)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-middleware")]
+    #[tokio::test]
+    async fn test_inclusion_proof_redacts_identity() -> eyre::Result<()> {
+        use std::collections::HashMap;
+
+        use crate::tree::identity_tree::LeafUpdates;
+        use crate::tree::redact::testing::{capture_logs, RedactionGuard};
+        use crate::tree::LeafIndex;
+
+        let cache_file = temp_path("redact-cache");
+        let identity_commitment = Hash::from(0xdead_beef_u64) << 200;
+
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?;
+        world_tree.identity_tree.write().await.apply_leaf_updates(
+            LeafUpdates::Insert(HashMap::from([(
+                LeafIndex::from(0),
+                identity_commitment,
+            )])),
+        )?;
+        world_tree.status.set(ServiceStatus::Serving);
+
+        // The request goes through the logging middleware, which logs request bodies, as well as the handler
+        let _redaction = RedactionGuard::set(true);
+        let (logs, _subscriber) = capture_logs();
+        let addr =
+            spawn_service(InclusionProofService::new(Arc::new(world_tree)))?;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{addr}/inclusionProof"))
+            .json(&InclusionProofRequest::new(identity_commitment))
+            .send()
+            .await?;
+
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(response
+            .json::<Option<FormattedInclusionProof>>()
+            .await?
+            .is_some());
+
+        let logs = logs.contents();
+        assert!(logs.contains("Processing request"));
+        assert!(logs.contains("Inclusion proof requested"));
+        assert!(
+            logs.contains(&LoggableIdentity(identity_commitment).to_string())
+        );
+        assert!(!logs.contains("deadbeef"));
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-middleware")]
+    #[tokio::test]
+    async fn test_health_fails_once_sync_stops() -> eyre::Result<()> {
+        let cache_file = temp_path("health-cache");
+
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| {
+                builder.block_number(100)
+            })
+            .await?;
+        let status = world_tree.status.clone();
+        let state = app_state(world_tree);
+
+        status.set(ServiceStatus::Serving);
+        let (status_code, Json(report)) = health(State(state.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(report.components["sync"], ComponentStatus::Ok);
+
+        // A sync task exited, so the liveness probe must fail for the pod to be restarted
+        status.set(ServiceStatus::Unhealthy);
+        let (status_code, Json(report)) = health(State(state)).await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report.components["sync"], ComponentStatus::Unhealthy);
+        assert_eq!(report.status, ComponentStatus::Unhealthy);
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-middleware")]
+    #[tokio::test]
+    async fn test_ready() -> eyre::Result<()> {
+        let cache_file = temp_path("ready-cache");
+
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?;
+        let state = app_state(world_tree);
+
+        // Not ready until the tree is synced
+        assert_eq!(
+            ready(State(state.clone())).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        state.world_tree.status.set(ServiceStatus::Serving);
+        assert_eq!(ready(State(state.clone())).await, StatusCode::OK);
+
+        // Fails as soon as shutdown begins, even though the tree is still serving
+        state.shutting_down.store(true, Ordering::SeqCst);
+        assert_eq!(
+            ready(State(state.clone())).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert!(state.world_tree.status.is_serving());
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-middleware")]
+    #[tokio::test]
+    async fn test_inclusion_proof_min_block_not_synced() -> eyre::Result<()> {
+        let cache_file = temp_path("min-block-cache");
+
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?;
+        world_tree.status.set(ServiceStatus::Serving);
+        world_tree
+            .canonical_tree_manager
+            .block_scanner
+            .head_block
+            .store(100, Ordering::SeqCst);
+
+        let query_params = InclusionProofQueryParams {
+            chain_id: None,
+            min_block: Some(101),
+            max_leaf_index: None,
+            proof_format: ProofFormat::Poseidon,
+        };
+
+        // The block is past the observed head, so the request is not held for the read-after-write timeout
+        let start = Instant::now();
+        let (status, headers, Json(proof)) = inclusion_proof(
+            State(app_state(world_tree)),
+            ConnectInfo("10.0.0.1:4000".parse()?),
+            HeaderMap::new(),
+            Query(query_params),
+            Json(InclusionProofRequest::new(Hash::from(1))),
+        )
+        .await?;
+
+        assert!(start.elapsed() < DEFAULT_READ_AFTER_WRITE_TIMEOUT);
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(
+            headers.get(RETRY_AFTER),
+            Some(&HeaderValue::from(BLOCK_SCANNER_SLEEP_TIME))
+        );
+        assert!(proof.is_none());
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-middleware")]
+    #[tokio::test]
+    async fn test_probes_exempt_from_host_check() -> eyre::Result<()> {
+        let cache_file = temp_path("host-cache");
+
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?;
+        let addr = spawn_service(
+            InclusionProofService::new(Arc::new(world_tree))
+                .with_allowed_hosts(AllowedHosts::new(["world-tree.example"])),
+        )?;
+
+        let client = reqwest::Client::new();
+        let status = |path: &'static str, host: &'static str| {
+            let request = client
+                .get(format!("http://{addr}{path}"))
+                .header("host", host);
+            async move { Ok::<_, eyre::Report>(request.send().await?.status()) }
+        };
+
+        assert_eq!(
+            status("/errors", "world-tree.example").await?,
+            reqwest::StatusCode::OK
+        );
+        assert_eq!(
+            status("/errors", "10.0.0.1").await?,
+            reqwest::StatusCode::MISDIRECTED_REQUEST
+        );
+
+        // Probes addressed to the pod IP reach the handler, which reports that the tree is still syncing
+        assert_eq!(
+            status("/ready", "10.0.0.1").await?,
+            reqwest::StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        std::fs::remove_file(&cache_file)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "mock-middleware")]
+    #[tokio::test]
+    async fn test_rejections_carry_error_code() -> eyre::Result<()> {
+        let cache_file = temp_path("rejections-cache");
+        let blocklist_path = temp_path("rejections-blocklist");
+        std::fs::write(&blocklist_path, "203.0.113.7\n")?;
+
+        let world_tree =
+            crate::testing::mock_world_tree(10, &cache_file, |builder| builder)
+                .await?;
+        let addr = spawn_service(
+            InclusionProofService::new(Arc::new(world_tree))
+                .with_trust_proxy(true)
+                .with_blocklist(Arc::new(Blocklist::load(&blocklist_path)?))
+                .with_allowed_hosts(AllowedHosts::new(["world-tree.example"])),
+        )?;
+
+        let client = reqwest::Client::new();
+        let inclusion_proof = || {
+            client
+                .post(format!("http://{addr}/inclusionProof"))
+                .header("host", "world-tree.example")
+        };
+        let error_code = |response: reqwest::Response| {
+            (
+                response.status(),
+                response
+                    .headers()
+                    .get(ERROR_CODE_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_owned),
+            )
+        };
+
+        let response = inclusion_proof()
+            .header("x-forwarded-for", "203.0.113.7")
+            .send()
+            .await?;
+        assert_eq!(
+            error_code(response),
+            (reqwest::StatusCode::FORBIDDEN, Some("forbidden".to_owned()))
+        );
+
+        let response = client
+            .post(format!("http://{addr}/inclusionProof"))
+            .header("host", "10.0.0.1")
+            .send()
+            .await?;
+        assert_eq!(
+            error_code(response),
+            (
+                reqwest::StatusCode::MISDIRECTED_REQUEST,
+                Some("misdirected_request".to_owned())
+            )
+        );
+
+        // Malformed JSON and a missing content type are both reported as 400
+        let invalid_request = (
+            reqwest::StatusCode::BAD_REQUEST,
+            Some("invalid_request".to_owned()),
+        );
+        let response = inclusion_proof()
+            .header("content-type", "application/json")
+            .body("{")
+            .send()
+            .await?;
+        assert_eq!(error_code(response), invalid_request);
+
+        let response = inclusion_proof().body("{}").send().await?;
+        assert_eq!(error_code(response), invalid_request);
+
+        std::fs::remove_file(&cache_file)?;
+        std::fs::remove_file(&blocklist_path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_aborts_after_grace_period(
+    ) -> eyre::Result<()> {
+        let router = Router::new().route(
+            "/slow",
+            axum::routing::get(|| async {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                StatusCode::OK
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let grace_period = Duration::from_millis(200);
+        let (signal_tx, signal_rx) = tokio::sync::oneshot::channel::<()>();
+
+        let server = tokio::spawn(run_server(
+            vec![listener],
+            router,
+            async {
+                signal_rx.await.ok();
+            },
+            Duration::ZERO,
+            grace_period,
+            shutting_down.clone(),
+        ));
+
+        // Start a request that outlives the grace period
+        let request = tokio::spawn(reqwest::get(format!("http://{addr}/slow")));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!shutting_down.load(Ordering::SeqCst));
+
+        let start = Instant::now();
+        signal_tx.send(()).ok();
+        server.await??;
+
+        assert!(shutting_down.load(Ordering::SeqCst));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        request.abort();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_waits_for_drain_delay() -> eyre::Result<()>
+    {
+        let router =
+            Router::new().route("/ok", axum::routing::get(|| async { "ok" }));
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let drain_delay = Duration::from_millis(500);
+        let (signal_tx, signal_rx) = tokio::sync::oneshot::channel::<()>();
+
+        let start = Instant::now();
+        let server = tokio::spawn(run_server(
+            vec![listener],
+            router,
+            async {
+                signal_rx.await.ok();
+            },
+            drain_delay,
+            Duration::from_secs(10),
+            shutting_down.clone(),
+        ));
+
+        signal_tx.send(()).ok();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Readiness fails right away, but new connections are still served during the drain delay
+        assert!(shutting_down.load(Ordering::SeqCst));
+        let response = reqwest::get(format!("http://{addr}/ok")).await?;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(!server.is_finished());
+
+        server.await??;
+        assert!(start.elapsed() >= drain_delay);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_completes_in_flight_requests(
+    ) -> eyre::Result<()> {
+        let router = Router::new().route(
+            "/slow",
+            axum::routing::get(|| async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                StatusCode::OK
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let (signal_tx, signal_rx) = tokio::sync::oneshot::channel::<()>();
+
+        let server = tokio::spawn(run_server(
+            vec![listener],
+            router,
+            async {
+                signal_rx.await.ok();
+            },
+            Duration::ZERO,
+            Duration::from_secs(10),
+            Arc::new(AtomicBool::new(false)),
+        ));
+
+        let request = tokio::spawn(reqwest::get(format!("http://{addr}/slow")));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        signal_tx.send(()).ok();
+
+        let response = request.await??;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        server.await??;
+
+        Ok(())
+    }
+}
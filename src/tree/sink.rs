@@ -0,0 +1,274 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use super::Hash;
+
+/// Default number of update summaries buffered for a sink before new summaries are dropped
+pub const DEFAULT_SINK_QUEUE_DEPTH: usize = 1024;
+/// Maximum time each observer may take to handle an update before the tree stops waiting for it
+pub const OBSERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Summary of an update applied to the canonical tree
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeUpdateSummary {
+    /// Root of the canonical tree before the update was applied
+    pub previous_root: Hash,
+    /// Root of the canonical tree after the update was applied
+    pub root: Hash,
+    /// Number of leaves in the canonical tree after the update was applied
+    pub num_leaves: usize,
+    /// Unix timestamp in seconds at which the update was applied
+    pub timestamp: u64,
+}
+
+impl TreeUpdateSummary {
+    pub fn new(previous_root: Hash, root: Hash, num_leaves: usize) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            previous_root,
+            root,
+            num_leaves,
+            timestamp,
+        }
+    }
+}
+
+/// Destination for summaries of the updates applied to the canonical tree, e.g. a message queue
+pub trait UpdateSink: Send + Sync + 'static {
+    fn publish(
+        &self,
+        update: &TreeUpdateSummary,
+    ) -> impl Future<Output = eyre::Result<()>> + Send;
+}
+
+/// Custom logic run after every update applied to the canonical tree, e.g. indexing updates into a database or
+/// invalidating caches. Observers are registered with [`WorldTree::with_update_observer`](super::WorldTree::with_update_observer)
+/// and awaited in order outside of the tree lock, each for at most [`OBSERVER_TIMEOUT`], so slow work should be queued
+/// rather than done inline.
+#[async_trait]
+pub trait UpdateObserver: Send + Sync + 'static {
+    async fn on_update(&self, summary: &TreeUpdateSummary);
+}
+
+/// Calls every observer in order with `summary`. Each observer runs in its own task so that a panicking observer is
+/// logged and counted without affecting the tree updates or the other observers, and an observer still running after
+/// `timeout` is left to complete in the background.
+pub async fn notify_observers(
+    observers: &[Arc<dyn UpdateObserver>],
+    summary: &TreeUpdateSummary,
+    timeout: Duration,
+) {
+    for observer in observers {
+        let observer = observer.clone();
+        let update = summary.clone();
+        let handle =
+            tokio::spawn(async move { observer.on_update(&update).await });
+
+        match tokio::time::timeout(timeout, handle).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                tracing::error!(%err, root = ?summary.root, "Tree update observer panicked");
+                metrics::increment_counter!(
+                    "world_tree_update_observer_panics"
+                );
+            }
+            Err(_) => {
+                tracing::warn!(?timeout, root = ?summary.root, "Tree update observer timed out");
+                metrics::increment_counter!(
+                    "world_tree_update_observer_timeouts"
+                );
+            }
+        }
+    }
+}
+
+/// Queues update summaries for a sink without blocking tree updates
+#[derive(Debug, Clone)]
+pub struct UpdatePublisher {
+    tx: mpsc::Sender<TreeUpdateSummary>,
+}
+
+impl UpdatePublisher {
+    /// Queues `update` for publishing, returning false if it was dropped because the queue is full
+    pub fn publish(&self, update: TreeUpdateSummary) -> bool {
+        match self.tx.try_send(update) {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::warn!(%err, "Dropping tree update summary");
+                metrics::increment_counter!("world_tree_sink_dropped_updates");
+                false
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl UpdateObserver for UpdatePublisher {
+    async fn on_update(&self, summary: &TreeUpdateSummary) {
+        self.publish(summary.clone());
+    }
+}
+
+/// Spawns a task publishing queued update summaries to `sink`, returning the publisher used to queue them.
+/// The task exits once all publishers are dropped.
+pub fn spawn_sink<S: UpdateSink>(
+    sink: S,
+    queue_depth: usize,
+) -> (UpdatePublisher, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::channel::<TreeUpdateSummary>(queue_depth);
+
+    let handle = tokio::spawn(async move {
+        while let Some(update) = rx.recv().await {
+            if let Err(err) = sink.publish(&update).await {
+                tracing::error!(?err, root = ?update.root, "Failed to publish tree update summary");
+                metrics::increment_counter!("world_tree_sink_failed_updates");
+            }
+        }
+    });
+
+    (UpdatePublisher { tx }, handle)
+}
+
+/// Publishes update summaries as JSON messages to a NATS subject
+#[cfg(feature = "nats")]
+pub struct NatsSink {
+    client: async_nats::Client,
+    subject: String,
+}
+
+#[cfg(feature = "nats")]
+impl NatsSink {
+    pub async fn connect(url: &str, subject: String) -> eyre::Result<Self> {
+        let client = async_nats::connect(url).await?;
+
+        Ok(Self { client, subject })
+    }
+}
+
+#[cfg(feature = "nats")]
+impl UpdateSink for NatsSink {
+    async fn publish(&self, update: &TreeUpdateSummary) -> eyre::Result<()> {
+        let payload = serde_json::to_vec(update)?;
+        self.client
+            .publish(self.subject.clone(), payload.into())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default, Clone)]
+    struct InMemorySink {
+        updates: Arc<Mutex<Vec<TreeUpdateSummary>>>,
+    }
+
+    impl UpdateSink for InMemorySink {
+        async fn publish(
+            &self,
+            update: &TreeUpdateSummary,
+        ) -> eyre::Result<()> {
+            self.updates.lock().unwrap().push(update.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawn_sink() -> eyre::Result<()> {
+        let sink = InMemorySink::default();
+        let (publisher, handle) =
+            spawn_sink(sink.clone(), DEFAULT_SINK_QUEUE_DEPTH);
+
+        let updates = vec![
+            TreeUpdateSummary::new(Hash::ZERO, Hash::from(1), 1),
+            TreeUpdateSummary::new(Hash::from(1), Hash::from(2), 2),
+        ];
+        for update in updates.iter() {
+            assert!(publisher.publish(update.clone()));
+        }
+
+        // The task exits once the publisher is dropped and the queue is drained
+        drop(publisher);
+        handle.await?;
+
+        assert_eq!(*sink.updates.lock().unwrap(), updates);
+
+        Ok(())
+    }
+
+    struct PanickingObserver;
+
+    #[async_trait]
+    impl UpdateObserver for PanickingObserver {
+        async fn on_update(&self, _summary: &TreeUpdateSummary) {
+            panic!("Observer failed");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_notify_observers_isolates_panics() -> eyre::Result<()> {
+        let (tx, mut rx) = mpsc::channel(2);
+        let observers: Vec<Arc<dyn UpdateObserver>> = vec![
+            Arc::new(PanickingObserver),
+            Arc::new(UpdatePublisher { tx }),
+        ];
+
+        let update = TreeUpdateSummary::new(Hash::ZERO, Hash::from(1), 1);
+        notify_observers(&observers, &update, OBSERVER_TIMEOUT).await;
+
+        // Observers after the panicking one are still notified
+        assert_eq!(rx.try_recv()?, update);
+
+        Ok(())
+    }
+
+    struct StalledObserver;
+
+    #[async_trait]
+    impl UpdateObserver for StalledObserver {
+        async fn on_update(&self, _summary: &TreeUpdateSummary) {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_notify_observers_times_out() -> eyre::Result<()> {
+        let (tx, mut rx) = mpsc::channel(2);
+        let observers: Vec<Arc<dyn UpdateObserver>> =
+            vec![Arc::new(StalledObserver), Arc::new(UpdatePublisher { tx })];
+
+        let update = TreeUpdateSummary::new(Hash::ZERO, Hash::from(1), 1);
+        notify_observers(&observers, &update, OBSERVER_TIMEOUT).await;
+
+        // Observers after the stalled one are still notified
+        assert_eq!(rx.try_recv()?, update);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_publish_drops_when_full() {
+        let (tx, _rx) = mpsc::channel(1);
+        let publisher = UpdatePublisher { tx };
+
+        let update = TreeUpdateSummary::new(Hash::ZERO, Hash::from(1), 1);
+        assert!(publisher.publish(update.clone()));
+        assert!(!publisher.publish(update));
+    }
+}
@@ -0,0 +1,186 @@
+use std::path::{Path, PathBuf};
+
+use eyre::{ContextCompat, WrapErr};
+use reqwest::header::{HeaderName, HeaderValue};
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+/// Suffix appended to the snapshot URL to locate its SHA-256 checksum
+pub const CHECKSUM_SUFFIX: &str = ".sha256";
+
+/// Location of a pre-built tree cache used to bootstrap new deployments
+#[derive(Debug, Clone)]
+pub struct SnapshotSource {
+    pub url: Url,
+    /// Header sent with both the snapshot and checksum requests, e.g. `Authorization: Bearer <token>`
+    pub auth_header: Option<(HeaderName, HeaderValue)>,
+}
+
+impl SnapshotSource {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            auth_header: None,
+        }
+    }
+
+    pub fn with_auth_header(
+        mut self,
+        auth_header: Option<(HeaderName, HeaderValue)>,
+    ) -> Self {
+        self.auth_header = auth_header;
+        self
+    }
+
+    /// URL of the checksum stored alongside the snapshot
+    pub fn checksum_url(&self) -> Url {
+        let mut url = self.url.clone();
+        url.set_path(&format!("{}{CHECKSUM_SUFFIX}", self.url.path()));
+        url
+    }
+
+    /// Downloads the snapshot to `path`, verifying it against the checksum stored alongside it.
+    ///
+    /// The snapshot is streamed to a temporary file next to `path` which is only moved into place once the checksum
+    /// matches, so a failed download never leaves a partial cache behind.
+    pub async fn download(&self, path: &Path) -> eyre::Result<()> {
+        let client = reqwest::Client::new();
+
+        let checksum_url = self.checksum_url();
+        let checksum = self
+            .get(&client, &checksum_url)
+            .await?
+            .text()
+            .await
+            .wrap_err("Failed to read snapshot checksum")?;
+        let expected = parse_checksum(&checksum)
+            .wrap_err_with(|| format!("Invalid checksum at {checksum_url}"))?;
+
+        let mut response = self.get(&client, &self.url).await?;
+
+        let download_path = download_path(path);
+        let mut file = File::create(&download_path).await?;
+        let mut hasher = Sha256::new();
+
+        let result = async {
+            while let Some(chunk) = response
+                .chunk()
+                .await
+                .wrap_err("Failed to download snapshot")?
+            {
+                hasher.update(&chunk);
+                file.write_all(&chunk).await?;
+            }
+            file.sync_all().await?;
+
+            let actual: [u8; 32] = hasher.finalize().into();
+            eyre::ensure!(
+                actual == expected,
+                "Snapshot checksum mismatch, expected {} but got {}",
+                hex::encode(expected),
+                hex::encode(actual)
+            );
+
+            tokio::fs::rename(&download_path, path).await?;
+
+            Ok(())
+        }
+        .await;
+
+        if result.is_err() {
+            tokio::fs::remove_file(&download_path).await.ok();
+        }
+
+        result
+    }
+
+    async fn get(
+        &self,
+        client: &reqwest::Client,
+        url: &Url,
+    ) -> eyre::Result<reqwest::Response> {
+        let mut request = client.get(url.clone());
+        if let Some((name, value)) = &self.auth_header {
+            request = request.header(name, value);
+        }
+
+        let response = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .wrap_err_with(|| format!("Failed to fetch {url}"))?;
+
+        Ok(response)
+    }
+}
+
+/// Parses a header of the form `Name: value`
+pub fn parse_header(header: &str) -> eyre::Result<(HeaderName, HeaderValue)> {
+    let (name, value) = header
+        .split_once(':')
+        .context("Expected a header of the form `Name: value`")?;
+
+    Ok((name.trim().parse()?, value.trim().parse()?))
+}
+
+/// Parses the output of `sha256sum`, i.e. a hex digest optionally followed by the file name
+fn parse_checksum(checksum: &str) -> eyre::Result<[u8; 32]> {
+    let digest = checksum
+        .split_whitespace()
+        .next()
+        .context("Empty checksum")?;
+
+    let mut bytes = [0; 32];
+    hex::decode_to_slice(digest, &mut bytes)?;
+
+    Ok(bytes)
+}
+
+fn download_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".download");
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_url() -> eyre::Result<()> {
+        let source =
+            SnapshotSource::new("https://example.com/trees/cache?v=1".parse()?);
+
+        assert_eq!(
+            source.checksum_url().as_str(),
+            "https://example.com/trees/cache.sha256?v=1"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_checksum() -> eyre::Result<()> {
+        let digest = Sha256::digest(b"snapshot");
+        let checksum = format!("{}  tree-cache\n", hex::encode(digest));
+
+        assert_eq!(parse_checksum(&checksum)?, <[u8; 32]>::from(digest));
+        assert!(parse_checksum("").is_err());
+        assert!(parse_checksum("abcd").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_header() -> eyre::Result<()> {
+        let (name, value) = parse_header("Authorization: Bearer token")?;
+
+        assert_eq!(name, "authorization");
+        assert_eq!(value, "Bearer token");
+        assert!(parse_header("Authorization").is_err());
+
+        Ok(())
+    }
+}
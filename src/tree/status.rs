@@ -0,0 +1,151 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Estimated time until a syncing service starts serving proofs, surfaced to clients via `Retry-After`
+pub const SYNCING_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Lifecycle state of the service, determining whether inclusion proofs can be served
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[repr(u8)]
+pub enum ServiceStatus {
+    /// The tree is being synced to the chain tip and does not yet reflect the onchain state
+    Syncing = 0,
+    /// The tree is synced and proofs can be served
+    Serving = 1,
+    /// A task responsible for keeping the tree in sync has failed, the tree will not be updated until the service restarts
+    Unhealthy = 2,
+}
+
+impl ServiceStatus {
+    pub const ALL: [ServiceStatus; 3] = [
+        ServiceStatus::Syncing,
+        ServiceStatus::Serving,
+        ServiceStatus::Unhealthy,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceStatus::Syncing => "syncing",
+            ServiceStatus::Serving => "serving",
+            ServiceStatus::Unhealthy => "unhealthy",
+        }
+    }
+
+    /// Estimated time until the service is able to serve proofs again, if recovery is expected without intervention
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ServiceStatus::Syncing => Some(SYNCING_RETRY_AFTER),
+            ServiceStatus::Serving | ServiceStatus::Unhealthy => None,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => ServiceStatus::Syncing,
+            1 => ServiceStatus::Serving,
+            _ => ServiceStatus::Unhealthy,
+        }
+    }
+}
+
+impl std::fmt::Display for ServiceStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Tracks the current `ServiceStatus`, logging transitions and exporting the status as a labeled gauge
+#[derive(Debug)]
+pub struct StatusTracker {
+    status: AtomicU8,
+}
+
+impl StatusTracker {
+    pub fn new(status: ServiceStatus) -> Self {
+        record_status_metric(status);
+
+        Self {
+            status: AtomicU8::new(status as u8),
+        }
+    }
+
+    pub fn get(&self) -> ServiceStatus {
+        ServiceStatus::from_u8(self.status.load(Ordering::SeqCst))
+    }
+
+    pub fn is_serving(&self) -> bool {
+        self.get() == ServiceStatus::Serving
+    }
+
+    /// Transitions to `status`, returning the previous status
+    pub fn set(&self, status: ServiceStatus) -> ServiceStatus {
+        let previous = ServiceStatus::from_u8(
+            self.status.swap(status as u8, Ordering::SeqCst),
+        );
+
+        if previous != status {
+            tracing::info!(from = %previous, to = %status, "Service status changed");
+            record_status_metric(status);
+        }
+
+        previous
+    }
+}
+
+impl Default for StatusTracker {
+    fn default() -> Self {
+        Self::new(ServiceStatus::Syncing)
+    }
+}
+
+fn record_status_metric(current: ServiceStatus) {
+    for status in ServiceStatus::ALL {
+        let value = if status == current { 1.0 } else { 0.0 };
+        metrics::gauge!("world_tree_service_status", value, "status" => status.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_transitions() {
+        let tracker = StatusTracker::default();
+        assert_eq!(tracker.get(), ServiceStatus::Syncing);
+        assert!(!tracker.is_serving());
+
+        assert_eq!(tracker.set(ServiceStatus::Serving), ServiceStatus::Syncing);
+        assert!(tracker.is_serving());
+
+        // Setting the same status is a no-op
+        assert_eq!(tracker.set(ServiceStatus::Serving), ServiceStatus::Serving);
+
+        assert_eq!(
+            tracker.set(ServiceStatus::Unhealthy),
+            ServiceStatus::Serving
+        );
+        assert!(!tracker.is_serving());
+    }
+
+    #[test]
+    fn test_status_serialization() -> eyre::Result<()> {
+        assert_eq!(
+            serde_json::to_string(&ServiceStatus::Serving)?,
+            "\"serving\""
+        );
+
+        for status in ServiceStatus::ALL {
+            assert_eq!(ServiceStatus::from_u8(status as u8), status);
+            assert_eq!(
+                serde_json::to_string(&status)?,
+                format!("\"{}\"", status.as_str())
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+//! Active block ranges fetched from a GraphQL subgraph, used to narrow the `eth_getLogs` queries of the canonical tree.
+//!
+//! The subgraph must serve an `activeBlockRanges` collection whose entities have `startBlock` and `endBlock` fields,
+//! the inclusive bounds of a range of blocks that may contain events, as numbers or decimal strings (e.g. `BigInt`).
+//! Ranges must not overlap, since pages are fetched after the start block of the last range received.
+
+use eyre::{ContextCompat, WrapErr};
+use serde::{Deserialize, Deserializer};
+use url::Url;
+
+use super::config::BlockRange;
+
+/// Number of ranges requested per query, the maximum page size of The Graph
+const PAGE_SIZE: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    data: Option<Data>,
+    #[serde(default)]
+    errors: Vec<GraphQlError>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Data {
+    active_block_ranges: Vec<ActiveBlockRange>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQlError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ActiveBlockRange {
+    #[serde(deserialize_with = "block_number")]
+    start_block: u64,
+    #[serde(deserialize_with = "block_number")]
+    end_block: u64,
+}
+
+/// Block numbers are served as `BigInt` strings by most subgraphs
+fn block_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BlockNumber {
+        Number(u64),
+        String(String),
+    }
+
+    match BlockNumber::deserialize(deserializer)? {
+        BlockNumber::Number(block) => Ok(block),
+        BlockNumber::String(block) => {
+            block.parse().map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+/// Fetches every active block range served by the subgraph at `url`, failing if any range ends before it starts
+pub async fn fetch_active_block_ranges(
+    url: &Url,
+) -> eyre::Result<Vec<BlockRange>> {
+    let client = reqwest::Client::new();
+    let mut ranges: Vec<BlockRange> = vec![];
+
+    loop {
+        let query = page_query(ranges.last().map(|range| range.start));
+        let body = serde_json::to_vec(&serde_json::json!({ "query": query }))?;
+
+        let response = client
+            .post(url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        let page = parse_page(&response)
+            .wrap_err_with(|| format!("Invalid response from {url}"))?;
+        let num_ranges = page.len();
+        ranges.extend(page);
+
+        if num_ranges < PAGE_SIZE {
+            return Ok(ranges);
+        }
+    }
+}
+
+/// Query for the page of ranges starting after `after`, or the first page if `None`. Pages are selected with a filter
+/// rather than `skip`, which The Graph rejects above 5000.
+fn page_query(after: Option<u64>) -> String {
+    let filter = match after {
+        Some(block) => format!(", where: {{ startBlock_gt: {block} }}"),
+        None => String::new(),
+    };
+
+    format!(
+        "{{ activeBlockRanges(first: {PAGE_SIZE}, orderBy: startBlock, orderDirection: asc{filter}) {{ startBlock endBlock }} }}"
+    )
+}
+
+fn parse_page(body: &[u8]) -> eyre::Result<Vec<BlockRange>> {
+    let response: Response = serde_json::from_slice(body)?;
+
+    if let Some(error) = response.errors.first() {
+        eyre::bail!("Subgraph query failed: {}", error.message);
+    }
+
+    response
+        .data
+        .context("Missing data")?
+        .active_block_ranges
+        .into_iter()
+        .map(|range| {
+            eyre::ensure!(
+                range.start_block <= range.end_block,
+                "Block range {}..={} ends before it starts",
+                range.start_block,
+                range.end_block
+            );
+
+            Ok(BlockRange {
+                start: range.start_block,
+                end: range.end_block,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_page() -> eyre::Result<()> {
+        let ranges = parse_page(
+            br#"{"data": {"activeBlockRanges": [
+                {"startBlock": "100", "endBlock": "200"},
+                {"startBlock": 300, "endBlock": 300}
+            ]}}"#,
+        )?;
+
+        assert_eq!(
+            ranges
+                .iter()
+                .map(|range| (range.start, range.end))
+                .collect::<Vec<_>>(),
+            vec![(100, 200), (300, 300)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_query() {
+        assert_eq!(
+            page_query(None),
+            "{ activeBlockRanges(first: 1000, orderBy: startBlock, orderDirection: asc) { startBlock endBlock } }"
+        );
+        assert_eq!(
+            page_query(Some(100)),
+            "{ activeBlockRanges(first: 1000, orderBy: startBlock, orderDirection: asc, where: { startBlock_gt: 100 }) { startBlock endBlock } }"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_active_block_ranges_pages() -> eyre::Result<()> {
+        // More ranges than The Graph allows skipping over
+        const NUM_RANGES: u64 = 6_500;
+
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post(
+                |axum::Json(request): axum::Json<serde_json::Value>| async move {
+                    let query = request["query"].as_str().unwrap_or_default();
+                    let after = query
+                        .split_once("startBlock_gt: ")
+                        .and_then(|(_, rest)| rest.split_once(' '))
+                        .map(|(block, _)| block.parse::<u64>().unwrap());
+
+                    let ranges = (0..NUM_RANGES)
+                        .map(|i| i * 10)
+                        .filter(|start| after.map_or(true, |after| *start > after))
+                        .take(PAGE_SIZE)
+                        .map(|start| {
+                            serde_json::json!({
+                                "startBlock": start.to_string(),
+                                "endBlock": (start + 5).to_string(),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+
+                    axum::Json(serde_json::json!({
+                        "data": { "activeBlockRanges": ranges }
+                    }))
+                },
+            ),
+        );
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = Url::parse(&format!("http://{}", listener.local_addr()?))?;
+        tokio::spawn(
+            axum::Server::from_tcp(listener)?.serve(router.into_make_service()),
+        );
+
+        let ranges = fetch_active_block_ranges(&url).await?;
+
+        assert_eq!(ranges.len(), NUM_RANGES as usize);
+        for (i, range) in ranges.iter().enumerate() {
+            assert_eq!(range.start, i as u64 * 10);
+            assert_eq!(range.end, i as u64 * 10 + 5);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_page_errors() {
+        assert!(parse_page(
+            br#"{"data": {"activeBlockRanges": [{"startBlock": "200", "endBlock": "100"}]}}"#
+        )
+        .is_err());
+
+        assert!(parse_page(
+            br#"{"data": null, "errors": [{"message": "Unknown field"}]}"#
+        )
+        .is_err());
+    }
+}
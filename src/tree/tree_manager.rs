@@ -1,5 +1,6 @@
 use std::collections::{BTreeMap, HashMap};
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -12,13 +13,13 @@ use futures::StreamExt;
 use tokio::sync::mpsc::Sender;
 use tokio::task::JoinHandle;
 
-use super::block_scanner::BlockScanner;
+use super::block_scanner::{BlockRangeFilter, BlockScanner};
 use super::error::WorldTreeError;
 use super::identity_tree::{LeafUpdates, Root};
 use super::{Hash, LeafIndex};
 use crate::abi::{
-    DeleteIdentitiesCall, RegisterIdentitiesCall, RootAddedFilter,
-    TreeChangedFilter,
+    ContractReader, DeleteIdentitiesCall, RegisterIdentitiesCall,
+    RootAddedFilter, TreeChangedFilter,
 };
 use crate::error::{ok, Log as _};
 
@@ -30,15 +31,37 @@ pub trait TreeVersion: Default {
     fn spawn<M: Middleware + 'static>(
         tx: Sender<Self::ChannelData>,
         block_scanner: Arc<BlockScanner<M>>,
+        queue_progress: Arc<QueueProgress>,
     ) -> JoinHandle<Result<(), WorldTreeError<M>>>;
 
     fn tree_changed_signature() -> H256;
 }
 
+/// Progress of the task sending the updates found in the logs to the channel, so that consumers can tell once every
+/// update up to a given block has been consumed
+#[derive(Debug, Default)]
+pub struct QueueProgress {
+    /// Number of updates sent to the channel
+    pub sent: AtomicU64,
+    /// Block up to which the updates of every log have been sent
+    pub sent_through_block: AtomicU64,
+}
+
+impl QueueProgress {
+    fn record_sent(&self) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn record_sent_through_block(&self, block: u64) {
+        self.sent_through_block.store(block, Ordering::SeqCst);
+    }
+}
+
 pub struct TreeManager<M: Middleware + 'static, T: TreeVersion> {
     pub address: H160,
     pub block_scanner: Arc<BlockScanner<M>>,
     pub chain_id: u64,
+    pub queue_progress: Arc<QueueProgress>,
     _tree_version: PhantomData<T>,
 }
 
@@ -51,6 +74,7 @@ where
         address: H160,
         window_size: u64,
         last_synced_block: u64,
+        block_range_filter: BlockRangeFilter,
         middleware: Arc<M>,
     ) -> Result<Self, WorldTreeError<M>> {
         let chain_id = middleware
@@ -71,22 +95,93 @@ where
                 filter,
             )
             .await
-            .map_err(WorldTreeError::MiddlewareError)?,
+            .map_err(WorldTreeError::MiddlewareError)?
+            .with_block_range_filter(block_range_filter),
         );
 
         Ok(Self {
             address,
             block_scanner,
             chain_id,
+            queue_progress: Arc::new(QueueProgress::default()),
             _tree_version: PhantomData,
         })
     }
 
+    /// Checks that the provider is connected to the `expected` chain, logging the detected chain ID if none is expected
+    pub fn ensure_chain_id(
+        &self,
+        expected: Option<u64>,
+    ) -> Result<(), WorldTreeError<M>> {
+        match expected {
+            Some(expected) if expected != self.chain_id => {
+                Err(WorldTreeError::ChainIdMismatch {
+                    address: self.address,
+                    expected,
+                    actual: self.chain_id,
+                })
+            }
+            Some(_) => Ok(()),
+            None => {
+                tracing::warn!(
+                    chain_id = self.chain_id,
+                    address = ?self.address,
+                    "No expected chain ID configured, make sure the provider is connected to the intended network"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks that the `creation_block` the tree is synced from exists on the connected chain, since syncing from a block
+    /// past the chain head silently yields an empty tree
+    pub async fn ensure_creation_block(
+        &self,
+        creation_block: u64,
+    ) -> Result<(), WorldTreeError<M>> {
+        let block = self
+            .block_scanner
+            .middleware
+            .get_block(creation_block)
+            .await
+            .map_err(WorldTreeError::MiddlewareError)?;
+
+        if block.is_none() {
+            return Err(WorldTreeError::BlockNotFound(creation_block));
+        }
+
+        Ok(())
+    }
+
     pub fn spawn(
         &self,
         tx: Sender<T::ChannelData>,
     ) -> JoinHandle<Result<(), WorldTreeError<M>>> {
-        T::spawn(tx, self.block_scanner.clone())
+        T::spawn(tx, self.block_scanner.clone(), self.queue_progress.clone())
+    }
+}
+
+impl<M> TreeManager<M, CanonicalTree>
+where
+    M: Middleware + 'static,
+{
+    /// Checks that the identity manager was deployed with the `expected` tree depth
+    pub async fn ensure_tree_depth(
+        &self,
+        expected: usize,
+    ) -> Result<(), WorldTreeError<M>> {
+        let actual = ContractReader::new(
+            self.address,
+            self.block_scanner.middleware.clone(),
+        )
+        .tree_depth()
+        .await?;
+
+        if actual != expected {
+            return Err(WorldTreeError::TreeDepthMismatch { expected, actual });
+        }
+
+        Ok(())
     }
 }
 
@@ -98,6 +193,7 @@ impl TreeVersion for CanonicalTree {
     fn spawn<M: Middleware + 'static>(
         tx: Sender<Self::ChannelData>,
         block_scanner: Arc<BlockScanner<M>>,
+        queue_progress: Arc<QueueProgress>,
     ) -> JoinHandle<Result<(), WorldTreeError<M>>> {
         tokio::spawn(async move {
             let chain_id = block_scanner
@@ -106,11 +202,18 @@ impl TreeVersion for CanonicalTree {
                 .await
                 .map_err(WorldTreeError::MiddlewareError)?
                 .as_u64();
+            // Everything up to the last synced block was applied by the initial sync
+            queue_progress
+                .record_sent_through_block(block_scanner.last_synced_block());
             loop {
                 async {
                     let logs = block_scanner.next().await?;
+                    record_received_events(chain_id, &logs);
 
                     if logs.is_empty() {
+                        queue_progress.record_sent_through_block(
+                            block_scanner.last_synced_block(),
+                        );
                         tokio::time::sleep(Duration::from_secs(
                             BLOCK_SCANNER_SLEEP_TIME,
                         ))
@@ -127,8 +230,18 @@ impl TreeVersion for CanonicalTree {
 
                     for update in identity_updates {
                         tracing::info!(?chain_id, new_root = ?update.0.hash, "Root updated");
-                        tx.send(update).await?;
+                        // Counted before sending so that updates blocked on a full queue are included
+                        metrics::increment_gauge!("world_tree_log_queue_depth", 1.0, "queue" => "leaf_updates");
+                        let sent = tx.send(update).await;
+                        if sent.is_err() {
+                            metrics::decrement_gauge!("world_tree_log_queue_depth", 1.0, "queue" => "leaf_updates");
+                        }
+                        sent?;
+                        queue_progress.record_sent();
                     }
+                    queue_progress.record_sent_through_block(
+                        block_scanner.last_synced_block(),
+                    );
                     ok(())
                 }
                 .await
@@ -149,6 +262,7 @@ impl TreeVersion for BridgedTree {
     fn spawn<M: Middleware + 'static>(
         tx: Sender<Self::ChannelData>,
         block_scanner: Arc<BlockScanner<M>>,
+        queue_progress: Arc<QueueProgress>,
     ) -> JoinHandle<Result<(), WorldTreeError<M>>> {
         tokio::spawn(async move {
             let chain_id = block_scanner
@@ -157,11 +271,19 @@ impl TreeVersion for BridgedTree {
                 .await
                 .map_err(WorldTreeError::MiddlewareError)?
                 .as_u64();
+            // Everything up to the last synced block was applied by the initial sync
+            queue_progress
+                .record_sent_through_block(block_scanner.last_synced_block());
 
             loop {
                 async {
                     let logs = block_scanner.next().await?;
+                    record_received_events(chain_id, &logs);
+
                     if logs.is_empty() {
+                        queue_progress.record_sent_through_block(
+                            block_scanner.last_synced_block(),
+                        );
                         tokio::time::sleep(Duration::from_secs(
                             BLOCK_SCANNER_SLEEP_TIME,
                         ))
@@ -172,13 +294,30 @@ impl TreeVersion for BridgedTree {
 
                     for log in logs {
                         // Extract the root from the RootAdded log
-                        let data =
-                            RootAddedFilter::decode_log(&RawLog::from(log))?;
+                        let data = RootAddedFilter::decode_log(&RawLog::from(
+                            log.clone(),
+                        ))
+                        .map_err(|source| {
+                            WorldTreeError::<M>::AbiDecodeError {
+                                log: Box::new(log),
+                                source,
+                            }
+                        })?;
                         let new_root = Hash::from_limbs(data.root.0);
 
                         tracing::info!(?chain_id, ?new_root, "Root updated");
-                        tx.send((chain_id, new_root)).await?;
+                        // Counted before sending so that updates blocked on a full queue are included
+                        metrics::increment_gauge!("world_tree_log_queue_depth", 1.0, "queue" => "bridged_roots");
+                        let sent = tx.send((chain_id, new_root)).await;
+                        if sent.is_err() {
+                            metrics::decrement_gauge!("world_tree_log_queue_depth", 1.0, "queue" => "bridged_roots");
+                        }
+                        sent?;
+                        queue_progress.record_sent();
                     }
+                    queue_progress.record_sent_through_block(
+                        block_scanner.last_synced_block(),
+                    );
                     ok(())
                 }
                 .await
@@ -192,6 +331,21 @@ impl TreeVersion for BridgedTree {
     }
 }
 
+/// Counts events received from the chain and tracks the highest block they were emitted in.
+/// Events are counted as processed by the tasks applying them to the tree, the difference being the processing backlog.
+fn record_received_events(chain_id: u64, logs: &[Log]) {
+    if logs.is_empty() {
+        return;
+    }
+
+    let chain_id = chain_id.to_string();
+    metrics::counter!("world_tree_events_received_total", logs.len() as u64, "chain_id" => chain_id.clone());
+
+    if let Some(block) = logs.iter().filter_map(|log| log.block_number).max() {
+        metrics::gauge!("world_tree_last_event_block", block.as_u64() as f64, "chain_id" => chain_id);
+    }
+}
+
 /// Extract identity updates from logs emitted by the `WorldIdIdentityManager`.
 pub async fn extract_identity_updates<M: Middleware + 'static>(
     logs: &[Log],
@@ -203,21 +357,30 @@ pub async fn extract_identity_updates<M: Middleware + 'static>(
 
     // Fetch the transactions for each log concurrently
     for log in logs {
-        let tx_hash = log
-            .transaction_hash
-            .ok_or(WorldTreeError::TransactionHashNotFound)?;
+        let tx_hash = log.transaction_hash.ok_or_else(|| {
+            WorldTreeError::TransactionHashNotFound {
+                log: Box::new(log.clone()),
+            }
+        })?;
 
         tracing::debug!(?tx_hash, "Getting transaction");
-        tasks.push(middleware.get_transaction(tx_hash));
+        let middleware = middleware.clone();
+        tasks.push(async move {
+            let transaction = middleware
+                .get_transaction(tx_hash)
+                .await
+                .map_err(WorldTreeError::MiddlewareError)?
+                .ok_or(WorldTreeError::TransactionNotFound { tx_hash })?;
+
+            Ok::<_, WorldTreeError<M>>(transaction)
+        });
     }
 
     let mut sorted_transactions = BTreeMap::new();
 
     // Sort the transactions by nonce. These should be in order due to the block scanner, but we sort them for redundancy in the case of out-of-order logs.
     while let Some(transaction) = tasks.next().await {
-        let transaction = transaction
-            .map_err(WorldTreeError::MiddlewareError)?
-            .ok_or(WorldTreeError::TransactionNotFound)?;
+        let transaction = transaction?;
 
         let tx_hash = transaction.hash;
         tracing::debug!(?tx_hash, "Transaction received");
@@ -230,14 +393,23 @@ pub async fn extract_identity_updates<M: Middleware + 'static>(
 
         let mut identity_updates: HashMap<LeafIndex, Hash> = HashMap::new();
 
-        let function_selector = Selector::try_from(&calldata[0..4])
-            .map_err(|_| WorldTreeError::MissingFunctionSelector)?;
+        let function_selector = calldata
+            .get(0..4)
+            .and_then(|selector| Selector::try_from(selector).ok())
+            .ok_or(WorldTreeError::MissingFunctionSelector {
+                tx_hash: transaction.hash,
+            })?;
 
         if function_selector == RegisterIdentitiesCall::selector() {
             tracing::debug!("Decoding registerIdentities calldata");
 
             let register_identities_call =
-                RegisterIdentitiesCall::decode(calldata.as_ref())?;
+                RegisterIdentitiesCall::decode(calldata.as_ref()).map_err(
+                    |source| WorldTreeError::CalldataDecodeError {
+                        tx_hash: transaction.hash,
+                        source,
+                    },
+                )?;
 
             let start_index = register_identities_call.start_index;
             let identities = register_identities_call.identity_commitments;
@@ -263,7 +435,12 @@ pub async fn extract_identity_updates<M: Middleware + 'static>(
             tracing::debug!("Decoding deleteIdentities calldata");
 
             let delete_identities_call =
-                DeleteIdentitiesCall::decode(calldata.as_ref())?;
+                DeleteIdentitiesCall::decode(calldata.as_ref()).map_err(
+                    |source| WorldTreeError::CalldataDecodeError {
+                        tx_hash: transaction.hash,
+                        source,
+                    },
+                )?;
 
             let indices = unpack_indices(
                 delete_identities_call.packed_deletion_indices.as_ref(),
@@ -0,0 +1,71 @@
+//! End to end test syncing the World Tree from a local Anvil node.
+//!
+//! Requires the `anvil` binary to be installed, run with `cargo test --features integration-tests`.
+#![cfg(feature = "integration-tests")]
+
+mod common;
+
+use std::sync::Arc;
+
+use ethers::providers::{Http, Provider};
+use ethers::utils::Anvil;
+use eyre::ContextCompat;
+use world_tree::tree::block_scanner::BlockRangeFilter;
+use world_tree::tree::identity_tree::IdentityTree;
+use world_tree::tree::status::ServiceStatus;
+use world_tree::tree::tree_manager::{CanonicalTree, TreeManager};
+use world_tree::tree::{Hash, WorldTree};
+
+use self::common::{deploy_identity_manager, TREE_DEPTH};
+
+const NUM_IDENTITIES: usize = 100;
+const BATCH_SIZE: usize = 10;
+const WINDOW_SIZE: u64 = 1000;
+
+#[tokio::test]
+async fn test_sync_from_anvil() -> eyre::Result<()> {
+    // The anvil process is killed when the instance is dropped
+    let anvil = Anvil::new().spawn();
+
+    let identities = (1..=NUM_IDENTITIES as u64)
+        .map(Hash::from)
+        .collect::<Vec<_>>();
+    let deployment =
+        deploy_identity_manager(&anvil, &identities, BATCH_SIZE).await?;
+    let expected_tree = deployment.expected_tree;
+
+    // Sync a fresh in memory tree from the chain
+    let tree_manager = TreeManager::<_, CanonicalTree>::new(
+        deployment.address,
+        WINDOW_SIZE,
+        deployment.creation_block,
+        BlockRangeFilter::default(),
+        Arc::new(Provider::<Http>::try_from(anvil.endpoint())?),
+    )
+    .await?;
+
+    let world_tree = WorldTree::from_identity_tree(
+        TREE_DEPTH,
+        IdentityTree::new(TREE_DEPTH),
+        deployment.creation_block,
+        tree_manager,
+        vec![],
+    )?;
+    let summary = world_tree.sync_to_head().await?;
+
+    assert_eq!(world_tree.status.get(), ServiceStatus::Serving);
+    assert_eq!(summary.leaves_processed, identities.len());
+    assert_eq!(world_tree.current_root().await, expected_tree.root());
+
+    for identity in identities.iter() {
+        let proof = world_tree
+            .inclusion_proof(*identity, None, None)
+            .await?
+            .context("Missing inclusion proof")?;
+
+        assert_eq!(proof.root, expected_tree.root());
+        assert!(proof.verify(*identity));
+    }
+
+    Ok(())
+}
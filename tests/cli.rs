@@ -0,0 +1,86 @@
+//! Exit codes of the `world-tree` binary for common misconfigurations
+
+use std::path::PathBuf;
+
+use assert_cmd::Command;
+
+fn world_tree() -> Command {
+    let mut cmd = Command::cargo_bin("world-tree").expect("Missing binary");
+    cmd.env_remove("PORT");
+    cmd
+}
+
+/// Writes `contents` to a config file unique to the test, removing it once the test completes
+struct ConfigFile(PathBuf);
+
+impl ConfigFile {
+    fn new(name: &str, contents: &str) -> Self {
+        let path = std::env::temp_dir()
+            .join(format!("world-tree-{name}-{}.toml", std::process::id()));
+        std::fs::write(&path, contents).expect("Could not write config");
+
+        Self(path)
+    }
+}
+
+impl Drop for ConfigFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
+}
+
+#[test]
+fn test_invalid_flag_is_config_error() {
+    world_tree().arg("--worker-threads=0").assert().code(2);
+}
+
+#[test]
+fn test_missing_config_file_is_config_error() {
+    world_tree()
+        .args(["--config", "does-not-exist.toml"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_invalid_address_is_config_error() {
+    let config = ConfigFile::new(
+        "invalid-address",
+        r#"
+        tree_depth = 30
+
+        [cache]
+        cache_file = "tree-cache"
+
+        [canonical_tree]
+        address = "0xnot-an-address"
+        provider.rpc_endpoint = "http://127.0.0.1:1"
+        "#,
+    );
+
+    world_tree()
+        .arg("--config")
+        .arg(&config.0)
+        .assert()
+        .code(2)
+        .stderr(predicates::str::starts_with("Error: "));
+}
+
+#[test]
+fn test_unreachable_provider_is_startup_error() {
+    let config = ConfigFile::new(
+        "unreachable-provider",
+        r#"
+        tree_depth = 30
+
+        [cache]
+        cache_file = "tree-cache"
+
+        [canonical_tree]
+        address = "0x0000000000000000000000000000000000000000"
+        provider.rpc_endpoint = "http://127.0.0.1:1"
+        "#,
+    );
+
+    world_tree().arg("--config").arg(&config.0).assert().code(3);
+}
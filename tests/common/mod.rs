@@ -0,0 +1,142 @@
+//! Helpers shared by the tests running against a local Anvil node
+
+use std::sync::Arc;
+
+use ethers::contract::EthEvent;
+use ethers::middleware::SignerMiddleware;
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::{Address, Bytes, TransactionRequest, U256};
+use ethers::utils::AnvilInstance;
+use eyre::ContextCompat;
+use semaphore::cascading_merkle_tree::CascadingMerkleTree;
+use semaphore::poseidon_tree::PoseidonHash;
+use world_tree::abi::{IWorldIDIdentityManager, TreeChangedFilter};
+use world_tree::tree::Hash;
+
+pub const TREE_DEPTH: usize = 20;
+
+/// Creation code for a stub of the `WorldIdIdentityManager`. Calls without arguments, e.g. `getTreeDepth`, return
+/// `TREE_DEPTH`. Any other call emits `TreeChanged(preRoot, 0, postRoot)` with the roots read from `registerIdentities`
+/// calldata. Proofs are not verified, the service only relies on the event and the calldata of the transaction.
+fn identity_manager_stub() -> Bytes {
+    #[rustfmt::skip]
+    let mut runtime = vec![
+        0x60, 0x04, // PUSH1 4, length of a selector
+        0x36, // CALLDATASIZE
+        0x11, // GT
+        0x60, 0x11, // PUSH1 0x11, offset of the JUMPDEST below
+        0x57, // JUMPI
+        0x60, TREE_DEPTH as u8, // PUSH1 tree depth
+        0x60, 0x00, // PUSH1 0
+        0x52, // MSTORE
+        0x60, 0x20, // PUSH1 32, size
+        0x60, 0x00, // PUSH1 0, offset
+        0xf3, // RETURN
+        0x5b, // JUMPDEST
+        0x61, 0x01, 0x64, // PUSH2 0x164, offset of postRoot in calldata
+        0x35, // CALLDATALOAD
+        0x60, 0x00, // PUSH1 0, kind
+        0x61, 0x01, 0x04, // PUSH2 0x104, offset of preRoot in calldata
+        0x35, // CALLDATALOAD
+        0x7f, // PUSH32 event signature
+    ];
+    runtime.extend_from_slice(TreeChangedFilter::signature().as_bytes());
+    runtime.extend_from_slice(&[
+        0x60, 0x00, // PUSH1 0, size
+        0x60, 0x00, // PUSH1 0, offset
+        0xa4, // LOG4
+        0x00, // STOP
+    ]);
+
+    // Copy the runtime code into memory and return it
+    #[rustfmt::skip]
+    let mut creation = vec![
+        0x60, runtime.len() as u8, // PUSH1 runtime length
+        0x80, // DUP1
+        0x60, 0x0b, // PUSH1 runtime offset, i.e. the length of this prefix
+        0x60, 0x00, // PUSH1 0
+        0x39, // CODECOPY
+        0x60, 0x00, // PUSH1 0
+        0xf3, // RETURN
+    ];
+    creation.extend(runtime);
+
+    creation.into()
+}
+
+fn to_u256(hash: Hash) -> U256 {
+    U256(hash.into_limbs())
+}
+
+/// Identity manager stub deployed to Anvil
+pub struct Deployment {
+    pub address: Address,
+    /// Block the stub was deployed at
+    pub creation_block: u64,
+    /// Tree expected once every registered identity is synced
+    pub expected_tree: CascadingMerkleTree<PoseidonHash>,
+}
+
+/// Deploys the identity manager stub and registers `identities` in batches of `batch_size`, tracking the expected
+/// tree locally
+pub async fn deploy_identity_manager(
+    anvil: &AnvilInstance,
+    identities: &[Hash],
+    batch_size: usize,
+) -> eyre::Result<Deployment> {
+    let provider = Provider::<Http>::try_from(anvil.endpoint())?;
+    let wallet: LocalWallet = anvil.keys()[0].clone().into();
+    let client = Arc::new(SignerMiddleware::new(
+        provider,
+        wallet.with_chain_id(anvil.chain_id()),
+    ));
+
+    let receipt = client
+        .send_transaction(
+            TransactionRequest::new().data(identity_manager_stub()),
+            None,
+        )
+        .await?
+        .await?
+        .context("Missing deployment receipt")?;
+    let address: Address = receipt
+        .contract_address
+        .context("Missing contract address")?;
+    let creation_block = receipt
+        .block_number
+        .context("Missing block number")?
+        .as_u64();
+
+    let identity_manager = IWorldIDIdentityManager::new(address, client);
+    let mut expected_tree = CascadingMerkleTree::<PoseidonHash>::new(
+        vec![],
+        TREE_DEPTH,
+        &Hash::ZERO,
+    );
+
+    for (batch_idx, batch) in identities.chunks(batch_size).enumerate() {
+        let pre_root = expected_tree.root();
+        expected_tree.extend_from_slice(batch);
+        let post_root = expected_tree.root();
+
+        identity_manager
+            .register_identities(
+                [U256::zero(); 8],
+                to_u256(pre_root),
+                (batch_idx * batch_size) as u32,
+                batch.iter().copied().map(to_u256).collect(),
+                to_u256(post_root),
+            )
+            .send()
+            .await?
+            .await?
+            .context("Missing registration receipt")?;
+    }
+
+    Ok(Deployment {
+        address,
+        creation_block,
+        expected_tree,
+    })
+}
@@ -0,0 +1,236 @@
+//! Two-process test of a new `world-tree` instance taking over the port and tree of a running one.
+//!
+//! Requires the `anvil` binary to be installed, run with `cargo test --features integration-tests --test takeover`.
+#![cfg(all(unix, feature = "integration-tests"))]
+
+mod common;
+
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ethers::utils::Anvil;
+use eyre::ContextCompat;
+use world_tree::tree::handoff::pid_path;
+use world_tree::tree::identity_tree::InclusionProof;
+use world_tree::tree::service::InclusionProofRequest;
+use world_tree::tree::Hash;
+
+use self::common::{deploy_identity_manager, TREE_DEPTH};
+
+const NUM_IDENTITIES: usize = 20;
+const BATCH_SIZE: usize = 10;
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `world-tree` process, killed once the test completes if it is still running
+struct Instance(Child);
+
+impl Instance {
+    fn spawn(
+        config: &Path,
+        handoff_path: &Path,
+        args: &[&str],
+    ) -> std::io::Result<Self> {
+        Command::new(env!("CARGO_BIN_EXE_world-tree"))
+            .arg("--config")
+            .arg(config)
+            .args(["--reuse-port", "--consistency-check-interval-blocks=0"])
+            .args(args)
+            .env("WORLD_TREE_HANDOFF_PATH", handoff_path)
+            .env_remove("PORT")
+            .stdout(Stdio::null())
+            .spawn()
+            .map(Self)
+    }
+
+    fn pid(&self) -> u32 {
+        self.0.id()
+    }
+
+    /// Waits for the process to exit, returning `None` if it is still running after `timeout`
+    async fn wait(
+        &mut self,
+        timeout: Duration,
+    ) -> std::io::Result<Option<ExitStatus>> {
+        let start = Instant::now();
+
+        loop {
+            let status = self.0.try_wait()?;
+            if status.is_some() || start.elapsed() >= timeout {
+                return Ok(status);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
+
+/// Removes the files created by the test once it completes, whether it passes or not
+struct TempFiles(Vec<PathBuf>);
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join(format!("world-tree-takeover-{name}-{}", std::process::id()))
+}
+
+/// Whether the request failed before a response was received, e.g. because the connection was reset by the instance
+/// shutting down, in which case a client retries it
+fn is_connection_error(error: &eyre::Report) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(|error| error.is_connect() || error.is_request())
+}
+
+/// Requests an inclusion proof for `identity`, failing unless one is returned
+async fn request_proof(
+    client: &reqwest::Client,
+    url: &str,
+    identity: Hash,
+) -> eyre::Result<InclusionProof> {
+    client
+        .post(url)
+        .json(&InclusionProofRequest::new(identity))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Option<InclusionProof>>()
+        .await?
+        .context("Missing inclusion proof")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_takeover() -> eyre::Result<()> {
+    // The anvil process is killed when the instance is dropped
+    let anvil = Anvil::new().spawn();
+
+    let identities = (1..=NUM_IDENTITIES as u64)
+        .map(Hash::from)
+        .collect::<Vec<_>>();
+    let deployment =
+        deploy_identity_manager(&anvil, &identities, BATCH_SIZE).await?;
+    let expected_root = deployment.expected_tree.root();
+
+    let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let config = temp_path("config.toml");
+    let cache_file = temp_path("cache");
+    let handoff_path = temp_path("handoff");
+    let _temp_files = TempFiles(vec![
+        config.clone(),
+        cache_file.clone(),
+        handoff_path.clone(),
+        pid_path(&handoff_path),
+    ]);
+
+    std::fs::write(
+        &config,
+        format!(
+            r#"
+tree_depth = {TREE_DEPTH}
+socket_address = "127.0.0.1:{port}"
+
+[cache]
+cache_file = "{}"
+
+[canonical_tree]
+address = "{:?}"
+creation_block = {}
+provider.rpc_endpoint = "{}"
+window_size = 1000
+"#,
+            cache_file.display(),
+            deployment.address,
+            deployment.creation_block,
+            anvil.endpoint(),
+        ),
+    )?;
+
+    // Fresh connections for every request, so that requests are spread over both instances while they share the port
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(0)
+        .build()?;
+    let url = format!("http://127.0.0.1:{port}/inclusionProof");
+
+    let mut old = Instance::spawn(&config, &handoff_path, &[])?;
+
+    let start = Instant::now();
+    while request_proof(&client, &url, identities[0]).await.is_err() {
+        eyre::ensure!(
+            start.elapsed() < STARTUP_TIMEOUT,
+            "The first instance did not start serving"
+        );
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    // Request proofs throughout the takeover. Connections reset while the first instance shuts down are retried, but
+    // every response must be a valid proof for the expected root.
+    let stop = Arc::new(AtomicBool::new(false));
+    let requests = Arc::new(AtomicUsize::new(0));
+    let load = tokio::spawn({
+        let client = client.clone();
+        let url = url.clone();
+        let identities = identities.clone();
+        let stop = stop.clone();
+        let requests = requests.clone();
+        async move {
+            for identity in identities.iter().cycle() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let proof = match request_proof(&client, &url, *identity).await
+                {
+                    Ok(proof) => proof,
+                    Err(error) if is_connection_error(&error) => continue,
+                    Err(error) => return Err(error),
+                };
+                eyre::ensure!(proof.root == expected_root, "Unexpected root");
+                eyre::ensure!(proof.verify(*identity), "Invalid proof");
+                requests.fetch_add(1, Ordering::SeqCst);
+            }
+
+            Ok(())
+        }
+    });
+
+    let takeover_pid = old.pid().to_string();
+    let mut new = Instance::spawn(
+        &config,
+        &handoff_path,
+        &["--takeover", &takeover_pid],
+    )?;
+
+    // The new instance stops the old one once it serves
+    let status = old
+        .wait(STARTUP_TIMEOUT)
+        .await?
+        .context("The first instance was not stopped")?;
+    assert!(status.success());
+    assert!(new.wait(Duration::ZERO).await?.is_none());
+
+    stop.store(true, Ordering::SeqCst);
+    load.await??;
+    assert!(requests.load(Ordering::SeqCst) > 0);
+
+    let proof = request_proof(&client, &url, identities[0]).await?;
+    assert_eq!(proof.root, expected_root);
+
+    Ok(())
+}
@@ -0,0 +1,21 @@
+#![cfg(feature = "testing")]
+
+use world_tree::testing::test_vectors::TestVectors;
+
+const VECTORS: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/vectors/identity_tree.json"
+);
+
+#[test]
+fn test_reproduces_checked_in_vectors() -> eyre::Result<()> {
+    let vectors: TestVectors =
+        serde_json::from_str(&std::fs::read_to_string(VECTORS)?)?;
+
+    assert!(!vectors.scenarios.is_empty());
+    for scenario in &vectors.scenarios {
+        scenario.check()?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,271 @@
+//! Wire format of every request and response type exposed by the service, checked against the golden JSON files in
+//! `tests/wire_format`. A failing test means clients would see a different format, so update the golden file only if
+//! the change is intended.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use ethers::types::H160;
+use eyre::ContextCompat;
+use semaphore::merkle_tree::Branch;
+use semaphore::poseidon_tree::Proof;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use world_tree::tree::block_scanner::ProviderStatus;
+use world_tree::tree::cadence::InclusionEta;
+use world_tree::tree::error::ErrorCatalog;
+use world_tree::tree::health::{ComponentStatus, HealthReport};
+use world_tree::tree::identity_tree::{
+    InclusionProof, ProofBundle, SmtPathNode, SmtProof,
+};
+use world_tree::tree::readiness::{CheckReport, CheckStatus, ReadinessReport};
+use world_tree::tree::service::{
+    ComputeRootRequest, EmptyLeafProof, InclusionProofRequest, SyncStatus,
+};
+use world_tree::tree::sink::TreeUpdateSummary;
+use world_tree::tree::status::ServiceStatus;
+use world_tree::tree::Hash;
+
+/// Whether a type accepts fields it does not know about. Requests reject them so that clients notice typos, responses
+/// ignore them so that clients keep working when fields are added.
+#[derive(Debug, Clone, Copy)]
+enum UnknownFields {
+    Rejected,
+    Ignored,
+}
+
+/// Checks that `value` serializes to the golden JSON and that the golden JSON deserializes back to `value`
+fn assert_wire_format<T>(
+    value: &T,
+    golden: &str,
+    unknown_fields: UnknownFields,
+) -> eyre::Result<()>
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let golden: Value = serde_json::from_str(golden)?;
+
+    assert_eq!(serde_json::to_value(value)?, golden);
+    assert_eq!(&serde_json::from_value::<T>(golden.clone())?, value);
+
+    let mut extended = golden;
+    extended
+        .as_object_mut()
+        .context("Golden file is not an object")?
+        .insert("unknownField".to_owned(), Value::Bool(true));
+    let result = serde_json::from_value::<T>(extended);
+
+    match unknown_fields {
+        UnknownFields::Rejected => assert!(result.is_err()),
+        UnknownFields::Ignored => assert_eq!(&result?, value),
+    }
+
+    Ok(())
+}
+
+fn proof() -> Proof {
+    Proof(vec![
+        Branch::Left(Hash::from(2)),
+        Branch::Right(Hash::from(3)),
+    ])
+}
+
+#[test]
+fn test_inclusion_proof_request() -> eyre::Result<()> {
+    assert_wire_format(
+        &InclusionProofRequest::new(Hash::from(1)),
+        include_str!("wire_format/inclusion_proof_request.json"),
+        UnknownFields::Rejected,
+    )
+}
+
+#[test]
+fn test_compute_root_request() -> eyre::Result<()> {
+    assert_wire_format(
+        &ComputeRootRequest::new(vec![Hash::from(1), Hash::from(2)]),
+        include_str!("wire_format/compute_root_request.json"),
+        UnknownFields::Rejected,
+    )
+}
+
+#[test]
+fn test_inclusion_proof() -> eyre::Result<()> {
+    assert_wire_format(
+        &InclusionProof::new(Hash::from(10), proof()),
+        include_str!("wire_format/inclusion_proof.json"),
+        UnknownFields::Ignored,
+    )
+}
+
+#[test]
+fn test_smt_proof() -> eyre::Result<()> {
+    let smt_proof =
+        InclusionProof::new(Hash::from(10), proof()).to_smt(Hash::from(1));
+    assert_eq!(
+        smt_proof.path,
+        vec![
+            SmtPathNode {
+                sibling: Hash::from(2),
+                is_left: false
+            },
+            SmtPathNode {
+                sibling: Hash::from(3),
+                is_left: true
+            },
+        ]
+    );
+
+    assert_wire_format::<SmtProof>(
+        &smt_proof,
+        include_str!("wire_format/smt_proof.json"),
+        UnknownFields::Ignored,
+    )
+}
+
+#[test]
+fn test_proof_bundle() -> eyre::Result<()> {
+    assert_wire_format(
+        &ProofBundle {
+            identity_commitment: Hash::from(1),
+            root: Hash::from(10),
+            leaf_index: 2,
+            siblings: vec![Hash::from(2), Hash::from(3)],
+            contract_address: H160::repeat_byte(0x11),
+            chain_id: 10,
+        },
+        include_str!("wire_format/proof_bundle.json"),
+        UnknownFields::Ignored,
+    )
+}
+
+#[test]
+fn test_empty_leaf_proof() -> eyre::Result<()> {
+    assert_wire_format(
+        &EmptyLeafProof {
+            index: 5,
+            leaf: Hash::ZERO,
+            proof: InclusionProof::new(
+                Hash::from(10),
+                Proof(vec![
+                    Branch::Right(Hash::from(2)),
+                    Branch::Left(Hash::from(3)),
+                ]),
+            ),
+        },
+        include_str!("wire_format/empty_leaf_proof.json"),
+        UnknownFields::Ignored,
+    )
+}
+
+#[test]
+fn test_health_report() -> eyre::Result<()> {
+    assert_wire_format(
+        &HealthReport::new(BTreeMap::from([
+            ("disk".to_owned(), ComponentStatus::Ok),
+            ("rpc".to_owned(), ComponentStatus::Degraded),
+            ("tree_lock".to_owned(), ComponentStatus::Ok),
+        ])),
+        include_str!("wire_format/health_report.json"),
+        UnknownFields::Ignored,
+    )
+}
+
+#[test]
+fn test_sync_status() -> eyre::Result<()> {
+    assert_wire_format(
+        &SyncStatus {
+            status: ServiceStatus::Serving,
+            last_synced_blocks: BTreeMap::from([(1, 100), (10, 200)]),
+            providers: BTreeMap::from([
+                (
+                    1,
+                    ProviderStatus {
+                        head_block: 105,
+                        last_synced_block: 100,
+                        last_response: Some(1_700_000_000),
+                        window_size: 1000,
+                    },
+                ),
+                (
+                    10,
+                    ProviderStatus {
+                        head_block: 200,
+                        last_synced_block: 200,
+                        last_response: None,
+                        window_size: 5000,
+                    },
+                ),
+            ]),
+        },
+        include_str!("wire_format/sync_status.json"),
+        UnknownFields::Ignored,
+    )
+}
+
+#[test]
+fn test_inclusion_eta() -> eyre::Result<()> {
+    assert_wire_format(
+        &InclusionEta {
+            average_batch_interval: Some(600),
+            average_batch_size: Some(250),
+            seconds_since_last_batch: Some(120),
+            estimated_seconds_until_next_batch: None,
+        },
+        include_str!("wire_format/inclusion_eta.json"),
+        UnknownFields::Ignored,
+    )
+}
+
+#[test]
+fn test_readiness_report() -> eyre::Result<()> {
+    assert_wire_format(
+        &ReadinessReport {
+            ready: true,
+            checks: BTreeMap::from([
+                (
+                    "block_lag".to_owned(),
+                    CheckReport {
+                        status: CheckStatus::Pass,
+                        message: "Chain 1 is 2 blocks behind".to_owned(),
+                    },
+                ),
+                (
+                    "last_update".to_owned(),
+                    CheckReport {
+                        status: CheckStatus::Warn,
+                        message: "Last insertion batch applied 7200s ago"
+                            .to_owned(),
+                    },
+                ),
+            ]),
+        },
+        include_str!("wire_format/readiness_report.json"),
+        UnknownFields::Ignored,
+    )
+}
+
+#[test]
+fn test_tree_update_summary() -> eyre::Result<()> {
+    assert_wire_format(
+        &TreeUpdateSummary {
+            previous_root: Hash::from(10),
+            root: Hash::from(11),
+            num_leaves: 3,
+            timestamp: 1_700_000_000,
+        },
+        include_str!("wire_format/tree_update_summary.json"),
+        UnknownFields::Ignored,
+    )
+}
+
+/// Codes must stay stable once published, so removing or renaming one fails this test while adding one only requires
+/// extending the golden file
+#[test]
+fn test_error_catalog() -> eyre::Result<()> {
+    assert_wire_format(
+        &ErrorCatalog::new(),
+        include_str!("wire_format/error_catalog.json"),
+        UnknownFields::Ignored,
+    )
+}